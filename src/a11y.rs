@@ -0,0 +1,259 @@
+/// This package checks templates for common accessibility problems, it only sees
+/// the template itself, so it warns about things that are wrong no matter what gets rendered
+use crate::diagnostics::{sorted_attributes, Diagnostic, Problem};
+use crate::templating::{visit_template, Attribute, Attributes, Node, NodeData, Template};
+
+/// `aria-*` attributes defined by WAI-ARIA 1.2
+pub const ARIA_ATTRIBUTES: &[&str] = &[
+    "activedescendant",
+    "atomic",
+    "autocomplete",
+    "braillelabel",
+    "brailleroledescription",
+    "busy",
+    "checked",
+    "colcount",
+    "colindex",
+    "colindextext",
+    "colspan",
+    "controls",
+    "current",
+    "describedby",
+    "description",
+    "details",
+    "disabled",
+    "dropeffect",
+    "errormessage",
+    "expanded",
+    "flowto",
+    "grabbed",
+    "haspopup",
+    "hidden",
+    "invalid",
+    "keyshortcuts",
+    "label",
+    "labelledby",
+    "level",
+    "live",
+    "modal",
+    "multiline",
+    "multiselectable",
+    "orientation",
+    "owns",
+    "placeholder",
+    "posinset",
+    "pressed",
+    "readonly",
+    "relevant",
+    "required",
+    "roledescription",
+    "rowcount",
+    "rowindex",
+    "rowindextext",
+    "rowspan",
+    "selected",
+    "setsize",
+    "sort",
+    "valuemax",
+    "valuemin",
+    "valuenow",
+    "valuetext",
+];
+
+/// Html elements keyboard and assistive technologies can not activate on their own,
+/// other tags, e.g. nested components, are not checked for click handlers
+const NON_INTERACTIVE_ELEMENTS: &[&str] = &[
+    "article", "aside", "b", "dd", "div", "dl", "dt", "em", "figure", "footer", "h1", "h2", "h3",
+    "h4", "h5", "h6", "header", "i", "img", "li", "main", "nav", "ol", "p", "section", "span",
+    "strong", "table", "td", "th", "tr", "ul",
+];
+
+/// Form controls that have to be labelled
+const FORM_CONTROLS: &[&str] = &["input", "select", "textarea"];
+
+/// Types of `<input>` that are labelled by their value or are not shown at all
+const SELF_LABELLED_INPUTS: &[&str] = &["button", "hidden", "image", "reset", "submit"];
+
+/// Check every element of the template, works without a browser, so templates can be checked in tests
+pub fn lint(template: &Template) -> Vec<Diagnostic> {
+    let labelled = labelled_ids(template);
+    let mut warnings = vec![];
+
+    visit_template(template, &mut |node, path, ancestors| {
+        if let NodeData::Element {
+            tag, attributes, ..
+        } = &node.data
+        {
+            for problem in check(tag, attributes, ancestors, &labelled) {
+                warnings.push(Diagnostic {
+                    path: path.to_string(),
+                    problem,
+                });
+            }
+        }
+    });
+
+    warnings
+}
+
+/// Ids `<label for="...">` elements point at, `None` if one of them is bound,
+/// as any control with an id could be the one it labels
+fn labelled_ids(template: &Template) -> Option<Vec<String>> {
+    let mut ids = Some(vec![]);
+
+    visit_template(template, &mut |node, _, _| match &node.data {
+        NodeData::Element {
+            tag, attributes, ..
+        } if tag == "label" => match attributes.get("for") {
+            Some(Attribute::Static(id)) => {
+                if let Some(ids) = ids.as_mut() {
+                    ids.push(id.clone());
+                }
+            }
+            Some(_) => ids = None,
+            None => {}
+        },
+        _ => {}
+    });
+
+    ids
+}
+
+fn check(
+    tag: &str,
+    attributes: &Attributes,
+    ancestors: &[&Node],
+    labelled: &Option<Vec<String>>,
+) -> Vec<Problem> {
+    let mut problems = vec![];
+    let has = |name: &str| attributes.contains_key(name);
+
+    if tag == "img" && !has("alt") {
+        problems.push(Problem::MissingAlt);
+    }
+
+    let clickable = matches!(attributes.get("click"), Some(Attribute::Handler(_)));
+    if clickable && NON_INTERACTIVE_ELEMENTS.contains(&tag) && !has("role") && !has("tabindex") {
+        problems.push(Problem::ClickWithoutRole {
+            tag: tag.to_string(),
+        });
+    }
+
+    if needs_label(tag, attributes) && !is_labelled(attributes, ancestors, labelled) {
+        problems.push(Problem::MissingLabel {
+            tag: tag.to_string(),
+        });
+    }
+
+    let invalid = sorted_attributes(attributes)
+        .into_iter()
+        .filter(|(name, _)| {
+            name.strip_prefix("aria-")
+                .is_some_and(|aria| !ARIA_ATTRIBUTES.contains(&aria))
+        });
+
+    for (name, _) in invalid {
+        problems.push(Problem::InvalidAria { name: name.clone() });
+    }
+
+    problems
+}
+
+fn needs_label(tag: &str, attributes: &Attributes) -> bool {
+    if !FORM_CONTROLS.contains(&tag) {
+        return false;
+    }
+
+    match (tag, attributes.get("type")) {
+        ("input", Some(Attribute::Static(kind))) => {
+            !SELF_LABELLED_INPUTS.contains(&kind.to_lowercase().as_str())
+        }
+        _ => true,
+    }
+}
+
+fn is_labelled(
+    attributes: &Attributes,
+    ancestors: &[&Node],
+    labelled: &Option<Vec<String>>,
+) -> bool {
+    if attributes.contains_key("aria-label") || attributes.contains_key("aria-labelledby") {
+        return true;
+    }
+
+    let in_label = ancestors
+        .iter()
+        .any(|node| matches!(&node.data, NodeData::Element { tag, .. } if tag == "label"));
+
+    let pointed_at = match (attributes.get("id"), labelled) {
+        (Some(Attribute::Static(id)), Some(ids)) => ids.contains(id),
+        (Some(_), None) => true,
+        _ => false,
+    };
+
+    in_label || pointed_at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::extract_html;
+
+    fn warnings(markup: &str) -> Vec<String> {
+        lint(&extract_html(&mut markup.to_string()))
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn images_need_alt_text() {
+        assert_eq!(
+            warnings("<div><img alt=\"\"><img src=\"a.png\"><img :alt=\"caption\"></div>"),
+            vec!["div > img[2]: <img> has no alt text"]
+        );
+    }
+
+    #[test]
+    fn click_handlers_need_role_or_tabindex() {
+        assert_eq!(
+            warnings(
+                "<div @click=\"open\"></div>\
+                 <div @click=\"open\" role=\"button\" tabindex=\"0\"></div>\
+                 <button @click=\"open\"></button>\
+                 <card @click=\"open\"></card>"
+            ),
+            vec!["div: <div> handles clicks but has no role or tabindex"]
+        );
+    }
+
+    #[test]
+    fn form_controls_need_labels() {
+        assert_eq!(
+            warnings(
+                "<form>\
+                   <input name=\"a\">\
+                   <label>b <input name=\"b\"></label>\
+                   <label for=\"c\">c</label><input id=\"c\">\
+                   <textarea aria-label=\"d\"></textarea>\
+                   <input type=\"submit\">\
+                   <select id=\"e\"></select>\
+                 </form>"
+            ),
+            vec![
+                "form > input: <input> has no label",
+                "form > select: <select> has no label"
+            ]
+        );
+    }
+
+    #[test]
+    fn aria_attributes_have_to_exist() {
+        assert_eq!(
+            warnings(
+                "<nav aria-lable=\"menu\" :aria-expanded=\"open\" aria-hidden=\"true\"></nav>"
+            ),
+            vec!["nav: aria-lable is not an aria attribute"]
+        );
+    }
+}
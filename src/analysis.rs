@@ -0,0 +1,153 @@
+/// This package checks bindings of a template against keys and messages its component declares,
+/// so typos show up when the component is registered instead of when it renders
+use crate::context::CONTEXT_PREFIX;
+use crate::diagnostics::{sorted_attributes, Diagnostic, Problem};
+use crate::router::ROUTE_PREFIX;
+use crate::store::STORE_PREFIX;
+use crate::templating::{
+    interpolations, visit_template, Attribute, Node, NodeData, Segment, Template, DEFAULT_ALIAS,
+    FOR,
+};
+
+/// Keys with these prefixes are not looked up in the component, they are not checked
+const FOREIGN_PREFIXES: &[&str] = &[ROUTE_PREFIX, CONTEXT_PREFIX, STORE_PREFIX];
+
+/// Check every binding, interpolation and handler of the template,
+/// empty list of keys or messages means component did not declare them and they are not checked
+pub fn check_bindings(template: &Template, keys: &[&str], messages: &[&str]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    visit_template(template, &mut |node, path, ancestors| {
+        let aliases = aliases(ancestors);
+        let is_known = |key: &str| is_known(key, keys, &aliases);
+
+        let mut report = |problem| {
+            diagnostics.push(Diagnostic {
+                path: path.to_string(),
+                problem,
+            })
+        };
+
+        match &node.data {
+            NodeData::Element {
+                tag, attributes, ..
+            } => {
+                for (name, attribute) in sorted_attributes(attributes) {
+                    match attribute {
+                        Attribute::Static(key)
+                            if tag == FOR && name == "collection" && !is_known(key) =>
+                        {
+                            report(Problem::UnknownKey { key: key.clone() })
+                        }
+                        Attribute::Dynamic(key) if !is_known(key) => {
+                            report(Problem::UnknownKey { key: key.clone() })
+                        }
+                        Attribute::Handler(message)
+                            if !messages.is_empty() && !messages.contains(&message.as_str()) =>
+                        {
+                            report(Problem::UnknownMessage {
+                                message: message.clone(),
+                            })
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            NodeData::Text { content } => {
+                for segment in interpolations(content) {
+                    match segment {
+                        Segment::Key(key) if !is_known(key) => report(Problem::UnknownKey {
+                            key: key.to_string(),
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    });
+
+    diagnostics
+}
+
+fn is_known(key: &str, keys: &[&str], aliases: &[&str]) -> bool {
+    keys.is_empty()
+        || keys.contains(&key)
+        || FOREIGN_PREFIXES
+            .iter()
+            .any(|prefix| key.starts_with(prefix))
+        // fields of list items are not declared anywhere
+        || key
+            .split_once('.')
+            .is_some_and(|(alias, _)| aliases.contains(&alias))
+}
+
+/// Names items of enclosing `<for>` elements are bound to
+fn aliases<'t>(ancestors: &[&'t Node]) -> Vec<&'t str> {
+    ancestors
+        .iter()
+        .filter_map(|ancestor| match &ancestor.data {
+            NodeData::Element {
+                tag, attributes, ..
+            } if tag == FOR => Some(match attributes.get("as") {
+                Some(Attribute::Static(alias)) => alias.as_str(),
+                _ => DEFAULT_ALIAS,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::extract_html;
+
+    fn diagnostics(markup: &str, keys: &[&str], messages: &[&str]) -> Vec<String> {
+        check_bindings(&extract_html(&mut markup.to_string()), keys, messages)
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect()
+    }
+
+    const TEMPLATE: &str = "<div :title=\"title\">\
+                              <p :class=\"count\">{{ count }} of {{total}}</p>\
+                              <p :class=\"$store.theme\" :id=\"$context.id\">{{ $route.id }}</p>\
+                              <button @click=\"increment\">+</button>\
+                              <button @click=\"incremnet\">+</button>\
+                            </div>";
+
+    #[test]
+    fn unknown_keys_and_messages_are_reported_with_location() {
+        assert_eq!(
+            diagnostics(TEMPLATE, &["count", "title"], &["increment"]),
+            vec![
+                "div > p > #text: component has no key total",
+                "div > button[2]: component does not handle message incremnet",
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_of_list_items_are_not_checked() {
+        let template = "<ul><for collection=\"todos\" as=\"todo\" key=\"id\">\
+                          <li :title=\"todo.title\">{{ item.title }}</li>\
+                        </for><for collection=\"todso\"></for></ul>";
+
+        assert_eq!(
+            diagnostics(template, &["todos"], &[]),
+            vec![
+                "ul > for > li > #text: component has no key item.title",
+                "ul > for[2]: component has no key todso",
+            ]
+        );
+    }
+
+    #[test]
+    fn undeclared_keys_and_messages_are_not_checked() {
+        assert!(diagnostics(TEMPLATE, &[], &[]).is_empty());
+        assert_eq!(
+            diagnostics(TEMPLATE, &[], &["increment"]),
+            vec!["div > button[2]: component does not handle message incremnet"]
+        );
+    }
+}
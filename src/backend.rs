@@ -0,0 +1,1641 @@
+/// This package abstracts dom manipulation, so virtual dom can be rendered in to the browser
+/// or in to in-memory document that works in native tests
+use crate::elements::{self, ElementCallbacks};
+use crate::framework::Subscription;
+use crate::html::extract_html;
+use crate::selector::Selector;
+use crate::templating::{Attribute, Node, NodeData};
+use crate::vdom::{
+    escape_attribute, escape_text, Event, EventTarget, Handler, Listener, Namespace, WeakListener,
+    INTERVAL_EVENT, RAW_TEXT_ELEMENTS, VOID_ELEMENTS,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Wake, Waker};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// Future driven to completion by the backend, its output is delivered by the future itself
+pub type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Handle to a node owned by one of the backends
+#[derive(Clone)]
+pub enum DomNode {
+    Web(web_sys::Node),
+    Memory(MemoryNode),
+}
+
+impl DomNode {
+    pub fn is_same(&self, other: &DomNode) -> bool {
+        match (self, other) {
+            (DomNode::Web(a), DomNode::Web(b)) => a.is_same_node(Some(b)),
+            (DomNode::Memory(a), DomNode::Memory(b)) => a.is_same(b),
+            _ => false,
+        }
+    }
+}
+
+pub trait DomBackend {
+    fn create_element(&self, tag: &str) -> DomNode;
+    /// Create element in a namespace other than html, e.g. svg
+    fn create_element_ns(&self, namespace: &str, tag: &str) -> DomNode;
+    fn create_text(&self, content: &str) -> DomNode;
+    fn get_element_by_id(&self, id: &str) -> Option<DomNode>;
+    /// First element in the document that matches css selector
+    fn query_selector(&self, selector: &str) -> Option<DomNode>;
+
+    /// Lowercase tag name of an element, `None` for any other node
+    fn tag(&self, node: &DomNode) -> Option<String>;
+    /// Content of a text node, `None` for any other node
+    fn text(&self, node: &DomNode) -> Option<String>;
+    fn set_text(&self, node: &DomNode, content: &str);
+
+    fn attribute(&self, node: &DomNode, name: &str) -> Option<String>;
+    fn attribute_names(&self, node: &DomNode) -> Vec<String>;
+    fn set_attribute(&self, node: &DomNode, name: &str, value: &str);
+    fn remove_attribute(&self, node: &DomNode, name: &str);
+
+    fn children(&self, node: &DomNode) -> Vec<DomNode>;
+    fn parent(&self, node: &DomNode) -> Option<DomNode>;
+    fn append_child(&self, parent: &DomNode, child: &DomNode);
+    fn insert_before(&self, parent: &DomNode, child: &DomNode, reference: &DomNode);
+    fn replace_child(&self, parent: &DomNode, new: &DomNode, old: &DomNode);
+    fn remove_child(&self, parent: &DomNode, child: &DomNode);
+    /// Remove every child of a node
+    fn clear(&self, node: &DomNode);
+    fn inner_html(&self, node: &DomNode) -> String;
+    /// Whether node is part of the document
+    fn is_connected(&self, node: &DomNode) -> bool;
+    /// Where element is rendered, left and top edge in pixels, `None` when it is not rendered
+    fn position(&self, node: &DomNode) -> Option<(f64, f64)>;
+    /// Set inline style property of an element, `None` removes it
+    fn set_style(&self, node: &DomNode, property: &str, value: Option<&str>);
+    /// Number that identifies node for as long as it is alive
+    fn node_key(&self, node: &DomNode) -> usize;
+
+    fn add_listener(&self, node: &DomNode, event: &str, handler: &Handler);
+    fn remove_listener(&self, node: &DomNode, event: &str, handler: &Handler);
+    /// Element components are rendered in to, backends that delegate events listen on it
+    fn add_root(&self, _root: &DomNode) {}
+    /// Nothing is rendered in to the element anymore, listeners added for it go away
+    fn remove_root(&self, _root: &DomNode) {}
+
+    /// Run callback once before the next repaint
+    fn request_frame(&self, callback: Box<dyn FnOnce()>);
+    /// Run callback right after currently running task, before the browser gets to render
+    fn queue_microtask(&self, callback: Box<dyn FnOnce()>);
+    /// Run callback once given number of milliseconds elapses
+    fn set_timeout(&self, millis: i32, callback: Box<dyn FnOnce()>);
+    /// Poll future in the background until it completes
+    fn spawn(&self, task: Task);
+
+    /// Start calling handler for events described by subscription
+    fn subscribe(&self, subscription: &Subscription, handler: &Handler);
+    fn unsubscribe(&self, subscription: &Subscription, handler: &Handler);
+
+    /// Path, query and fragment of the current page, e.g. `/users?page=2#top`
+    fn location(&self) -> String;
+    /// Add entry to the session history without reloading the page,
+    /// position is kept with the entry, see `history_position`
+    fn push_history(&self, url: &str, position: usize);
+    /// Change url and position of the current history entry without reloading the page
+    fn replace_history(&self, url: &str, position: usize);
+    /// Position the current history entry was added with,
+    /// `None` for entries the page did not add, e.g. url typed in to the address bar
+    fn history_position(&self) -> Option<usize>;
+    /// Move through the session history the same way back and forward buttons do
+    fn go(&self, delta: i32);
+
+    /// Make every element with given tag call back in to the framework, now and in the future
+    fn define_element(&self, tag: &str, attributes: &[String], callbacks: ElementCallbacks);
+    /// Shadow root of an element, created the first time it is asked for
+    fn attach_shadow(&self, host: &DomNode) -> DomNode;
+    /// Fire custom event with a detail on a node, it bubbles out of shadow roots
+    fn emit(&self, node: &DomNode, event: &str, detail: &str);
+
+    /// Add `<style>` with given id to the document head or to the shadow root,
+    /// unless it is already there
+    fn add_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str);
+    /// Add constructed stylesheet with given id to the document or to the shadow root,
+    /// unless it is already there, works under Content Security Policy
+    /// that does not allow inline styles
+    fn adopt_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str);
+}
+
+// ************** Browser backend **************
+
+/// Backend that talks to the real browser document through web_sys
+pub struct WebBackend;
+
+const NODE_KEY_PROPERTY: &str = "__componentsNodeKey";
+const STYLESHEET_ID_PROPERTY: &str = "__componentsStylesheetId";
+static NEXT_NODE_KEY: AtomicUsize = AtomicUsize::new(1);
+
+fn document() -> web_sys::Document {
+    web_sys::window()
+        .expect("could not get js/window")
+        .document()
+        .expect("could not get js/document instance")
+}
+
+fn history() -> web_sys::History {
+    web_sys::window()
+        .expect("could not get js/window")
+        .history()
+        .expect("could not get history")
+}
+
+fn web(node: &DomNode) -> &web_sys::Node {
+    match node {
+        DomNode::Web(node) => node,
+        DomNode::Memory(_) => panic!("in-memory node passed to the web backend"),
+    }
+}
+
+fn web_element(node: &DomNode) -> &web_sys::Element {
+    web(node)
+        .dyn_ref::<web_sys::Element>()
+        .expect("dom node is not an element")
+}
+
+fn shadow_root(node: &DomNode) -> &web_sys::ShadowRoot {
+    web(node)
+        .dyn_ref::<web_sys::ShadowRoot>()
+        .expect("dom node is not a shadow root")
+}
+
+impl DomBackend for WebBackend {
+    fn create_element(&self, tag: &str) -> DomNode {
+        let element = document()
+            .create_element(tag)
+            .expect("could not create dom element");
+
+        DomNode::Web(element.into())
+    }
+
+    fn create_element_ns(&self, namespace: &str, tag: &str) -> DomNode {
+        let element = document()
+            .create_element_ns(Some(namespace), tag)
+            .expect("could not create dom element");
+
+        DomNode::Web(element.into())
+    }
+
+    fn create_text(&self, content: &str) -> DomNode {
+        DomNode::Web(document().create_text_node(content).into())
+    }
+
+    fn get_element_by_id(&self, id: &str) -> Option<DomNode> {
+        document()
+            .get_element_by_id(id)
+            .map(|element| DomNode::Web(element.into()))
+    }
+
+    fn query_selector(&self, selector: &str) -> Option<DomNode> {
+        document()
+            .query_selector(selector)
+            .expect("invalid selector")
+            .map(|element| DomNode::Web(element.into()))
+    }
+
+    fn tag(&self, node: &DomNode) -> Option<String> {
+        web(node)
+            .dyn_ref::<web_sys::Element>()
+            // tag name of html elements is upper case, svg ones keep their case, e.g. foreignObject
+            .map(|element| element.local_name())
+    }
+
+    fn text(&self, node: &DomNode) -> Option<String> {
+        let node = web(node);
+
+        if node.node_type() == web_sys::Node::TEXT_NODE {
+            node.text_content()
+        } else {
+            None
+        }
+    }
+
+    fn set_text(&self, node: &DomNode, content: &str) {
+        web(node).set_text_content(Some(content));
+    }
+
+    fn attribute(&self, node: &DomNode, name: &str) -> Option<String> {
+        web_element(node).get_attribute(name)
+    }
+
+    fn attribute_names(&self, node: &DomNode) -> Vec<String> {
+        let attributes = web_element(node).attributes();
+
+        (0..attributes.length())
+            .filter_map(|i| attributes.item(i))
+            .map(|attr| attr.name())
+            .collect()
+    }
+
+    fn set_attribute(&self, node: &DomNode, name: &str, value: &str) {
+        web_element(node)
+            .set_attribute(name, value)
+            .expect("could not set attribute");
+    }
+
+    fn remove_attribute(&self, node: &DomNode, name: &str) {
+        web_element(node)
+            .remove_attribute(name)
+            .expect("could not remove attribute");
+    }
+
+    fn children(&self, node: &DomNode) -> Vec<DomNode> {
+        let children = web(node).child_nodes();
+
+        (0..children.length())
+            .filter_map(|i| children.item(i))
+            .map(DomNode::Web)
+            .collect()
+    }
+
+    fn parent(&self, node: &DomNode) -> Option<DomNode> {
+        web(node).parent_node().map(DomNode::Web)
+    }
+
+    fn append_child(&self, parent: &DomNode, child: &DomNode) {
+        web(parent)
+            .append_child(web(child))
+            .expect("could not insert a child");
+    }
+
+    fn insert_before(&self, parent: &DomNode, child: &DomNode, reference: &DomNode) {
+        web(parent)
+            .insert_before(web(child), Some(web(reference)))
+            .expect("could not insert a child");
+    }
+
+    fn replace_child(&self, parent: &DomNode, new: &DomNode, old: &DomNode) {
+        web(parent)
+            .replace_child(web(new), web(old))
+            .expect("could not replace a child");
+    }
+
+    fn remove_child(&self, parent: &DomNode, child: &DomNode) {
+        web(parent)
+            .remove_child(web(child))
+            .expect("could not remove a child");
+    }
+
+    fn clear(&self, node: &DomNode) {
+        // children are removed one by one, so nothing relies on `innerHTML`
+        let node = web(node);
+
+        while let Some(child) = node.first_child() {
+            node.remove_child(&child).expect("could not remove a child");
+        }
+    }
+
+    fn inner_html(&self, node: &DomNode) -> String {
+        web_element(node).inner_html()
+    }
+
+    fn is_connected(&self, node: &DomNode) -> bool {
+        web(node).is_connected()
+    }
+
+    fn position(&self, node: &DomNode) -> Option<(f64, f64)> {
+        let rect = web(node)
+            .dyn_ref::<web_sys::Element>()?
+            .get_bounding_client_rect();
+
+        Some((rect.left(), rect.top()))
+    }
+
+    fn set_style(&self, node: &DomNode, property: &str, value: Option<&str>) {
+        // svg elements have inline styles as well, they are not html elements though
+        let style = match web(node).dyn_ref::<web_sys::HtmlElement>() {
+            Some(element) => element.style(),
+            None => return,
+        };
+
+        match value {
+            Some(value) => style.set_property(property, value),
+            None => style.remove_property(property).map(drop),
+        }
+        .expect("could not set style");
+    }
+
+    fn node_key(&self, node: &DomNode) -> usize {
+        // key is kept as an expando property, so it lives exactly as long as the node
+        let node = web(node);
+        let property = JsValue::from_str(NODE_KEY_PROPERTY);
+
+        match js_sys::Reflect::get(node, &property)
+            .ok()
+            .and_then(|k| k.as_f64())
+        {
+            Some(key) => key as usize,
+            None => {
+                let key = NEXT_NODE_KEY.fetch_add(1, Ordering::Relaxed);
+                js_sys::Reflect::set(node, &property, &JsValue::from_f64(key as f64))
+                    .expect("could not set node key");
+                key
+            }
+        }
+    }
+
+    fn add_listener(&self, node: &DomNode, event: &str, handler: &Handler) {
+        handler.attach(web(node), event);
+    }
+
+    fn remove_listener(&self, node: &DomNode, event: &str, handler: &Handler) {
+        handler.detach(web(node), event);
+    }
+
+    fn request_frame(&self, callback: Box<dyn FnOnce()>) {
+        // one shot closure frees itself after the call
+        let closure = Closure::once_into_js(callback);
+
+        web_sys::window()
+            .expect("could not get js/window")
+            .request_animation_frame(closure.unchecked_ref())
+            .expect("could not request animation frame");
+    }
+
+    fn queue_microtask(&self, callback: Box<dyn FnOnce()>) {
+        let closure = Closure::once_into_js(callback);
+
+        web_sys::window()
+            .expect("could not get js/window")
+            .queue_microtask(closure.unchecked_ref());
+    }
+
+    fn set_timeout(&self, millis: i32, callback: Box<dyn FnOnce()>) {
+        let closure = Closure::once_into_js(callback);
+
+        web_sys::window()
+            .expect("could not get js/window")
+            .set_timeout_with_callback_and_timeout_and_arguments_0(closure.unchecked_ref(), millis)
+            .expect("could not set timeout");
+    }
+
+    fn spawn(&self, task: Task) {
+        wasm_bindgen_futures::spawn_local(task);
+    }
+
+    fn subscribe(&self, subscription: &Subscription, handler: &Handler) {
+        let window = web_sys::window().expect("could not get js/window");
+
+        match subscription {
+            Subscription::Interval { millis, .. } => handler.start_interval(&window, *millis),
+            Subscription::Window { event, .. } => handler.attach(&window, event),
+            Subscription::Document { event, .. } => handler.attach(&document(), event),
+        }
+    }
+
+    fn unsubscribe(&self, subscription: &Subscription, handler: &Handler) {
+        let window = web_sys::window().expect("could not get js/window");
+
+        match subscription {
+            Subscription::Interval { .. } => handler.stop_interval(),
+            Subscription::Window { event, .. } => handler.detach(&window, event),
+            Subscription::Document { event, .. } => handler.detach(&document(), event),
+        }
+    }
+
+    fn location(&self) -> String {
+        let location = web_sys::window()
+            .expect("could not get js/window")
+            .location();
+
+        let part = |value: Result<String, JsValue>| value.expect("could not read location");
+        part(location.pathname()) + &part(location.search()) + &part(location.hash())
+    }
+
+    fn push_history(&self, url: &str, position: usize) {
+        history()
+            .push_state_with_url(&JsValue::from(position as f64), "", Some(url))
+            .expect("could not push history state");
+    }
+
+    fn replace_history(&self, url: &str, position: usize) {
+        history()
+            .replace_state_with_url(&JsValue::from(position as f64), "", Some(url))
+            .expect("could not replace history state");
+    }
+
+    fn history_position(&self) -> Option<usize> {
+        let state = history().state().expect("could not read history state");
+        state.as_f64().map(|position| position as usize)
+    }
+
+    fn go(&self, delta: i32) {
+        history()
+            .go_with_delta(delta)
+            .expect("could not move through history");
+    }
+
+    fn define_element(&self, tag: &str, attributes: &[String], callbacks: ElementCallbacks) {
+        let ElementCallbacks {
+            connected,
+            disconnected,
+            attribute_changed,
+        } = callbacks;
+
+        let connected =
+            Closure::wrap(
+                Box::new(move |node: web_sys::Node| connected(&DomNode::Web(node)))
+                    as Box<dyn Fn(web_sys::Node)>,
+            );
+        let disconnected =
+            Closure::wrap(
+                Box::new(move |node: web_sys::Node| disconnected(&DomNode::Web(node)))
+                    as Box<dyn Fn(web_sys::Node)>,
+            );
+        let changed = Closure::wrap(Box::new(
+            move |node: web_sys::Node, name: String, value: Option<String>| {
+                attribute_changed(&DomNode::Web(node), &name, value)
+            },
+        )
+            as Box<dyn Fn(web_sys::Node, String, Option<String>)>);
+
+        let observed = attributes.iter().map(|a| JsValue::from_str(a)).collect();
+
+        elements::define_element(
+            tag,
+            observed,
+            connected.as_ref(),
+            disconnected.as_ref(),
+            changed.as_ref(),
+        );
+
+        // custom elements can not be undefined, so closures live as long as the page
+        connected.forget();
+        disconnected.forget();
+        changed.forget();
+    }
+
+    fn attach_shadow(&self, host: &DomNode) -> DomNode {
+        let host = web_element(host);
+
+        let shadow = host.shadow_root().unwrap_or_else(|| {
+            host.attach_shadow(&web_sys::ShadowRootInit::new(web_sys::ShadowRootMode::Open))
+                .expect("could not attach shadow root")
+        });
+
+        DomNode::Web(shadow.into())
+    }
+
+    fn emit(&self, node: &DomNode, event: &str, detail: &str) {
+        let init = web_sys::CustomEventInit::new();
+        init.set_bubbles(true);
+        init.set_composed(true);
+        init.set_detail(&JsValue::from_str(detail));
+
+        let event = web_sys::CustomEvent::new_with_event_init_dict(event, &init)
+            .expect("could not create custom event");
+
+        web(node)
+            .dispatch_event(&event)
+            .expect("could not dispatch custom event");
+    }
+
+    fn add_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str) {
+        let document = document();
+        let shadow = root.map(shadow_root);
+
+        let exists = match shadow {
+            Some(shadow) => shadow.get_element_by_id(id).is_some(),
+            None => document.get_element_by_id(id).is_some(),
+        };
+
+        if exists {
+            return;
+        }
+
+        let style = document
+            .create_element("style")
+            .expect("could not create style element");
+        style.set_id(id);
+        style.set_text_content(Some(css));
+
+        let parent: web_sys::Node = match shadow {
+            Some(shadow) => shadow.clone().into(),
+            None => document.head().expect("document has no head").into(),
+        };
+
+        parent
+            .append_child(&style)
+            .expect("could not add stylesheet");
+    }
+
+    fn adopt_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str) {
+        let shadow = root.map(shadow_root);
+        let sheets = match shadow {
+            Some(shadow) => shadow.adopted_style_sheets(),
+            None => document().adopted_style_sheets(),
+        };
+        let property = JsValue::from_str(STYLESHEET_ID_PROPERTY);
+
+        let exists = sheets.iter().any(|sheet| {
+            js_sys::Reflect::get(&sheet, &property)
+                .ok()
+                .and_then(|v| v.as_string())
+                .is_some_and(|v| v == id)
+        });
+
+        if exists {
+            return;
+        }
+
+        let sheet = web_sys::CssStyleSheet::new().expect("could not create stylesheet");
+        sheet.replace_sync(css).expect("could not parse stylesheet");
+        js_sys::Reflect::set(&sheet, &property, &JsValue::from_str(id))
+            .expect("could not set stylesheet id");
+
+        // adopted sheets can only be replaced as a whole
+        sheets.push(&sheet);
+        match shadow {
+            Some(shadow) => shadow.set_adopted_style_sheets(&sheets),
+            None => document().set_adopted_style_sheets(&sheets),
+        }
+    }
+}
+
+// ************** In-memory backend **************
+
+enum MemoryNodeData {
+    Element {
+        tag: String,
+        /// Uri of the namespace, `None` for html elements
+        namespace: Option<String>,
+        attributes: BTreeMap<String, String>,
+    },
+    Text {
+        content: String,
+    },
+}
+
+struct MemoryNodeInner {
+    data: MemoryNodeData,
+    children: Vec<MemoryNode>,
+    parent: Weak<RefCell<MemoryNodeInner>>,
+    /// Handlers own their callbacks, listeners of dropped handlers are never called again
+    listeners: HashMap<String, Vec<WeakListener>>,
+    /// Root of the shadow tree attached to this element, its parent is the host
+    shadow: Option<MemoryNode>,
+}
+
+/// Node of in-memory document, cloning gives another handle to the same node
+#[derive(Clone)]
+pub struct MemoryNode(Rc<RefCell<MemoryNodeInner>>);
+
+impl MemoryNode {
+    fn new(data: MemoryNodeData) -> Self {
+        MemoryNode(Rc::new(RefCell::new(MemoryNodeInner {
+            data,
+            children: vec![],
+            parent: Weak::new(),
+            listeners: HashMap::new(),
+            shadow: None,
+        })))
+    }
+
+    pub fn is_same(&self, other: &MemoryNode) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn tag(&self) -> Option<String> {
+        match &self.0.borrow().data {
+            MemoryNodeData::Element { tag, .. } => Some(tag.clone()),
+            MemoryNodeData::Text { .. } => None,
+        }
+    }
+
+    pub fn namespace(&self) -> Option<String> {
+        match &self.0.borrow().data {
+            MemoryNodeData::Element { namespace, .. } => namespace.clone(),
+            MemoryNodeData::Text { .. } => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<String> {
+        match &self.0.borrow().data {
+            MemoryNodeData::Text { content } => Some(content.clone()),
+            MemoryNodeData::Element { .. } => None,
+        }
+    }
+
+    /// Concatenated content of every text node in the subtree
+    pub fn text_content(&self) -> String {
+        match self.text() {
+            Some(content) => content,
+            None => self.children().iter().map(|c| c.text_content()).collect(),
+        }
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        match &self.0.borrow().data {
+            MemoryNodeData::Element { attributes, .. } => attributes.get(name).cloned(),
+            MemoryNodeData::Text { .. } => None,
+        }
+    }
+
+    pub fn children(&self) -> Vec<MemoryNode> {
+        self.0.borrow().children.clone()
+    }
+
+    pub fn parent(&self) -> Option<MemoryNode> {
+        self.0.borrow().parent.upgrade().map(MemoryNode)
+    }
+
+    pub fn shadow_root(&self) -> Option<MemoryNode> {
+        self.0.borrow().shadow.clone()
+    }
+
+    /// Number of listeners attached to this node for given event type
+    pub fn listener_count(&self, event: &str) -> usize {
+        self.0.borrow().listeners.get(event).map_or(0, |listeners| {
+            listeners.iter().filter(|l| l.strong_count() > 0).count()
+        })
+    }
+
+    /// Call listeners of this node and then of its ancestors, the way bubbling events do
+    pub fn dispatch(&self, kind: &str) {
+        self.dispatch_event(&Event::new(kind));
+    }
+
+    pub fn dispatch_event(&self, event: &Event) {
+        let mut event = event.clone();
+        event
+            .target
+            .get_or_insert_with(|| EventTarget(DomNode::Memory(self.clone())));
+        let event = &event;
+
+        let mut current = Some(self.clone());
+
+        while let Some(node) = current {
+            // release the borrow before calling listeners, they are free to modify the tree
+            let listeners = node
+                .0
+                .borrow()
+                .listeners
+                .get(&event.kind)
+                .map(|listeners| listeners.iter().filter_map(Weak::upgrade).collect())
+                .unwrap_or_else(Vec::new);
+
+            for listener in listeners {
+                listener(event);
+            }
+
+            current = node.parent();
+        }
+    }
+
+    /// Depth first search through the subtree, including this node
+    pub fn find(&self, predicate: &dyn Fn(&MemoryNode) -> bool) -> Option<MemoryNode> {
+        if predicate(self) {
+            return Some(self.clone());
+        }
+
+        self.children()
+            .iter()
+            .find_map(|child| child.find(predicate))
+    }
+
+    pub fn to_html_string(&self) -> String {
+        let inner = self.0.borrow();
+
+        match &inner.data {
+            MemoryNodeData::Text { content } => escape_text(content),
+            MemoryNodeData::Element {
+                tag, attributes, ..
+            } => {
+                let mut html = format!("<{}", tag);
+
+                for (name, value) in attributes {
+                    html.push_str(&format!(" {}=\"{}\"", name, escape_attribute(value)));
+                }
+
+                html.push('>');
+
+                if !VOID_ELEMENTS.contains(&tag.as_str()) {
+                    html.push_str(&self.inner_html());
+                    html.push_str(&format!("</{}>", tag));
+                }
+
+                html
+            }
+        }
+    }
+
+    pub fn inner_html(&self) -> String {
+        match self.tag() {
+            Some(tag) if RAW_TEXT_ELEMENTS.contains(&tag.as_str()) => self.text_content(),
+            _ => self.children().iter().map(|c| c.to_html_string()).collect(),
+        }
+    }
+
+    pub fn set_attribute(&self, name: &str, value: &str) {
+        if let MemoryNodeData::Element { attributes, .. } = &mut self.0.borrow_mut().data {
+            attributes.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    fn remove_attribute(&self, name: &str) {
+        if let MemoryNodeData::Element { attributes, .. } = &mut self.0.borrow_mut().data {
+            attributes.remove(name);
+        }
+    }
+
+    fn index_of(&self, child: &MemoryNode) -> usize {
+        self.0
+            .borrow()
+            .children
+            .iter()
+            .position(|c| c.is_same(child))
+            .expect("node is not a child of this parent")
+    }
+
+    fn detach(&self) {
+        if let Some(parent) = self.parent() {
+            let index = parent.index_of(self);
+            parent.0.borrow_mut().children.remove(index);
+        }
+
+        self.0.borrow_mut().parent = Weak::new();
+    }
+
+    fn insert(&self, index: usize, child: &MemoryNode) {
+        child.detach();
+        child.0.borrow_mut().parent = Rc::downgrade(&self.0);
+        self.0.borrow_mut().children.insert(index, child.clone());
+    }
+}
+
+fn memory(node: &DomNode) -> &MemoryNode {
+    match node {
+        DomNode::Memory(node) => node,
+        DomNode::Web(_) => panic!("web node passed to the in-memory backend"),
+    }
+}
+
+/// Fragment part of the url
+fn hash(url: &str) -> &str {
+    url.split_once('#').map_or("", |(_, hash)| hash)
+}
+
+type Timeout = (u64, Box<dyn FnOnce()>);
+
+/// Flag spawned task gets woken through, only flagged tasks are polled
+struct TaskWaker(AtomicBool);
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Backend that keeps the whole document in memory, used to test rendering without a browser
+pub struct MemoryBackend {
+    head: MemoryNode,
+    body: MemoryNode,
+    frames: RefCell<Vec<Box<dyn FnOnce()>>>,
+    microtasks: RefCell<Vec<Box<dyn FnOnce()>>>,
+    tasks: RefCell<Vec<(Task, Arc<TaskWaker>)>>,
+    /// Registered subscriptions with the time their interval fires next
+    subscriptions: RefCell<Vec<(Subscription, Listener, u64)>>,
+    /// Callbacks with the time they are due at
+    timeouts: RefCell<Vec<Timeout>>,
+    /// Virtual time in milliseconds, moved forward by `advance`
+    now: Cell<u64>,
+    /// Session history, urls with positions they were added with
+    history: RefCell<Vec<(String, Option<usize>)>>,
+    /// Index of the current location in `history`
+    current: Cell<usize>,
+    /// Custom elements by tag
+    elements: RefCell<HashMap<String, Rc<DefinedElement>>>,
+    /// Constructed stylesheets by the shadow root that adopted them, `None` for the document,
+    /// in the order they were adopted
+    adopted: RefCell<Vec<(Option<MemoryNode>, String, String)>>,
+}
+
+struct DefinedElement {
+    attributes: Vec<String>,
+    callbacks: ElementCallbacks,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend {
+            head: MemoryNode::new(MemoryNodeData::Element {
+                tag: "head".to_string(),
+                namespace: None,
+                attributes: BTreeMap::new(),
+            }),
+            body: MemoryNode::new(MemoryNodeData::Element {
+                tag: "body".to_string(),
+                namespace: None,
+                attributes: BTreeMap::new(),
+            }),
+            frames: RefCell::new(vec![]),
+            microtasks: RefCell::new(vec![]),
+            tasks: RefCell::new(vec![]),
+            subscriptions: RefCell::new(vec![]),
+            timeouts: RefCell::new(vec![]),
+            now: Cell::new(0),
+            history: RefCell::new(vec![("/".to_string(), None)]),
+            current: Cell::new(0),
+            elements: RefCell::new(HashMap::new()),
+            adopted: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn head(&self) -> MemoryNode {
+        self.head.clone()
+    }
+
+    /// Ids and css of constructed stylesheets the document adopted
+    pub fn adopted_stylesheets(&self) -> Vec<(String, String)> {
+        self.adopted_by(None)
+    }
+
+    /// Ids and css of constructed stylesheets the shadow root adopted
+    pub fn adopted_stylesheets_of(&self, shadow: &MemoryNode) -> Vec<(String, String)> {
+        self.adopted_by(Some(shadow))
+    }
+
+    fn adopted_by(&self, root: Option<&MemoryNode>) -> Vec<(String, String)> {
+        self.adopted
+            .borrow()
+            .iter()
+            .filter(|(r, _, _)| match (r, root) {
+                (Some(r), Some(root)) => r.is_same(root),
+                (r, root) => r.is_none() && root.is_none(),
+            })
+            .map(|(_, id, css)| (id.clone(), css.clone()))
+            .collect()
+    }
+
+    pub fn body(&self) -> MemoryNode {
+        self.body.clone()
+    }
+
+    pub fn pending_frames(&self) -> usize {
+        self.frames.borrow().len()
+    }
+
+    pub fn pending_microtasks(&self) -> usize {
+        self.microtasks.borrow().len()
+    }
+
+    /// Run queued microtasks, including the ones queued while running
+    pub fn run_microtasks(&self) {
+        loop {
+            let microtasks = self.microtasks.replace(vec![]);
+
+            if microtasks.is_empty() {
+                return;
+            }
+
+            for microtask in microtasks {
+                microtask();
+            }
+        }
+    }
+
+    pub fn pending_tasks(&self) -> usize {
+        self.tasks.borrow().len()
+    }
+
+    /// Poll spawned futures that were woken since the last call and keep the ones that
+    /// are not ready yet, there is no reactor, so tests wake futures themselves and call this again
+    pub fn run_tasks(&self) {
+        let tasks = self.tasks.replace(vec![]);
+
+        for (mut task, waker) in tasks {
+            if waker.0.swap(false, Ordering::Relaxed) {
+                let woken = Waker::from(Arc::clone(&waker));
+                if task
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&woken))
+                    .is_ready()
+                {
+                    continue;
+                }
+            }
+
+            self.tasks.borrow_mut().push((task, waker));
+        }
+    }
+
+    pub fn subscription_count(&self) -> usize {
+        self.subscriptions.borrow().len()
+    }
+
+    fn listeners(&self, matches: impl Fn(&Subscription) -> bool) -> Vec<Listener> {
+        self.subscriptions
+            .borrow()
+            .iter()
+            .filter(|(subscription, _, _)| matches(subscription))
+            .map(|(_, listener, _)| Rc::clone(listener))
+            .collect()
+    }
+
+    /// Fire event on the window, e.g. `resize`
+    pub fn dispatch_window(&self, event: &Event) {
+        let listeners = self.listeners(|s| match s {
+            Subscription::Window { event: kind, .. } => *kind == event.kind,
+            _ => false,
+        });
+
+        for listener in listeners {
+            listener(event);
+        }
+    }
+
+    /// Fire event on the document, e.g. `keydown`
+    pub fn dispatch_document(&self, event: &Event) {
+        let listeners = self.listeners(|s| match s {
+            Subscription::Document { event: kind, .. } => *kind == event.kind,
+            _ => false,
+        });
+
+        for listener in listeners {
+            listener(event);
+        }
+    }
+
+    /// Move virtual time forward and fire intervals and timeouts that are due, in order
+    pub fn advance(&self, millis: u64) {
+        let until = self.now.get() + millis;
+
+        loop {
+            let timeout = self
+                .timeouts
+                .borrow()
+                .iter()
+                .map(|(due, _)| *due)
+                .filter(|due| *due <= until)
+                .min();
+
+            // earliest interval that is due, listener is called without holding the borrow
+            let due = {
+                let mut subscriptions = self.subscriptions.borrow_mut();
+                let next = subscriptions
+                    .iter_mut()
+                    .filter(|(s, _, due)| {
+                        matches!(s, Subscription::Interval { .. })
+                            && *due <= until
+                            && timeout.is_none_or(|timeout| *due < timeout)
+                    })
+                    .min_by_key(|(_, _, due)| *due);
+
+                match next {
+                    Some((Subscription::Interval { millis, .. }, listener, due)) => {
+                        let fired = *due;
+                        *due += (*millis).max(1) as u64;
+                        Some((fired, Rc::clone(listener)))
+                    }
+                    _ => None,
+                }
+            };
+
+            match (due, timeout) {
+                (Some((fired, listener)), _) => {
+                    self.now.set(fired);
+                    listener(&Event::new(INTERVAL_EVENT));
+                }
+                (None, Some(fired)) => {
+                    let callback = {
+                        let mut timeouts = self.timeouts.borrow_mut();
+                        let index = timeouts.iter().position(|(due, _)| *due == fired);
+                        timeouts.remove(index.expect("timeout is gone")).1
+                    };
+                    self.now.set(fired);
+                    callback();
+                }
+                (None, None) => break,
+            }
+        }
+
+        self.now.set(until);
+    }
+
+    /// Pretend user opened given url, e.g. typed it in to the address bar
+    pub fn visit(&self, url: &str) {
+        let previous = self.location();
+        self.add_entry(url, None);
+
+        if hash(&previous) != hash(&self.location()) {
+            self.dispatch_window(&Event::new("hashchange"));
+        }
+    }
+
+    /// Go to the previous history entry, same as browser back button
+    pub fn back(&self) {
+        self.go(-1);
+    }
+
+    /// Go to the next history entry, same as browser forward button
+    pub fn forward(&self) {
+        self.go(1);
+    }
+
+    /// Urls of the session history and index of the current one
+    pub fn history(&self) -> (Vec<String>, usize) {
+        let urls = self
+            .history
+            .borrow()
+            .iter()
+            .map(|(url, _)| url.clone())
+            .collect();
+        (urls, self.current.get())
+    }
+
+    /// Add entry after the current one, entries the user could go forward to are dropped
+    fn add_entry(&self, url: &str, position: Option<usize>) {
+        let url = self.resolve_url(url);
+        let current = self.current.get() + 1;
+
+        let mut history = self.history.borrow_mut();
+        history.truncate(current);
+        history.push((url, position));
+        self.current.set(current);
+    }
+
+    /// Resolve url against the current one the same way browser does it for links
+    fn resolve_url(&self, url: &str) -> String {
+        let current = self.location();
+
+        if url.starts_with('#') {
+            format!("{}{}", current.split('#').next().unwrap_or_default(), url)
+        } else if url.starts_with('?') {
+            format!(
+                "{}{}",
+                current.split(['?', '#']).next().unwrap_or_default(),
+                url
+            )
+        } else {
+            url.to_string()
+        }
+    }
+
+    pub fn history_length(&self) -> usize {
+        self.history.borrow().len()
+    }
+
+    /// Run callbacks requested so far, frames requested by the callbacks wait for the next call
+    pub fn run_frames(&self) {
+        let frames = self.frames.replace(vec![]);
+
+        for frame in frames {
+            frame();
+        }
+    }
+
+    fn in_document(&self, node: &MemoryNode) -> bool {
+        let mut current = Some(node.clone());
+
+        while let Some(node) = current {
+            if node.is_same(&self.body) {
+                return true;
+            }
+            current = node.parent();
+        }
+
+        false
+    }
+
+    /// Custom elements in the subtree, including shadow trees, in document order
+    fn defined_in(&self, node: &MemoryNode, found: &mut Vec<(MemoryNode, Rc<DefinedElement>)>) {
+        let defined = node
+            .tag()
+            .and_then(|tag| self.elements.borrow().get(&tag).cloned());
+
+        if let Some(defined) = defined {
+            found.push((node.clone(), defined));
+        }
+
+        for child in node.shadow_root().into_iter().chain(node.children()) {
+            self.defined_in(&child, found);
+        }
+    }
+
+    /// Call lifecycle callback of every custom element in the subtree
+    fn notify(&self, node: &MemoryNode, callback: impl Fn(&ElementCallbacks) -> &dyn Fn(&DomNode)) {
+        let mut found = vec![];
+        self.defined_in(node, &mut found);
+
+        for (element, defined) in found {
+            callback(&defined.callbacks)(&DomNode::Memory(element));
+        }
+    }
+
+    fn connect(&self, node: &MemoryNode) {
+        if self.in_document(node) {
+            self.notify(node, |callbacks| &callbacks.connected);
+        }
+    }
+
+    /// Take node out of its parent, custom elements in it get disconnected
+    fn take_out(&self, node: &MemoryNode) {
+        let connected = self.in_document(node);
+        node.detach();
+
+        if connected {
+            self.notify(node, |callbacks| &callbacks.disconnected);
+        }
+    }
+
+    fn attribute_changed(&self, node: &MemoryNode, name: &str) {
+        let defined = node
+            .tag()
+            .and_then(|tag| self.elements.borrow().get(&tag).cloned());
+
+        if let Some(defined) = defined {
+            if defined.attributes.iter().any(|a| a == name) {
+                (defined.callbacks.attribute_changed)(
+                    &DomNode::Memory(node.clone()),
+                    name,
+                    node.attribute(name),
+                );
+            }
+        }
+    }
+
+    /// Parse html and append resulting nodes to the given node
+    pub fn append_html(&self, node: &DomNode, html: &str) {
+        for child in extract_html(&mut html.to_string()).iter() {
+            let created = self.create_from_template(child);
+            self.append_child(node, &created);
+        }
+    }
+
+    fn create_from_template(&self, node: &Node) -> DomNode {
+        let created = match &node.data {
+            NodeData::Text { content } => self.create_text(content),
+            NodeData::Element {
+                tag,
+                namespace,
+                attributes,
+            } => {
+                let element = match namespace {
+                    Namespace::Html => self.create_element(tag),
+                    namespace => self.create_element_ns(namespace.uri(), tag),
+                };
+
+                for (name, attribute) in attributes {
+                    let (name, value) = match attribute {
+                        Attribute::Static(value) => (name.clone(), value),
+                        Attribute::Dynamic(value) => (format!(":{}", name), value),
+                        Attribute::Handler(value) => (format!("@{}", name), value),
+                    };
+
+                    self.set_attribute(&element, &name, value);
+                }
+
+                element
+            }
+        };
+
+        for child in node.children.iter() {
+            let child = self.create_from_template(child);
+            self.append_child(&created, &child);
+        }
+
+        created
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DomBackend for MemoryBackend {
+    fn create_element(&self, tag: &str) -> DomNode {
+        DomNode::Memory(MemoryNode::new(MemoryNodeData::Element {
+            tag: tag.to_lowercase(),
+            namespace: None,
+            attributes: BTreeMap::new(),
+        }))
+    }
+
+    fn create_element_ns(&self, namespace: &str, tag: &str) -> DomNode {
+        DomNode::Memory(MemoryNode::new(MemoryNodeData::Element {
+            tag: tag.to_string(),
+            namespace: Some(namespace.to_string()),
+            attributes: BTreeMap::new(),
+        }))
+    }
+
+    fn create_text(&self, content: &str) -> DomNode {
+        DomNode::Memory(MemoryNode::new(MemoryNodeData::Text {
+            content: content.to_string(),
+        }))
+    }
+
+    fn get_element_by_id(&self, id: &str) -> Option<DomNode> {
+        self.body
+            .find(&|node| node.attribute("id").as_deref() == Some(id))
+            .map(DomNode::Memory)
+    }
+
+    fn query_selector(&self, selector: &str) -> Option<DomNode> {
+        let selector = Selector::parse(selector);
+
+        self.body
+            .find(&|node| selector.matches(node))
+            .map(DomNode::Memory)
+    }
+
+    fn tag(&self, node: &DomNode) -> Option<String> {
+        memory(node).tag()
+    }
+
+    fn text(&self, node: &DomNode) -> Option<String> {
+        memory(node).text()
+    }
+
+    fn set_text(&self, node: &DomNode, content: &str) {
+        if let MemoryNodeData::Text { content: current } = &mut memory(node).0.borrow_mut().data {
+            *current = content.to_string();
+        }
+    }
+
+    fn attribute(&self, node: &DomNode, name: &str) -> Option<String> {
+        memory(node).attribute(name)
+    }
+
+    fn attribute_names(&self, node: &DomNode) -> Vec<String> {
+        match &memory(node).0.borrow().data {
+            MemoryNodeData::Element { attributes, .. } => attributes.keys().cloned().collect(),
+            MemoryNodeData::Text { .. } => vec![],
+        }
+    }
+
+    fn set_attribute(&self, node: &DomNode, name: &str, value: &str) {
+        memory(node).set_attribute(name, value);
+        self.attribute_changed(memory(node), name);
+    }
+
+    fn remove_attribute(&self, node: &DomNode, name: &str) {
+        memory(node).remove_attribute(name);
+        self.attribute_changed(memory(node), name);
+    }
+
+    fn children(&self, node: &DomNode) -> Vec<DomNode> {
+        memory(node)
+            .children()
+            .into_iter()
+            .map(DomNode::Memory)
+            .collect()
+    }
+
+    fn parent(&self, node: &DomNode) -> Option<DomNode> {
+        memory(node).parent().map(DomNode::Memory)
+    }
+
+    fn append_child(&self, parent: &DomNode, child: &DomNode) {
+        let parent = memory(parent);
+        // detach first, index would be off by one when child is already in this parent
+        self.take_out(memory(child));
+        let index = parent.0.borrow().children.len();
+        parent.insert(index, memory(child));
+        self.connect(memory(child));
+    }
+
+    fn insert_before(&self, parent: &DomNode, child: &DomNode, reference: &DomNode) {
+        let parent = memory(parent);
+        self.take_out(memory(child));
+        let index = parent.index_of(memory(reference));
+        parent.insert(index, memory(child));
+        self.connect(memory(child));
+    }
+
+    fn replace_child(&self, parent: &DomNode, new: &DomNode, old: &DomNode) {
+        self.insert_before(parent, new, old);
+        self.remove_child(parent, old);
+    }
+
+    fn remove_child(&self, parent: &DomNode, child: &DomNode) {
+        let parent = memory(parent);
+        let connected = self.in_document(parent);
+        let index = parent.index_of(memory(child));
+        parent.0.borrow_mut().children.remove(index);
+        memory(child).0.borrow_mut().parent = Weak::new();
+
+        if connected {
+            self.notify(memory(child), |callbacks| &callbacks.disconnected);
+        }
+    }
+
+    fn clear(&self, node: &DomNode) {
+        for child in memory(node).children() {
+            self.take_out(&child);
+        }
+    }
+
+    fn inner_html(&self, node: &DomNode) -> String {
+        memory(node).inner_html()
+    }
+
+    fn is_connected(&self, node: &DomNode) -> bool {
+        self.in_document(memory(node))
+    }
+
+    /// There is no layout, every element is one pixel below the previous element sibling
+    fn position(&self, node: &DomNode) -> Option<(f64, f64)> {
+        let node = memory(node);
+        let parent = node.parent().filter(|_| self.in_document(node))?;
+        let top = parent
+            .children()
+            .iter()
+            .filter(|child| child.tag().is_some())
+            .position(|child| child.is_same(node))?;
+
+        Some((0.0, top as f64))
+    }
+
+    fn set_style(&self, node: &DomNode, property: &str, value: Option<&str>) {
+        let node = memory(node);
+        let mut declarations: Vec<(String, String)> = node
+            .attribute("style")
+            .unwrap_or_default()
+            .split(';')
+            .filter_map(|declaration| declaration.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .filter(|(name, _)| name != property)
+            .collect();
+
+        if let Some(value) = value {
+            declarations.push((property.to_string(), value.to_string()));
+        }
+
+        match declarations.is_empty() {
+            true => node.remove_attribute("style"),
+            false => {
+                let style: Vec<_> = declarations
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect();
+                node.set_attribute("style", &style.join("; "));
+            }
+        }
+
+        // same as in the browser, inline style is reflected in the attribute
+        self.attribute_changed(node, "style");
+    }
+
+    fn node_key(&self, node: &DomNode) -> usize {
+        // address can not be reused while somebody holds the node to ask for its key
+        Rc::as_ptr(&memory(node).0) as usize
+    }
+
+    fn add_listener(&self, node: &DomNode, event: &str, handler: &Handler) {
+        let mut inner = memory(node).0.borrow_mut();
+        let listeners = inner.listeners.entry(event.to_string()).or_default();
+
+        listeners.retain(|l| l.strong_count() > 0);
+        listeners.push(Rc::downgrade(&handler.listener()));
+    }
+
+    fn remove_listener(&self, node: &DomNode, event: &str, handler: &Handler) {
+        let listener = handler.listener();
+
+        if let Some(listeners) = memory(node).0.borrow_mut().listeners.get_mut(event) {
+            listeners.retain(|l| {
+                l.strong_count() > 0 && !std::ptr::addr_eq(l.as_ptr(), Rc::as_ptr(&listener))
+            });
+        }
+    }
+
+    fn request_frame(&self, callback: Box<dyn FnOnce()>) {
+        self.frames.borrow_mut().push(callback);
+    }
+
+    fn queue_microtask(&self, callback: Box<dyn FnOnce()>) {
+        self.microtasks.borrow_mut().push(callback);
+    }
+
+    fn set_timeout(&self, millis: i32, callback: Box<dyn FnOnce()>) {
+        let due = self.now.get() + millis.max(0) as u64;
+        self.timeouts.borrow_mut().push((due, callback));
+    }
+
+    fn spawn(&self, task: Task) {
+        // new task is polled on the next run
+        let waker = Arc::new(TaskWaker(AtomicBool::new(true)));
+        self.tasks.borrow_mut().push((task, waker));
+    }
+
+    fn subscribe(&self, subscription: &Subscription, handler: &Handler) {
+        let due = match subscription {
+            Subscription::Interval { millis, .. } => self.now.get() + (*millis).max(1) as u64,
+            _ => 0,
+        };
+
+        self.subscriptions
+            .borrow_mut()
+            .push((subscription.clone(), handler.listener(), due));
+    }
+
+    fn location(&self) -> String {
+        self.history.borrow()[self.current.get()].0.clone()
+    }
+
+    fn push_history(&self, url: &str, position: usize) {
+        self.add_entry(url, Some(position));
+    }
+
+    fn replace_history(&self, url: &str, position: usize) {
+        let url = self.resolve_url(url);
+        self.history.borrow_mut()[self.current.get()] = (url, Some(position));
+    }
+
+    fn history_position(&self) -> Option<usize> {
+        self.history.borrow()[self.current.get()].1
+    }
+
+    fn go(&self, delta: i32) {
+        let previous = self.location();
+        let current = self.current.get() as i64 + delta as i64;
+
+        if delta == 0 || current < 0 || current >= self.history.borrow().len() as i64 {
+            return;
+        }
+        self.current.set(current as usize);
+
+        self.dispatch_window(&Event::new("popstate"));
+
+        if hash(&previous) != hash(&self.location()) {
+            self.dispatch_window(&Event::new("hashchange"));
+        }
+    }
+
+    fn unsubscribe(&self, _subscription: &Subscription, handler: &Handler) {
+        let listener = handler.listener();
+
+        self.subscriptions
+            .borrow_mut()
+            .retain(|(_, l, _)| !Rc::ptr_eq(l, &listener));
+    }
+
+    fn define_element(&self, tag: &str, attributes: &[String], callbacks: ElementCallbacks) {
+        let defined = Rc::new(DefinedElement {
+            attributes: attributes.to_vec(),
+            callbacks,
+        });
+        self.elements
+            .borrow_mut()
+            .insert(tag.to_lowercase(), Rc::clone(&defined));
+
+        // elements that are already in the document get upgraded
+        let mut found = vec![];
+        self.defined_in(&self.body, &mut found);
+
+        for (element, _) in found {
+            if element.tag().as_deref() == Some(&tag.to_lowercase()) {
+                (defined.callbacks.connected)(&DomNode::Memory(element));
+            }
+        }
+    }
+
+    fn attach_shadow(&self, host: &DomNode) -> DomNode {
+        let host = memory(host);
+
+        if let Some(shadow) = host.shadow_root() {
+            return DomNode::Memory(shadow);
+        }
+
+        let shadow = MemoryNode::new(MemoryNodeData::Element {
+            tag: "#shadow-root".to_string(),
+            namespace: None,
+            attributes: BTreeMap::new(),
+        });
+        shadow.0.borrow_mut().parent = Rc::downgrade(&host.0);
+        host.0.borrow_mut().shadow = Some(shadow.clone());
+
+        DomNode::Memory(shadow)
+    }
+
+    fn emit(&self, node: &DomNode, event: &str, detail: &str) {
+        memory(node).dispatch_event(&Event::with_value(event, detail));
+    }
+
+    fn add_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str) {
+        let parent = root.map_or_else(|| self.head(), |root| memory(root).clone());
+        let exists = parent
+            .find(&|node| node.attribute("id").as_deref() == Some(id))
+            .is_some();
+
+        if exists {
+            return;
+        }
+
+        let style = self.create_element("style");
+        self.set_attribute(&style, "id", id);
+        self.append_child(&style, &self.create_text(css));
+        self.append_child(&DomNode::Memory(parent), &style);
+    }
+
+    fn adopt_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str) {
+        let root = root.map(|root| memory(root).clone());
+
+        if !self.adopted_by(root.as_ref()).iter().any(|(i, _)| i == id) {
+            self.adopted
+                .borrow_mut()
+                .push((root, id.to_string(), css.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(backend: &MemoryBackend, id: &str) -> MemoryNode {
+        match backend.get_element_by_id(id) {
+            Some(DomNode::Memory(node)) => node,
+            _ => panic!("could not find #{}", id),
+        }
+    }
+
+    #[test]
+    fn append_html_and_find_by_id() {
+        let backend = MemoryBackend::new();
+        let body = DomNode::Memory(backend.body());
+        backend.append_html(&body, "<div id=\"a\"><p id=\"b\">hi</p></div>");
+
+        assert_eq!(node(&backend, "b").text_content(), "hi");
+        assert_eq!(node(&backend, "b").parent().unwrap().tag().unwrap(), "div");
+        assert_eq!(
+            backend.body().inner_html(),
+            "<div id=\"a\"><p id=\"b\">hi</p></div>"
+        );
+    }
+
+    #[test]
+    fn insert_replace_and_remove() {
+        let backend = MemoryBackend::new();
+        let body = DomNode::Memory(backend.body());
+        let a = backend.create_text("a");
+        let b = backend.create_text("b");
+        let c = backend.create_text("c");
+
+        backend.append_child(&body, &c);
+        backend.insert_before(&body, &a, &c);
+        assert_eq!(backend.inner_html(&body), "ac");
+
+        backend.replace_child(&body, &b, &c);
+        assert_eq!(backend.inner_html(&body), "ab");
+
+        backend.remove_child(&body, &a);
+        assert_eq!(backend.inner_html(&body), "b");
+
+        backend.clear(&body);
+        assert_eq!(backend.inner_html(&body), "");
+    }
+
+    #[test]
+    fn inner_html_escapes_content() {
+        let backend = MemoryBackend::new();
+        let body = DomNode::Memory(backend.body());
+        let p = backend.create_element("p");
+        backend.set_attribute(&p, "title", "\"quoted\"");
+        backend.append_child(&p, &backend.create_text("a < b"));
+        backend.append_child(&body, &p);
+
+        assert_eq!(
+            backend.inner_html(&body),
+            "<p title=\"&quot;quoted&quot;\">a &lt; b</p>"
+        );
+    }
+
+    #[test]
+    fn dispatch_bubbles_to_ancestors() {
+        let backend = MemoryBackend::new();
+        let body = DomNode::Memory(backend.body());
+        backend.append_html(&body, "<div id=\"outer\"><b id=\"inner\"></b></div>");
+
+        let calls = Rc::new(RefCell::new(vec![]));
+        let inner_calls = Rc::clone(&calls);
+        let outer_calls = Rc::clone(&calls);
+        let inner = Handler::new("inner", move |_| inner_calls.borrow_mut().push("inner"));
+        let outer = Handler::new("outer", move |_| outer_calls.borrow_mut().push("outer"));
+
+        let inner_node = DomNode::Memory(node(&backend, "inner"));
+        let outer_node = DomNode::Memory(node(&backend, "outer"));
+        backend.add_listener(&inner_node, "click", &inner);
+        backend.add_listener(&outer_node, "click", &outer);
+
+        node(&backend, "inner").dispatch("click");
+        assert_eq!(*calls.borrow(), vec!["inner", "outer"]);
+
+        backend.remove_listener(&inner_node, "click", &inner);
+        node(&backend, "inner").dispatch("click");
+        assert_eq!(*calls.borrow(), vec!["inner", "outer", "outer"]);
+    }
+}
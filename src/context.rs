@@ -0,0 +1,354 @@
+/// This package lets components provide values to all of their nested components,
+/// e.g. theme, locale or current user, without passing them through every level
+use crate::framework::{Dirty, Priority};
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::{Rc, Weak};
+
+/// Prefix of template keys that are looked up in the context provided by ancestors,
+/// e.g. `:class="$context.theme"`
+pub const CONTEXT_PREFIX: &str = "$context.";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Type(TypeId),
+    Name(String),
+}
+
+/// Provided value, compared with the previous one to find out if consumers need to render
+trait Value {
+    fn as_any(&self) -> &dyn Any;
+    fn same(&self, other: &dyn Value) -> bool;
+    /// Text representation for templates, only named values have one
+    fn text(&self) -> Option<String>;
+}
+
+struct Typed<T>(T);
+
+impl<T: PartialEq + 'static> Value for Typed<T> {
+    fn as_any(&self) -> &dyn Any {
+        &self.0
+    }
+
+    fn same(&self, other: &dyn Value) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(&self.0)
+    }
+
+    fn text(&self) -> Option<String> {
+        None
+    }
+}
+
+struct Named<T>(T);
+
+impl<T: PartialEq + Display + 'static> Value for Named<T> {
+    fn as_any(&self) -> &dyn Any {
+        &self.0
+    }
+
+    fn same(&self, other: &dyn Value) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(&self.0)
+    }
+
+    fn text(&self) -> Option<String> {
+        Some(self.0.to_string())
+    }
+}
+
+struct Entry {
+    value: Box<dyn Value>,
+    consumers: Vec<Weak<RefCell<Dirty>>>,
+}
+
+/// Values provided by a single component runtime, linked to the runtime of its parent
+#[derive(Default)]
+pub struct ContextNode {
+    parent: Option<Rc<ContextNode>>,
+    entries: RefCell<HashMap<Key, Entry>>,
+}
+
+impl ContextNode {
+    /// Context of a top level component
+    pub fn root() -> Rc<Self> {
+        Rc::new(Self::default())
+    }
+
+    /// Context of a nested component, values not provided by it are resolved in the parent
+    pub fn child(parent: &Rc<ContextNode>) -> Rc<Self> {
+        Rc::new(ContextNode {
+            parent: Some(Rc::clone(parent)),
+            entries: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Store value and mark consumers dirty when it differs from the previous one
+    fn provide(&self, key: Key, value: Box<dyn Value>) {
+        let mut entries = self.entries.borrow_mut();
+
+        let consumers = match entries.get_mut(&key) {
+            Some(entry) if entry.value.same(&*value) => return,
+            Some(entry) => {
+                entry.value = value;
+                entry
+                    .consumers
+                    .retain(|consumer| consumer.strong_count() > 0);
+                entry.consumers.clone()
+            }
+            None => {
+                entries.insert(
+                    key,
+                    Entry {
+                        value,
+                        consumers: vec![],
+                    },
+                );
+                return;
+            }
+        };
+
+        drop(entries);
+
+        for consumer in consumers.iter().filter_map(Weak::upgrade) {
+            consumer.borrow_mut().mark(Priority::User);
+        }
+    }
+
+    /// Find closest ancestor that provides the key and remember who asked for it
+    fn resolve<R>(
+        &self,
+        key: &Key,
+        consumer: &Weak<RefCell<Dirty>>,
+        read: impl FnOnce(&dyn Value) -> R,
+    ) -> Option<R> {
+        let mut entries = self.entries.borrow_mut();
+
+        match entries.get_mut(key) {
+            Some(entry) => {
+                if !entry.consumers.iter().any(|c| c.ptr_eq(consumer)) {
+                    entry.consumers.push(Weak::clone(consumer));
+                }
+                Some(read(&*entry.value))
+            }
+            None => {
+                drop(entries);
+                self.parent.as_ref()?.resolve(key, consumer, read)
+            }
+        }
+    }
+}
+
+/// Passed to `Component::provide` before every render of the providing component
+pub struct Provider<'a> {
+    node: &'a ContextNode,
+}
+
+impl<'a> Provider<'a> {
+    pub fn new(node: &'a ContextNode) -> Self {
+        Provider { node }
+    }
+
+    /// Provide value keyed by its type
+    pub fn provide<T: PartialEq + 'static>(&mut self, value: T) {
+        self.node
+            .provide(Key::Type(TypeId::of::<T>()), Box::new(Typed(value)));
+    }
+
+    /// Provide value under a name, templates can read it with `$context.` prefix
+    pub fn provide_named<T: PartialEq + Display + 'static>(&mut self, name: &str, value: T) {
+        self.node
+            .provide(Key::Name(name.to_string()), Box::new(Named(value)));
+    }
+}
+
+/// Handle components get at construction to read values provided by their ancestors,
+/// it can be kept and used later, e.g. from `Lookup`, to always get current values
+#[derive(Clone)]
+pub struct Injector {
+    node: Rc<ContextNode>,
+    consumer: Weak<RefCell<Dirty>>,
+}
+
+impl Injector {
+    pub fn new(node: &Rc<ContextNode>, consumer: &Rc<RefCell<Dirty>>) -> Self {
+        Injector {
+            node: Rc::clone(node),
+            consumer: Rc::downgrade(consumer),
+        }
+    }
+
+    /// Value provided by the closest ancestor, the component is rendered again when it changes
+    pub fn inject<T: Clone + 'static>(&self) -> Option<T> {
+        let key = Key::Type(TypeId::of::<T>());
+        self.parent()?
+            .resolve(&key, &self.consumer, |value| {
+                value.as_any().downcast_ref::<T>().cloned()
+            })
+            .flatten()
+    }
+
+    pub fn inject_named<T: Clone + 'static>(&self, name: &str) -> Option<T> {
+        let key = Key::Name(name.to_string());
+        self.parent()?
+            .resolve(&key, &self.consumer, |value| {
+                value.as_any().downcast_ref::<T>().cloned()
+            })
+            .flatten()
+    }
+
+    /// Text of a named value, used by templates
+    pub fn lookup(&self, name: &str) -> Option<String> {
+        let key = Key::Name(name.to_string());
+        self.parent()?
+            .resolve(&key, &self.consumer, |value| value.text())
+            .flatten()
+    }
+
+    /// Components do not inject values they provide themselves
+    fn parent(&self) -> Option<&Rc<ContextNode>> {
+        self.node.parent.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::*;
+    use crate::testing::Harness;
+    use crate::vdom::SharableDomNode;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Theme(&'static str);
+
+    fn clean_dirty() -> Rc<RefCell<Dirty>> {
+        let dirty = Rc::new(RefCell::new(Dirty::new()));
+        dirty.borrow_mut().dirty = false;
+        dirty
+    }
+
+    #[test]
+    fn values_resolve_through_parent_chain() {
+        let root = ContextNode::root();
+        let middle = ContextNode::child(&root);
+        let leaf = ContextNode::child(&middle);
+
+        Provider::new(&root).provide(Theme("dark"));
+        Provider::new(&root).provide_named("locale", "en");
+        Provider::new(&middle).provide_named("locale", "de");
+
+        let injector = Injector::new(&leaf, &clean_dirty());
+        assert_eq!(injector.inject::<Theme>(), Some(Theme("dark")));
+        assert_eq!(injector.inject_named::<&str>("locale"), Some("de"));
+        assert_eq!(injector.lookup("locale").unwrap(), "de");
+        assert_eq!(injector.inject::<String>(), None);
+        assert_eq!(injector.lookup("user"), None);
+    }
+
+    #[test]
+    fn consumers_are_marked_when_value_changes() {
+        let root = ContextNode::root();
+        let leaf = ContextNode::child(&root);
+        let dirty = clean_dirty();
+
+        Provider::new(&root).provide(Theme("dark"));
+        Injector::new(&leaf, &dirty).inject::<Theme>();
+
+        Provider::new(&root).provide(Theme("dark"));
+        assert!(!dirty.borrow().dirty);
+
+        Provider::new(&root).provide(Theme("light"));
+        assert!(dirty.borrow().dirty);
+    }
+
+    #[test]
+    fn own_values_are_not_injected() {
+        let root = ContextNode::root();
+        Provider::new(&root).provide(Theme("dark"));
+
+        let injector = Injector::new(&root, &clean_dirty());
+        assert_eq!(injector.inject::<Theme>(), None);
+    }
+
+    /// Provides theme to nested components and switches it on click
+    struct App {
+        theme: &'static str,
+    }
+
+    impl Component for App {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            self.theme = "light";
+            true
+        }
+
+        fn provide(&self, provider: &mut Provider) {
+            provider.provide(Theme(self.theme));
+            provider.provide_named("theme", self.theme);
+        }
+    }
+
+    impl Lookup for App {
+        fn lookup(&self, _k: &String) -> Option<LookupValue> {
+            None
+        }
+    }
+
+    /// Reads the theme from the injector it got at construction
+    struct Panel {
+        injector: Injector,
+        initial: Theme,
+    }
+
+    impl Component for Panel {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            false
+        }
+    }
+
+    impl Lookup for Panel {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "initial" => Some(Box::new(self.initial.0)),
+                "current" => Some(Box::new(self.injector.inject::<Theme>()?.0)),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn nested_components_inject_provided_values() {
+        let mut harness = Harness::new();
+        harness.framework.register_component_template(
+            "app",
+            ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(App { theme: "dark" })))),
+            "<main><button @click=\"switch\"></button><section><panel></panel></section></main>",
+        );
+        harness.framework.register_component_template(
+            "panel",
+            ComponentWrapper::with_injector(Box::new(|injector: &Injector| {
+                Rc::new(RefCell::new(Panel {
+                    injector: injector.clone(),
+                    initial: injector.inject::<Theme>().expect("theme is not provided"),
+                }))
+            })),
+            "<p :class=\"$context.theme\" :data-initial=\"initial\" :title=\"current\"></p>",
+        );
+        harness.mount("app");
+
+        assert_eq!(harness.attribute("p", "class").unwrap(), "dark");
+        assert_eq!(harness.attribute("p", "title").unwrap(), "dark");
+
+        harness.click("button").settle();
+        assert_eq!(harness.attribute("p", "class").unwrap(), "light");
+        assert_eq!(harness.attribute("p", "title").unwrap(), "light");
+        assert_eq!(harness.attribute("p", "data-initial").unwrap(), "dark");
+    }
+}
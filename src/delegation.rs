@@ -0,0 +1,556 @@
+/// This package implements event delegation: instead of a listener on every element
+/// with `@event` binding there is one listener per event type on every mount point,
+/// events are routed to handlers through a map from nodes to handlers,
+/// events that do not bubble still get a listener on their element
+use crate::backend::{DomBackend, DomNode, Task};
+use crate::elements::ElementCallbacks;
+use crate::framework::Subscription;
+use crate::vdom::{Event, Handler, WeakListener};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Events that do not bubble, they never reach the root, so their handlers
+/// are attached to the elements themselves
+const NON_BUBBLING: &[&str] = &[
+    "focus",
+    "blur",
+    "mouseenter",
+    "mouseleave",
+    "pointerenter",
+    "pointerleave",
+    "load",
+    "error",
+    "scroll",
+    "play",
+    "pause",
+    "ended",
+];
+
+/// Map is swept for nodes whose handlers are all gone once it grows past this size
+const PRUNE_THRESHOLD: usize = 64;
+
+/// Handler registered for a node, handlers are owned by virtual nodes,
+/// so entries are forgotten once their virtual node is gone
+struct Delegated {
+    listener: WeakListener,
+    prevent_default: bool,
+}
+
+/// Backend that forwards everything to another backend, except for element listeners
+pub struct DelegatingBackend {
+    inner: Rc<dyn DomBackend>,
+    /// Node key to handlers of that node by event type
+    handlers: RefCell<HashMap<usize, HashMap<String, Vec<Delegated>>>>,
+    roots: RefCell<Vec<DomNode>>,
+    /// Every event type handlers were registered for, roots added later listen to them too
+    events: RefCell<Vec<String>>,
+    /// Listeners of bubbling events, one per root and event type
+    root_listeners: RefCell<Vec<(DomNode, String, Handler)>>,
+    /// Size of the handler map after it was last swept
+    pruned: Cell<usize>,
+    this: Weak<DelegatingBackend>,
+}
+
+impl DelegatingBackend {
+    pub fn new(inner: Rc<dyn DomBackend>) -> Rc<Self> {
+        Rc::new_cyclic(|this| DelegatingBackend {
+            inner,
+            handlers: RefCell::new(HashMap::new()),
+            roots: RefCell::new(vec![]),
+            events: RefCell::new(vec![]),
+            root_listeners: RefCell::new(vec![]),
+            pruned: Cell::new(0),
+            this: Weak::clone(this),
+        })
+    }
+
+    fn listen(&self, root: &DomNode, event: &str) {
+        let this = Weak::clone(&self.this);
+        let root_node = root.clone();
+        let handler = Handler::new(event, move |event: &Event| {
+            if let Some(this) = this.upgrade() {
+                this.dispatch(&root_node, event);
+            }
+        });
+
+        self.inner.add_listener(root, event, &handler);
+        self.root_listeners
+            .borrow_mut()
+            .push((root.clone(), event.to_string(), handler));
+    }
+
+    /// Make sure every root listens to given event type
+    fn ensure_listening(&self, event: &str) {
+        if !self.events.borrow().iter().any(|e| e == event) {
+            self.events.borrow_mut().push(event.to_string());
+        }
+
+        let roots = self.roots.borrow().clone();
+
+        for root in roots {
+            let listening = self
+                .root_listeners
+                .borrow()
+                .iter()
+                .any(|(r, e, _)| e == event && r.is_same(&root));
+
+            if !listening {
+                self.listen(&root, event);
+            }
+        }
+    }
+
+    /// Forget nodes whose handlers were all dropped together with their virtual nodes,
+    /// done only when the map doubled since the last time, so it stays cheap
+    fn prune(&self) {
+        let mut handlers = self.handlers.borrow_mut();
+        if handlers.len() < PRUNE_THRESHOLD.max(self.pruned.get() * 2) {
+            return;
+        }
+
+        handlers.retain(|_, by_event| {
+            by_event.retain(|_, delegated| {
+                delegated.retain(|d| d.listener.strong_count() > 0);
+                !delegated.is_empty()
+            });
+            !by_event.is_empty()
+        });
+        self.pruned.set(handlers.len());
+    }
+
+    /// Call handlers from the node event was fired on up to the root, same as bubbling does.
+    /// Nodes up to a root nested in this one, e.g. a portal target inside of the mount point,
+    /// are left to the listener of that root, it hears the event first
+    fn dispatch(&self, root: &DomNode, event: &Event) {
+        let mut current = event.target.as_ref().map(|target| target.0.clone());
+        let mut path = vec![];
+
+        while let Some(node) = current {
+            if node.is_same(root) {
+                path.push(node);
+                break;
+            }
+
+            let nested = self.roots.borrow().iter().any(|r| r.is_same(&node));
+            current = self.inner.parent(&node);
+            path.push(node);
+
+            if nested {
+                path.clear();
+            }
+        }
+
+        for node in path {
+            let key = self.inner.node_key(&node);
+
+            // release the map before calling handlers, they render and register new ones
+            let listeners: Vec<_> = {
+                let mut handlers = self.handlers.borrow_mut();
+                match handlers.get_mut(&key).and_then(|h| h.get_mut(&event.kind)) {
+                    Some(delegated) => {
+                        delegated.retain(|d| d.listener.strong_count() > 0);
+                        delegated
+                            .iter()
+                            .filter_map(|d| d.listener.upgrade().map(|l| (l, d.prevent_default)))
+                            .collect()
+                    }
+                    None => vec![],
+                }
+            };
+
+            for (listener, prevent_default) in listeners {
+                if prevent_default {
+                    event.prevent_default();
+                }
+                listener(event);
+            }
+        }
+    }
+}
+
+impl DomBackend for DelegatingBackend {
+    fn create_element(&self, tag: &str) -> DomNode {
+        self.inner.create_element(tag)
+    }
+
+    fn create_element_ns(&self, namespace: &str, tag: &str) -> DomNode {
+        self.inner.create_element_ns(namespace, tag)
+    }
+
+    fn create_text(&self, content: &str) -> DomNode {
+        self.inner.create_text(content)
+    }
+
+    fn get_element_by_id(&self, id: &str) -> Option<DomNode> {
+        self.inner.get_element_by_id(id)
+    }
+
+    fn query_selector(&self, selector: &str) -> Option<DomNode> {
+        self.inner.query_selector(selector)
+    }
+
+    fn tag(&self, node: &DomNode) -> Option<String> {
+        self.inner.tag(node)
+    }
+
+    fn text(&self, node: &DomNode) -> Option<String> {
+        self.inner.text(node)
+    }
+
+    fn set_text(&self, node: &DomNode, content: &str) {
+        self.inner.set_text(node, content)
+    }
+
+    fn attribute(&self, node: &DomNode, name: &str) -> Option<String> {
+        self.inner.attribute(node, name)
+    }
+
+    fn attribute_names(&self, node: &DomNode) -> Vec<String> {
+        self.inner.attribute_names(node)
+    }
+
+    fn set_attribute(&self, node: &DomNode, name: &str, value: &str) {
+        self.inner.set_attribute(node, name, value)
+    }
+
+    fn remove_attribute(&self, node: &DomNode, name: &str) {
+        self.inner.remove_attribute(node, name)
+    }
+
+    fn children(&self, node: &DomNode) -> Vec<DomNode> {
+        self.inner.children(node)
+    }
+
+    fn parent(&self, node: &DomNode) -> Option<DomNode> {
+        self.inner.parent(node)
+    }
+
+    fn append_child(&self, parent: &DomNode, child: &DomNode) {
+        self.inner.append_child(parent, child)
+    }
+
+    fn insert_before(&self, parent: &DomNode, child: &DomNode, reference: &DomNode) {
+        self.inner.insert_before(parent, child, reference)
+    }
+
+    fn replace_child(&self, parent: &DomNode, new: &DomNode, old: &DomNode) {
+        self.inner.replace_child(parent, new, old)
+    }
+
+    fn remove_child(&self, parent: &DomNode, child: &DomNode) {
+        self.inner.remove_child(parent, child)
+    }
+
+    fn clear(&self, node: &DomNode) {
+        self.inner.clear(node)
+    }
+
+    fn inner_html(&self, node: &DomNode) -> String {
+        self.inner.inner_html(node)
+    }
+
+    fn is_connected(&self, node: &DomNode) -> bool {
+        self.inner.is_connected(node)
+    }
+
+    fn position(&self, node: &DomNode) -> Option<(f64, f64)> {
+        self.inner.position(node)
+    }
+
+    fn set_style(&self, node: &DomNode, property: &str, value: Option<&str>) {
+        self.inner.set_style(node, property, value)
+    }
+
+    fn node_key(&self, node: &DomNode) -> usize {
+        self.inner.node_key(node)
+    }
+
+    /// Remember handler instead of attaching it, no js closure is created for it
+    fn add_listener(&self, node: &DomNode, event: &str, handler: &Handler) {
+        if NON_BUBBLING.contains(&event) {
+            return self.inner.add_listener(node, event, handler);
+        }
+
+        self.prune();
+        let key = self.inner.node_key(node);
+
+        self.handlers
+            .borrow_mut()
+            .entry(key)
+            .or_default()
+            .entry(event.to_string())
+            .or_default()
+            .push(Delegated {
+                listener: Rc::downgrade(&handler.listener()),
+                prevent_default: handler.prevents_default(),
+            });
+
+        self.ensure_listening(event);
+    }
+
+    fn remove_listener(&self, node: &DomNode, event: &str, handler: &Handler) {
+        if NON_BUBBLING.contains(&event) {
+            return self.inner.remove_listener(node, event, handler);
+        }
+
+        let key = self.inner.node_key(node);
+        let listener = handler.listener();
+        let mut handlers = self.handlers.borrow_mut();
+
+        if let Some(by_event) = handlers.get_mut(&key) {
+            if let Some(delegated) = by_event.get_mut(event) {
+                delegated.retain(|d| {
+                    d.listener.strong_count() > 0
+                        && !std::ptr::addr_eq(d.listener.as_ptr(), Rc::as_ptr(&listener))
+                });
+            }
+
+            by_event.retain(|_, delegated| !delegated.is_empty());
+            if by_event.is_empty() {
+                handlers.remove(&key);
+            }
+        }
+    }
+
+    /// Events that bubble up to the root get delegated to handlers of nodes they passed
+    fn add_root(&self, root: &DomNode) {
+        if self.roots.borrow().iter().any(|r| r.is_same(root)) {
+            return;
+        }
+
+        self.roots.borrow_mut().push(root.clone());
+
+        let events = self.events.borrow().clone();
+        for event in events {
+            self.listen(root, &event);
+        }
+    }
+
+    fn remove_root(&self, root: &DomNode) {
+        self.roots.borrow_mut().retain(|r| !r.is_same(root));
+
+        let removed: Vec<_> = {
+            let mut listeners = self.root_listeners.borrow_mut();
+            let (removed, kept) = listeners.drain(..).partition(|(r, _, _)| r.is_same(root));
+            *listeners = kept;
+            removed
+        };
+
+        for (root, event, handler) in removed {
+            self.inner.remove_listener(&root, &event, &handler);
+        }
+    }
+
+    fn request_frame(&self, callback: Box<dyn FnOnce()>) {
+        self.inner.request_frame(callback)
+    }
+
+    fn queue_microtask(&self, callback: Box<dyn FnOnce()>) {
+        self.inner.queue_microtask(callback)
+    }
+
+    fn set_timeout(&self, millis: i32, callback: Box<dyn FnOnce()>) {
+        self.inner.set_timeout(millis, callback)
+    }
+
+    fn spawn(&self, task: Task) {
+        self.inner.spawn(task)
+    }
+
+    fn subscribe(&self, subscription: &Subscription, handler: &Handler) {
+        self.inner.subscribe(subscription, handler)
+    }
+
+    fn unsubscribe(&self, subscription: &Subscription, handler: &Handler) {
+        self.inner.unsubscribe(subscription, handler)
+    }
+
+    fn location(&self) -> String {
+        self.inner.location()
+    }
+
+    fn push_history(&self, url: &str, position: usize) {
+        self.inner.push_history(url, position)
+    }
+
+    fn replace_history(&self, url: &str, position: usize) {
+        self.inner.replace_history(url, position)
+    }
+
+    fn history_position(&self) -> Option<usize> {
+        self.inner.history_position()
+    }
+
+    fn go(&self, delta: i32) {
+        self.inner.go(delta)
+    }
+
+    fn define_element(&self, tag: &str, attributes: &[String], callbacks: ElementCallbacks) {
+        self.inner.define_element(tag, attributes, callbacks)
+    }
+
+    fn attach_shadow(&self, host: &DomNode) -> DomNode {
+        self.inner.attach_shadow(host)
+    }
+
+    fn emit(&self, node: &DomNode, event: &str, detail: &str) {
+        self.inner.emit(node, event, detail)
+    }
+
+    fn add_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str) {
+        self.inner.add_stylesheet(root, id, css)
+    }
+
+    fn adopt_stylesheet(&self, root: Option<&DomNode>, id: &str, css: &str) {
+        self.inner.adopt_stylesheet(root, id, css)
+    }
+}
+
+impl Drop for DelegatingBackend {
+    fn drop(&mut self) {
+        for (root, event, handler) in self.root_listeners.borrow().iter() {
+            self.inner.remove_listener(root, event, handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::framework::*;
+    use crate::testing::Harness;
+    use crate::vdom::SharableDomNode;
+
+    struct Counter {
+        count: i32,
+    }
+
+    impl Component for Counter {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, message: String) -> bool {
+            match message.as_ref() {
+                "increment" => self.count += 1,
+                "decrement" => self.count -= 1,
+                _ => return false,
+            }
+            true
+        }
+    }
+
+    impl Lookup for Counter {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "count" => Some(Box::new(self.count)),
+                _ => None,
+            }
+        }
+    }
+
+    fn harness() -> Harness {
+        let mut harness = Harness::new();
+        harness.framework.use_event_delegation();
+        harness.framework.register_component_template(
+            "counter",
+            ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Counter { count: 0 })))),
+            "<p :title=\"count\"></p>\
+             <button id=\"increment\" @click=\"increment\"><span>+</span></button>\
+             <button id=\"decrement\" @click=\"decrement\">-</button>\
+             <input @input=\"ignored\" @focus=\"increment\">",
+        );
+        harness.mount("counter");
+        harness
+    }
+
+    #[test]
+    fn listeners_are_attached_only_to_mount_point() {
+        let harness = harness();
+
+        assert_eq!(
+            harness.find("#increment").unwrap().listener_count("click"),
+            0
+        );
+        assert_eq!(
+            harness.find("#decrement").unwrap().listener_count("click"),
+            0
+        );
+        assert_eq!(harness.find("input").unwrap().listener_count("input"), 0);
+
+        let app = harness.find("#app").unwrap();
+        assert_eq!(app.listener_count("click"), 1);
+        assert_eq!(app.listener_count("input"), 1);
+    }
+
+    #[test]
+    fn events_reach_handlers_of_nodes_they_bubble_through() {
+        let mut harness = harness();
+
+        harness.click("#increment").settle();
+        harness.click("#increment span").settle();
+        harness.click("#decrement").settle();
+        assert_eq!(harness.attribute("p", "title").unwrap(), "1");
+
+        // clicks outside of handled elements are ignored
+        harness.click("p").settle();
+        assert_eq!(harness.attribute("p", "title").unwrap(), "1");
+        assert_eq!(harness.find("#app").unwrap().listener_count("click"), 1);
+    }
+
+    #[test]
+    fn non_bubbling_events_are_attached_to_elements() {
+        let mut harness = harness();
+
+        assert_eq!(harness.find("input").unwrap().listener_count("focus"), 1);
+        assert_eq!(harness.find("#app").unwrap().listener_count("focus"), 0);
+
+        harness.dispatch("input", Event::new("focus")).settle();
+        assert_eq!(harness.attribute("p", "title").unwrap(), "1");
+    }
+
+    #[test]
+    fn portal_targets_inside_of_mount_point_call_handlers_once() {
+        let mut harness = Harness::new();
+        harness.framework.use_event_delegation();
+        harness.framework.register_component_template(
+            "counter",
+            ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Counter { count: 0 })))),
+            "<p :title=\"count\"></p>\
+             <section @click=\"increment\"><div id=\"modals\"></div></section>\
+             <portal to=\"#modals\"><button @click=\"increment\">+</button></portal>",
+        );
+        harness.mount("counter");
+
+        // the button and the section it ended up in handle the click once each
+        harness.click("#modals button").settle();
+        assert_eq!(harness.attribute("p", "title").unwrap(), "2");
+    }
+
+    #[test]
+    fn unmount_removes_root_listeners() {
+        let mut harness = harness();
+        harness.framework.unmount("app").unwrap();
+
+        let app = harness.find("#app").unwrap();
+        assert_eq!(app.listener_count("click"), 0);
+        assert_eq!(app.listener_count("input"), 0);
+    }
+
+    #[test]
+    fn handlers_of_dropped_nodes_are_pruned() {
+        let backend = DelegatingBackend::new(Rc::new(MemoryBackend::new()));
+        let mut nodes = vec![];
+
+        for _ in 0..200 {
+            let node = backend.create_element("b");
+            let handler = Handler::new("click", |_: &Event| {});
+            backend.add_listener(&node, "click", &handler);
+            nodes.push(node);
+        }
+
+        assert!(backend.handlers.borrow().len() <= PRUNE_THRESHOLD);
+    }
+}
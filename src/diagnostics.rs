@@ -0,0 +1,59 @@
+/// This package holds problems template checks find, `analysis` checks bindings against
+/// what the component declares and `a11y` checks elements for accessibility
+use crate::templating::{Attribute, Attributes};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// `:binding` or `{{ interpolation }}` of a key that is not in `ComponentWrapper::keys`
+    UnknownKey { key: String },
+    /// `@handler` with a message that is not in `ComponentWrapper::messages`
+    UnknownMessage { message: String },
+    /// `<img>` without `alt`, use `alt=""` for decorative images
+    MissingAlt,
+    /// Element that can not get focus reacts to clicks, so it can not be used with keyboard
+    ClickWithoutRole { tag: String },
+    /// Form control with no `<label>`, `aria-label` nor `aria-labelledby`
+    MissingLabel { tag: String },
+    /// `aria-*` attribute that is not in `a11y::ARIA_ATTRIBUTES`, e.g. misspelled one
+    InvalidAria { name: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::UnknownKey { key } => write!(f, "component has no key {}", key),
+            Problem::UnknownMessage { message } => {
+                write!(f, "component does not handle message {}", message)
+            }
+            Problem::MissingAlt => write!(f, "<img> has no alt text"),
+            Problem::ClickWithoutRole { tag } => {
+                write!(f, "<{}> handles clicks but has no role or tabindex", tag)
+            }
+            Problem::MissingLabel { tag } => write!(f, "<{}> has no label", tag),
+            Problem::InvalidAria { name } => write!(f, "{} is not an aria attribute", name),
+        }
+    }
+}
+
+/// Problem found in a template together with the path of the node that has it,
+/// see `visit_template`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub path: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// Attributes of an element ordered by name, they are kept in a map,
+/// but diagnostics should not change order between runs
+pub fn sorted_attributes(attributes: &Attributes) -> Vec<(&String, &Attribute)> {
+    let mut attributes: Vec<_> = attributes.iter().collect();
+    attributes.sort_by_key(|(name, _)| *name);
+    attributes
+}
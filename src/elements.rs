@@ -0,0 +1,256 @@
+/// This package lets registered components be used as custom elements from plain html,
+/// e.g. `<my-counter count="5"></my-counter>` on a page that knows nothing about the framework
+use crate::backend::DomNode;
+use wasm_bindgen::prelude::*;
+
+/// How a registered component is exported as a custom element
+#[derive(Debug, Clone, Default)]
+pub struct ElementOptions {
+    attributes: Vec<String>,
+    shadow: bool,
+}
+
+impl ElementOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attribute passed to the component with `Component::set_property` whenever it changes
+    pub fn attribute(mut self, name: &str) -> Self {
+        self.attributes.push(name.to_string());
+        self
+    }
+
+    /// Render in to an open shadow root, so page styles do not leak in to the component
+    pub fn shadow(mut self) -> Self {
+        self.shadow = true;
+        self
+    }
+
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    pub fn uses_shadow(&self) -> bool {
+        self.shadow
+    }
+}
+
+pub type LifecycleCallback = Box<dyn Fn(&DomNode)>;
+pub type AttributeCallback = Box<dyn Fn(&DomNode, &str, Option<String>)>;
+
+/// Lifecycle of every instance of a custom element, called by the backend
+pub struct ElementCallbacks {
+    /// Element got in to the document
+    pub connected: LifecycleCallback,
+    /// Element got removed from the document
+    pub disconnected: LifecycleCallback,
+    /// Observed attribute changed, value is `None` when attribute got removed
+    pub attribute_changed: AttributeCallback,
+}
+
+#[wasm_bindgen(inline_js = "
+export function define_element(tag, attributes, connected, disconnected, changed) {
+    customElements.define(tag, class extends HTMLElement {
+        static get observedAttributes() { return attributes; }
+        connectedCallback() { connected(this); }
+        disconnectedCallback() { disconnected(this); }
+        attributeChangedCallback(name, old, value) { changed(this, name, value); }
+    });
+}
+")]
+extern "C" {
+    /// `customElements.define` with a class that forwards lifecycle callbacks to closures
+    pub(crate) fn define_element(
+        tag: &str,
+        attributes: js_sys::Array,
+        connected: &JsValue,
+        disconnected: &JsValue,
+        changed: &JsValue,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::DomBackend;
+    use crate::framework::*;
+    use crate::testing::Harness;
+    use crate::vdom::{Event, Handler, SharableDomNode};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Counter that starts from `count` attribute and reports every change
+    struct Counter {
+        count: i32,
+        _alive: Rc<()>,
+    }
+
+    impl Component for Counter {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            self.count += 1;
+            true
+        }
+
+        fn update(&mut self, message: String, event: &Event, commands: &mut Commands) -> bool {
+            let dirty = self.handle_event(message, event);
+            commands.emit("change", &self.count.to_string());
+            dirty
+        }
+
+        fn set_property(&mut self, name: &str, value: Option<&str>) -> bool {
+            match name {
+                "count" => {
+                    self.count = value.and_then(|v| v.parse().ok()).unwrap_or(0);
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    impl Lookup for Counter {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "count" => Some(Box::new(self.count)),
+                _ => None,
+            }
+        }
+    }
+
+    fn harness(page: &str, options: ElementOptions) -> (Harness, Rc<()>) {
+        let alive = Rc::new(());
+        let instances = Rc::clone(&alive);
+
+        let mut harness = Harness::with_page(page);
+        harness.framework.register_component_template(
+            "counter",
+            ComponentWrapper::new(Box::new(move || {
+                Rc::new(RefCell::new(Counter {
+                    count: 0,
+                    _alive: Rc::clone(&instances),
+                }))
+            })),
+            "<button @click=\"increment\" :title=\"count\"></button>",
+        );
+        harness
+            .framework
+            .define_element("my-counter", "counter", options);
+        harness.settle();
+
+        (harness, alive)
+    }
+
+    fn host(harness: &Harness) -> DomNode {
+        DomNode::Memory(harness.find("my-counter").expect("no <my-counter>"))
+    }
+
+    #[test]
+    fn elements_in_the_page_render_with_their_attributes() {
+        let (mut harness, _) = harness(
+            "<my-counter count=\"5\"></my-counter>",
+            ElementOptions::new().attribute("count"),
+        );
+        assert_eq!(harness.attribute("button", "title").unwrap(), "5");
+
+        harness
+            .backend()
+            .set_attribute(&host(&harness), "count", "7");
+        harness.settle();
+        assert_eq!(harness.attribute("button", "title").unwrap(), "7");
+
+        harness.backend().remove_attribute(&host(&harness), "count");
+        harness.settle();
+        assert_eq!(harness.attribute("button", "title").unwrap(), "0");
+    }
+
+    #[test]
+    fn emitted_events_are_fired_on_the_element() {
+        let (mut harness, _) = harness("<my-counter></my-counter>", ElementOptions::new());
+
+        let details = Rc::new(RefCell::new(vec![]));
+        let received = Rc::clone(&details);
+        let listener = Handler::new("change", move |event: &Event| {
+            received
+                .borrow_mut()
+                .push(event.value.clone().unwrap_or_default())
+        });
+        let body = DomNode::Memory(harness.backend().body());
+        harness.backend().add_listener(&body, "change", &listener);
+
+        harness.click("button").settle();
+        harness.click("button").settle();
+        assert_eq!(*details.borrow(), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn elements_added_later_get_their_own_instance() {
+        let (mut harness, alive) = harness("<div id=\"widgets\"></div>", ElementOptions::new());
+        let widgets = DomNode::Memory(harness.find("#widgets").unwrap());
+
+        harness.backend().append_html(
+            &widgets,
+            "<my-counter id=\"a\"></my-counter><my-counter id=\"b\"></my-counter>",
+        );
+        harness.settle();
+        // one more is held by the constructor
+        assert_eq!(Rc::strong_count(&alive), 4);
+
+        harness.click("#a button").settle();
+        assert_eq!(harness.attribute("#a button", "title").unwrap(), "1");
+        assert_eq!(harness.attribute("#b button", "title").unwrap(), "0");
+
+        let a = DomNode::Memory(harness.find("#a").unwrap());
+        harness.backend().remove_child(&widgets, &a);
+        harness.settle();
+        assert_eq!(Rc::strong_count(&alive), 3);
+    }
+
+    #[test]
+    fn moved_elements_keep_their_state() {
+        let (mut harness, alive) = harness(
+            "<div id=\"from\"><my-counter></my-counter></div><div id=\"to\"></div>",
+            ElementOptions::new(),
+        );
+        harness.click("button").settle();
+        assert_eq!(Rc::strong_count(&alive), 3);
+
+        let to = DomNode::Memory(harness.find("#to").unwrap());
+        harness.backend().append_child(&to, &host(&harness));
+        harness.settle();
+        assert_eq!(Rc::strong_count(&alive), 3);
+        assert_eq!(harness.attribute("#to button", "title").unwrap(), "1");
+
+        harness.click("#to button").settle();
+        assert_eq!(harness.attribute("#to button", "title").unwrap(), "2");
+    }
+
+    #[test]
+    fn removed_elements_are_emptied() {
+        let (mut harness, alive) = harness("<my-counter></my-counter>", ElementOptions::new());
+        let host = host(&harness);
+
+        let body = DomNode::Memory(harness.backend().body());
+        harness.backend().remove_child(&body, &host);
+        harness.settle();
+        assert_eq!(Rc::strong_count(&alive), 2);
+        assert_eq!(harness.backend().inner_html(&host), "");
+    }
+
+    #[test]
+    fn shadow_root_keeps_rendered_content_out_of_the_page() {
+        let (harness, _) = harness(
+            "<my-counter count=\"3\"></my-counter>",
+            ElementOptions::new().attribute("count").shadow(),
+        );
+
+        assert_eq!(harness.html(), "<my-counter count=\"3\"></my-counter>");
+
+        let shadow = harness.find("my-counter").unwrap().shadow_root().unwrap();
+        assert_eq!(shadow.inner_html(), "<button title=\"3\"></button>");
+    }
+}
@@ -1,162 +1,2278 @@
 /// Framework public API surface
+use crate::a11y;
+use crate::analysis::check_bindings;
+use crate::backend::{DomBackend, DomNode, WebBackend};
+use crate::context::{ContextNode, Injector, Provider};
+use crate::delegation::DelegatingBackend;
+use crate::diagnostics::Diagnostic;
+use crate::elements::{ElementCallbacks, ElementOptions};
 use crate::html::*;
+use crate::router::Router;
+use crate::store::SharedStore;
+use crate::styles::{extract_scoped_style, ScopedStyle};
 use crate::templating::*;
-use crate::vdom::{SharableDomNode, VDom};
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fmt::Display;
+use crate::vdom::{
+    apply_changes, diff_children, hydrate_children, Event, Handler, SharableDomNode, VDom, VNode,
+    Violation,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::future::Future;
 use std::io;
-use std::rc::Rc;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
 use wasm_bindgen::prelude::*;
 
 pub type ComponentInstance = Rc<RefCell<dyn Component>>;
 pub type ComponentConstructor = Box<dyn Fn() -> ComponentInstance>;
+/// Constructor that reads values provided by ancestor components
+pub type InjectingConstructor = Box<dyn Fn(&Injector) -> ComponentInstance>;
+
+/// Problem the framework recovered from, handed to the handler set with `Framework::on_error`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameworkError {
+    /// Value bound to an attribute was not rendered, because it could run a script
+    UnsafeAttribute { tag: String, violation: Violation },
+    /// Template of a component registered by a lazy route has bindings the component
+    /// does not declare, components registered up front get these from registration instead
+    UndeclaredBinding {
+        component: &'static str,
+        diagnostic: Diagnostic,
+    },
+    /// Nothing in the page matches `to` of a `<portal>`, its content was not rendered
+    MissingPortalTarget { to: String },
+    /// Key of a `{{ key }}` interpolation was not found, it was rendered empty
+    UnknownKey { key: String },
+}
+
+impl fmt::Display for FrameworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameworkError::UnsafeAttribute { tag, violation } => {
+                write!(f, "<{}> attribute was not rendered: {}", tag, violation)
+            }
+            FrameworkError::UndeclaredBinding {
+                component,
+                diagnostic,
+            } => write!(f, "template of {}: {}", component, diagnostic),
+            FrameworkError::MissingPortalTarget { to } => {
+                write!(f, "<portal> was not rendered: could not find target {}", to)
+            }
+            FrameworkError::UnknownKey { key } => {
+                write!(
+                    f,
+                    "{{{{ {} }}}} was not rendered: could not find the key",
+                    key
+                )
+            }
+        }
+    }
+}
+
+pub type ErrorHandler = Rc<dyn Fn(&FrameworkError)>;
 
 // ************** Trait that enforces component specific methods **************
 pub type LookupValue = Box<dyn Display>;
 
 pub trait Lookup {
+    // kept as `&String`, every component out there implements it
+    #[allow(clippy::ptr_arg)]
     fn lookup(&self, k: &String) -> Option<LookupValue>;
+
+    /// Markup for `:html` bindings, keys that are not listed here can not be bound as html
+    fn lookup_html(&self, _k: &String) -> Option<TrustedHtml> {
+        None
+    }
+
+    /// Items of a list for `<for collection="...">`, every item is looked up the same way
+    /// a component is, e.g. `item.title`
+    fn lookup_list(&self, _k: &String) -> Option<Vec<Rc<dyn Lookup>>> {
+        None
+    }
 }
 
 pub trait Component: Lookup {
     fn render(&self) -> Vec<SharableDomNode>;
     fn handle(&mut self, message: String) -> bool;
+
+    /// Handle message triggered by a dom event, override to get access to event details
+    fn handle_event(&mut self, message: String, _event: &Event) -> bool {
+        self.handle(message)
+    }
+
+    /// Handle message and optionally start commands, override to run async work like fetch,
+    /// message produced by each command is sent back to this component once it is ready
+    fn update(&mut self, message: String, event: &Event, _commands: &mut Commands) -> bool {
+        self.handle_event(message, event)
+    }
+
+    /// Sources of messages outside of the template, asked for again after every render,
+    /// subscriptions that are gone from the list get torn down
+    fn subscriptions(&self) -> Vec<Subscription> {
+        vec![]
+    }
+
+    /// Values this component makes available to nested components, called before every render
+    fn provide(&self, _provider: &mut Provider) {}
+
+    /// Observed attribute of the custom element this component is exported as,
+    /// value is `None` when attribute got removed, returns whether component should render
+    fn set_property(&mut self, _name: &str, _value: Option<&str>) -> bool {
+        false
+    }
+}
+
+// ************** Subscriptions **************
+
+/// Declarative description of events component wants to receive as messages
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subscription {
+    /// Message sent every `millis` milliseconds
+    Interval { millis: i32, message: String },
+    /// Event fired on the window, e.g. `resize` or `hashchange`
+    Window { event: String, message: String },
+    /// Event fired on the document, e.g. `keydown` or `visibilitychange`
+    Document { event: String, message: String },
+}
+
+impl Subscription {
+    pub fn interval(millis: i32, message: &str) -> Self {
+        Subscription::Interval {
+            millis,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn window(event: &str, message: &str) -> Self {
+        Subscription::Window {
+            event: event.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn document(event: &str, message: &str) -> Self {
+        Subscription::Document {
+            event: event.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Subscription::Interval { message, .. }
+            | Subscription::Window { message, .. }
+            | Subscription::Document { message, .. } => message,
+        }
+    }
+
+    /// Timers are rendered with the next frame, everything else is most likely user input
+    fn priority(&self) -> Priority {
+        match self {
+            Subscription::Interval { .. } => Priority::Background,
+            _ => Priority::User,
+        }
+    }
+}
+
+/// Content of a `<portal>` rendered in to its target element as a fragment,
+/// so it keeps its place next to other content of the target.
+/// Nodes are removed from the target when it is dropped
+struct MountedPortal {
+    to: String,
+    target: DomNode,
+    content: VNode,
+    backend: Rc<dyn DomBackend>,
+}
+
+impl MountedPortal {
+    fn mount(to: String, target: DomNode, vdom: VDom, backend: &Rc<dyn DomBackend>) -> Self {
+        backend.add_root(&target);
+
+        let content = VNode::fragment(vdom);
+        for node in content.create(&**backend) {
+            backend.append_child(&target, &node);
+        }
+
+        MountedPortal {
+            to,
+            target,
+            content,
+            backend: Rc::clone(backend),
+        }
+    }
+
+    fn patch(&mut self, vdom: VDom) {
+        let content = VNode::fragment(vdom);
+
+        apply_changes(self.content.diff(&content, &self.target), &self.backend);
+
+        self.content = content;
+    }
+}
+
+impl Drop for MountedPortal {
+    fn drop(&mut self) {
+        for node in self.content.nodes() {
+            self.backend.remove_child(&self.target, &node);
+        }
+    }
+}
+
+/// Subscription registered with the backend, it is torn down when dropped
+/// together with the runtime that owns it
+struct ActiveSubscription {
+    subscription: Subscription,
+    handler: Handler,
+    backend: Rc<dyn DomBackend>,
+}
+
+impl Drop for ActiveSubscription {
+    fn drop(&mut self) {
+        self.backend.unsubscribe(&self.subscription, &self.handler);
+    }
+}
+
+// ************** Commands **************
+
+/// Async work started by a component, resolves to a message for the same component
+pub type Command = Pin<Box<dyn Future<Output = String>>>;
+
+/// Commands collected while component handles a single message
+#[derive(Default)]
+pub struct Commands {
+    queue: Vec<Command>,
+    events: Vec<(String, String)>,
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run future in the background and send its output to the component as a message
+    pub fn perform(&mut self, future: impl Future<Output = String> + 'static) {
+        self.queue.push(Box::pin(future));
+    }
+
+    /// Fire `CustomEvent` with given detail on the custom element this component is exported as
+    pub fn emit(&mut self, event: &str, detail: &str) {
+        self.events.push((event.to_string(), detail.to_string()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.events.is_empty()
+    }
+}
+
+/// Event passed to the component together with a message produced by a command
+pub const COMMAND_EVENT: &str = "command";
+
+/// Command shared by the task that runs it and the component that started it,
+/// component takes it out when it goes away, so everything it captured is freed right away,
+/// even if the command would never wake up again
+struct CommandSlot {
+    command: RefCell<Option<Command>>,
+    waker: RefCell<Option<Waker>>,
+}
+
+impl CommandSlot {
+    fn cancel(&self) {
+        // slot is busy only while command is being polled, it is finished off by the task then
+        if let Ok(mut command) = self.command.try_borrow_mut() {
+            command.take();
+        }
+
+        if let Some(waker) = self.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Task side of a command slot, done as soon as the slot is empty
+struct Guarded {
+    slot: Rc<CommandSlot>,
+    alive: Weak<RefCell<Dirty>>,
+}
+
+impl Future for Guarded {
+    type Output = Option<String>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut command = self.slot.command.borrow_mut();
+
+        let poll = match command.as_mut() {
+            Some(_) if self.alive.strong_count() == 0 => Poll::Ready(None),
+            Some(running) => running.as_mut().poll(cx).map(Some),
+            None => Poll::Ready(None),
+        };
+
+        match poll {
+            Poll::Ready(_) => *command = None,
+            Poll::Pending => *self.slot.waker.borrow_mut() = Some(cx.waker().clone()),
+        }
+
+        poll
+    }
+}
+
+/// Send message to a component, mark it dirty if needed and start commands it returned,
+/// only weak references are kept, so messages to unmounted components are dropped
+pub fn dispatch(
+    component: &Weak<RefCell<dyn Component>>,
+    dirty: &Weak<RefCell<Dirty>>,
+    message: String,
+    event: &Event,
+    priority: Priority,
+) {
+    let (component_rc, dirty_rc) = match (component.upgrade(), dirty.upgrade()) {
+        (Some(component), Some(dirty)) => (component, dirty),
+        _ => {
+            log!("Could not get instance of commponent, might be freed");
+            return;
+        }
+    };
+
+    let mut commands = Commands::new();
+    let is_dirty = component_rc
+        .borrow_mut()
+        .update(message, event, &mut commands);
+
+    if is_dirty {
+        dirty_rc.borrow_mut().mark(priority);
+    }
+
+    let scheduler = dirty_rc.borrow().scheduler.as_ref().and_then(Weak::upgrade);
+    let host = dirty_rc.borrow().host.clone();
+
+    match (scheduler, host) {
+        (Some(scheduler), host) => {
+            for command in commands.queue {
+                scheduler.spawn(command, Weak::clone(component), Weak::clone(dirty));
+            }
+
+            for (event, detail) in commands.events {
+                match &host {
+                    Some(host) => scheduler.backend.emit(host, &event, &detail),
+                    None => log!(
+                        "Component is not a custom element, dropping {} event",
+                        event
+                    ),
+                }
+            }
+        }
+        (None, _) if !commands.is_empty() => {
+            log!("Component is not mounted, dropping its commands")
+        }
+        (None, _) => {}
+    }
+}
+
+/// How urgently a dirty component should be rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Result of user input, rendered right after current event is handled
+    User,
+    /// Everything else, rendered on the next animation frame
+    Background,
 }
 
+static NEXT_RUNTIME_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Dirty {
     pub dirty: bool,
+    /// Unique id of the component runtime this flag belongs to
+    pub id: usize,
+    /// Number of component ancestors, top level components have depth of 0
+    pub depth: usize,
+    /// Gets notified when component becomes dirty, so it can be rendered later
+    pub scheduler: Option<Weak<Scheduler>>,
+    /// Custom element top level component is exported as, events it emits are fired on it
+    pub host: Option<DomNode>,
+    /// Commands the component started, cancelled when it goes away
+    commands: Vec<Weak<CommandSlot>>,
 }
 
 impl Dirty {
     pub fn new() -> Self {
-        Self { dirty: true }
+        Self {
+            dirty: true,
+            id: NEXT_RUNTIME_ID.fetch_add(1, Ordering::Relaxed),
+            depth: 0,
+            scheduler: None,
+            host: None,
+            commands: vec![],
+        }
+    }
+
+    /// Nested components are scheduled by the same scheduler as their parent
+    pub fn nest_in(&mut self, parent: &Dirty) {
+        self.depth = parent.depth + 1;
+        self.scheduler = parent.scheduler.clone();
+    }
+
+    /// Router attached to the framework this component belongs to
+    pub fn router(&self) -> Option<Rc<Router>> {
+        let scheduler = self.scheduler.as_ref().and_then(Weak::upgrade)?;
+        let router = scheduler.router.borrow();
+        router.clone()
+    }
+
+    /// Store attached to the framework this component belongs to
+    pub fn store(&self) -> Option<Rc<dyn SharedStore>> {
+        let scheduler = self.scheduler.as_ref().and_then(Weak::upgrade)?;
+        let store = scheduler.store.borrow();
+        store.clone()
+    }
+
+    /// Hand error over to the error handler of the framework, it is logged when there is none
+    pub fn report(&self, error: FrameworkError) {
+        match self.scheduler.as_ref().and_then(Weak::upgrade) {
+            Some(scheduler) => scheduler.report(error),
+            None => log!("Framework error: {}", error),
+        }
+    }
+
+    pub fn mark(&mut self, priority: Priority) {
+        self.dirty = true;
+
+        if let Some(scheduler) = self.scheduler.as_ref().and_then(Weak::upgrade) {
+            scheduler.enqueue(Update {
+                priority,
+                depth: self.depth,
+                id: self.id,
+            });
+        }
+    }
+}
+
+impl Drop for Dirty {
+    fn drop(&mut self) {
+        for slot in self.commands.iter().filter_map(Weak::upgrade) {
+            slot.cancel();
+        }
+    }
+}
+
+impl Default for Dirty {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 pub type DirtyInstance = Rc<RefCell<Dirty>>;
 
+pub type Registry = HashMap<&'static str, ComponentWrapper>;
+pub type ChildRuntimes = Vec<(String, ComponentRuntime)>;
+
 pub struct ComponentRuntime {
     pub component: ComponentInstance,
     pub dirty: DirtyInstance,
     pub template: Template,
     pub vdom: VDom,
+    pub children: ChildRuntimes,
+    /// Element top level component is mounted in to
+    pub target: Option<DomNode>,
+    /// Values provided by this component, linked to the ones of its ancestors
+    pub context: Rc<ContextNode>,
+    subscriptions: Vec<ActiveSubscription>,
+    /// Portal content realized by the last render, waiting to be patched in to its targets
+    pending_portals: Vec<(String, VDom)>,
+    portals: Vec<MountedPortal>,
 }
 
 impl ComponentRuntime {
-    pub fn render(&mut self) -> Vec<SharableDomNode> {
-        self.vdom = self
+    /// Realize template in to virtual nodes, slots are filled with content provided by the parent
+    pub fn realize(&mut self, registry: &Registry, slots: Slots) -> VDom {
+        // nested components are realized below and should see fresh values
+        self.component
+            .borrow()
+            .provide(&mut Provider::new(&self.context));
+
+        let mut ctx = RenderContext::new(
+            Rc::clone(&self.component),
+            Rc::clone(&self.dirty),
+            Rc::clone(&self.context),
+            registry,
+            &mut self.children,
+            slots,
+        );
+
+        let vdom = self
             .template
             .iter()
-            .map(|node| node.realize(Rc::clone(&self.component), Rc::clone(&self.dirty)))
+            .flat_map(|node| node.realize(&mut ctx))
             .collect();
 
-        let result = self.vdom.iter().map(|vnode| vnode.to_dom()).collect();
+        self.pending_portals = std::mem::take(&mut ctx.portals);
+        ctx.finish();
+
+        // template was realized with the latest component state
+        self.dirty.borrow_mut().dirty = false;
+
+        vdom
+    }
+
+    pub fn render(
+        &mut self,
+        registry: &Registry,
+        backend: &dyn DomBackend,
+    ) -> Vec<SharableDomNode> {
+        self.vdom = self.realize(registry, Slots::new());
+
+        self.vdom
+            .iter()
+            .flat_map(|vnode| vnode.create(backend))
+            .map(|node| Rc::new(RefCell::new(node)))
+            .collect()
+    }
+
+    /// Same as render, but adopts nodes that are already present in the target
+    pub fn hydrate(
+        &mut self,
+        registry: &Registry,
+        backend: &dyn DomBackend,
+        target: &DomNode,
+        mismatches: &mut Vec<String>,
+    ) -> Vec<SharableDomNode> {
+        self.vdom = self.realize(registry, Slots::new());
+
+        hydrate_children(backend, &self.vdom, target, mismatches)
+    }
+
+    /// Render again and patch the dom of mounted component with the difference
+    pub fn update(&mut self, registry: &Registry, backend: &Rc<dyn DomBackend>) {
+        let target = self.target.clone().expect("component is not mounted");
+        let vdom = self.realize(registry, Slots::new());
+
+        apply_changes(diff_children(&self.vdom, &vdom, &target), backend);
+
+        self.vdom = vdom;
+    }
+
+    /// Bring registered subscriptions of this and nested components in line with
+    /// what components ask for, unchanged subscriptions keep running
+    pub fn subscribe(&mut self, backend: &Rc<dyn DomBackend>) {
+        let mut wanted = self.component.borrow().subscriptions();
+
+        self.subscriptions.retain(|active| {
+            match wanted.iter().position(|s| *s == active.subscription) {
+                Some(index) => {
+                    wanted.remove(index);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        for subscription in wanted {
+            let component = Rc::downgrade(&self.component);
+            let dirty = Rc::downgrade(&self.dirty);
+            let message = subscription.message().to_string();
+            let priority = subscription.priority();
+
+            let handler = Handler::new(&message.clone(), move |event: &Event| {
+                dispatch(&component, &dirty, message.clone(), event, priority);
+            });
+
+            backend.subscribe(&subscription, &handler);
+            self.subscriptions.push(ActiveSubscription {
+                subscription,
+                handler,
+                backend: Rc::clone(backend),
+            });
+        }
+
+        for (_, child) in self.children.iter_mut() {
+            child.subscribe(backend);
+        }
+    }
+
+    /// Render content of portals from the last render in to their targets,
+    /// portals that are gone or point at another target now are removed,
+    /// portals with no target in the page are reported and left out
+    pub fn patch_portals(&mut self, backend: &Rc<dyn DomBackend>) {
+        let mut previous = std::mem::take(&mut self.portals).into_iter();
+
+        for (to, vdom) in std::mem::take(&mut self.pending_portals) {
+            let portal = match previous.next() {
+                Some(mut portal) if portal.to == to => {
+                    portal.patch(vdom);
+                    portal
+                }
+                _ => match backend.query_selector(&to) {
+                    Some(target) => MountedPortal::mount(to, target, vdom, backend),
+                    None => {
+                        self.dirty
+                            .borrow()
+                            .report(FrameworkError::MissingPortalTarget { to });
+                        continue;
+                    }
+                },
+            };
 
-        self.dirty.borrow_mut().dirty = true;
+            self.portals.push(portal);
+        }
+
+        for (_, child) in self.children.iter_mut() {
+            child.patch_portals(backend);
+        }
+    }
 
-        result
+    /// Whether this component or any of its nested components needs to be rendered again
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.borrow().dirty || self.children.iter().any(|(_, child)| child.is_dirty())
+    }
+
+    /// Whether runtime with given id is this one or one of nested runtimes
+    pub fn contains(&self, id: usize) -> bool {
+        self.dirty.borrow().id == id || self.children.iter().any(|(_, child)| child.contains(id))
     }
 }
 
 pub struct ComponentWrapper {
     pub template: Template,
-    pub constructor: ComponentConstructor,
+    pub constructor: InjectingConstructor,
+    /// Content of `<style scoped>` taken out of the template
+    pub style: Option<ScopedStyle>,
+    /// Keys `lookup`, `lookup_html` and `lookup_list` of the component know
+    pub keys: Vec<&'static str>,
+    /// Messages the component handles
+    pub messages: Vec<&'static str>,
 }
 
 impl ComponentWrapper {
     pub fn new(constructor: ComponentConstructor) -> Self {
+        Self::with_injector(Box::new(move |_| constructor()))
+    }
+
+    /// Parse template markup for the wrapper, handy for components registered lazily
+    pub fn template(self, markup: &str) -> Self {
+        self.parsed_template(extract_html(&mut markup.to_string()))
+    }
+
+    /// Use template that was already parsed, e.g. one taken from a template bundle
+    pub fn parsed_template(mut self, template: Template) -> Self {
+        self.template = template;
+        self.style = extract_scoped_style(&mut self.template);
+        self
+    }
+
+    /// Declare keys the component looks up, bindings of the template are checked against them
+    /// on registration, nothing is checked when there are none
+    pub fn keys(mut self, keys: &[&'static str]) -> Self {
+        self.keys = keys.to_vec();
+        self
+    }
+
+    /// Declare messages the component handles, `@handlers` of the template are checked
+    /// against them on registration, nothing is checked when there are none
+    pub fn messages(mut self, messages: &[&'static str]) -> Self {
+        self.messages = messages.to_vec();
+        self
+    }
+
+    /// Bindings of the template that the component does not declare
+    pub fn check(&self) -> Vec<Diagnostic> {
+        check_bindings(&self.template, &self.keys, &self.messages)
+    }
+
+    /// Wrapper for components that inject values provided by ancestors during construction
+    pub fn with_injector(constructor: InjectingConstructor) -> Self {
         ComponentWrapper {
             constructor,
             template: vec![],
+            style: None,
+            keys: vec![],
+            messages: vec![],
         }
     }
 
+    /// Runtime of a top level component
     pub fn construct(&self) -> ComponentRuntime {
+        self.construct_in(ContextNode::root())
+    }
+
+    /// Runtime with given context, nested components get a child of their parent's context
+    pub fn construct_in(&self, context: Rc<ContextNode>) -> ComponentRuntime {
+        let dirty = Rc::new(RefCell::new(Dirty::new()));
+        let component = (self.constructor)(&Injector::new(&context, &dirty));
+
         ComponentRuntime {
-            component: (self.constructor)(),
+            component,
             template: self.template.clone(),
-            dirty: Rc::new(RefCell::new(Dirty::new())),
+            dirty,
             vdom: vec![],
+            children: vec![],
+            target: None,
+            subscriptions: vec![],
+            context,
+            pending_portals: vec![],
+            portals: vec![],
         }
     }
 }
 
-fn load_template_data(id: &'static str) -> String {
-    web_sys::window()
-        .expect("could not get js/window")
-        .document()
-        .expect("could not get js/document instance")
-        .get_element_by_id(id)
-        .expect(&*format!("could not find target element {}", id))
-        .inner_html()
-}
+// ************** Scheduler **************
 
-// ************** Framework structure **************
+/// Request to render component runtime with given id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Update {
+    pub priority: Priority,
+    pub depth: usize,
+    pub id: usize,
+}
 
-#[wasm_bindgen]
-pub struct Framework {
-    components: HashMap<&'static str, ComponentWrapper>,
-    instances: Vec<ComponentRuntime>,
+/// Collects updates of dirty components and patches all of them in one pass,
+/// user updates are flushed in a microtask and background ones on the next animation frame
+pub struct Scheduler {
+    backend: Rc<dyn DomBackend>,
+    state: Weak<RefCell<State>>,
+    queue: RefCell<Vec<Update>>,
+    microtask_requested: Cell<bool>,
+    frame_requested: Cell<bool>,
+    automatic: Cell<bool>,
+    /// Global store templates can read with `$store.` prefix
+    store: RefCell<Option<Rc<dyn SharedStore>>>,
+    /// Router that renders matched components in to `<router-view>`
+    router: RefCell<Option<Rc<Router>>>,
+    /// Receives errors components ran in to while rendering
+    errors: RefCell<Option<ErrorHandler>>,
 }
 
-#[wasm_bindgen]
-impl Framework {
-    pub fn tick(&mut self) {
-        log!("Tick in Framework");
+impl Scheduler {
+    /// Hand error over to the error handler, it is logged when there is none
+    pub fn report(&self, error: FrameworkError) {
+        let handler = self.errors.borrow().clone();
+
+        match handler {
+            Some(handler) => handler(&error),
+            None => log!("Framework error: {}", error),
+        }
     }
-}
 
-impl Framework {
-    pub fn new() -> Self {
-        Framework {
-            components: HashMap::new(),
-            instances: vec![],
+    pub fn enqueue(self: &Rc<Self>, update: Update) {
+        self.queue.borrow_mut().push(update);
+
+        if !self.automatic.get() {
+            return;
+        }
+
+        let scheduler = Rc::downgrade(self);
+        let priority = update.priority;
+        let flush = Box::new(move || {
+            if let Some(scheduler) = scheduler.upgrade() {
+                scheduler.run(priority);
+            }
+        });
+
+        match update.priority {
+            Priority::User if !self.microtask_requested.replace(true) => {
+                self.backend.queue_microtask(flush)
+            }
+            Priority::Background if !self.frame_requested.replace(true) => {
+                self.backend.request_frame(flush)
+            }
+            _ => {}
         }
     }
 
-    pub fn register_component_wrapper(
-        &mut self,
-        name: &'static str,
-        mut wrapper: ComponentWrapper,
-        template_id: &'static str,
+    /// Run command in the background and dispatch its message back to the component,
+    /// results of commands arrive outside of user input, so they are rendered on the next frame
+    pub fn spawn(
+        &self,
+        command: Command,
+        component: Weak<RefCell<dyn Component>>,
+        dirty: Weak<RefCell<Dirty>>,
     ) {
-        wrapper.template = extract_html(&mut load_template_data(template_id));
-        self.components.insert(name, wrapper);
+        let slot = Rc::new(CommandSlot {
+            command: RefCell::new(Some(command)),
+            waker: RefCell::new(None),
+        });
+
+        if let Some(dirty) = dirty.upgrade() {
+            let commands = &mut dirty.borrow_mut().commands;
+            commands.retain(|slot| slot.strong_count() > 0);
+            commands.push(Rc::downgrade(&slot));
+        }
+
+        let guarded = Guarded {
+            slot,
+            alive: Weak::clone(&dirty),
+        };
+
+        self.backend.spawn(Box::pin(async move {
+            if let Some(message) = guarded.await {
+                let event = Event::new(COMMAND_EVENT);
+                dispatch(&component, &dirty, message, &event, Priority::Background);
+            }
+        }));
     }
 
-    fn instantiate(&self, component: &'static str) -> &ComponentWrapper {
-        self.components
-            .get(component)
-            .expect(&*format!("Unknown component {}", component))
+    /// Pending updates at least as urgent as `priority`, ordered by priority and parent first,
+    /// without duplicates, less urgent ones stay queued
+    fn take_queue(&self, priority: Priority) -> Vec<Update> {
+        let queue = self.queue.replace(vec![]);
+        let (mut updates, rest): (Vec<_>, Vec<_>) = queue
+            .into_iter()
+            .partition(|update| update.priority <= priority);
+        *self.queue.borrow_mut() = rest;
+        updates.sort();
+
+        // keep the most urgent update of every runtime
+        let mut seen = HashSet::new();
+        updates.retain(|update| seen.insert(update.id));
+        updates
     }
 
-    pub fn mount(&mut self, target_id: &'static str, component: &'static str) -> io::Result<()> {
-        log!("Mounting {} into #{}", component, target_id);
+    /// Flush scheduled for given priority, microtask renders only user updates,
+    /// frame renders everything that is left
+    fn run(self: &Rc<Self>, priority: Priority) {
+        match priority {
+            Priority::User => self.microtask_requested.set(false),
+            Priority::Background => self.frame_requested.set(false),
+        }
+
+        if let Some(state) = self.state.upgrade() {
+            match state.try_borrow_mut() {
+                Ok(mut state) => state.process(self.take_queue(priority)),
+                // framework is busy, try again on the next frame
+                Err(_) => {
+                    if !self.frame_requested.replace(true) {
+                        let scheduler = Rc::downgrade(self);
+                        self.backend.request_frame(Box::new(move || {
+                            if let Some(scheduler) = scheduler.upgrade() {
+                                scheduler.run(Priority::Background);
+                            }
+                        }));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ************** Framework structure **************
+
+/// Change caused by a custom element lifecycle callback, applied once framework is idle
+type ElementUpdate = Box<dyn FnOnce(&mut State, &Rc<Scheduler>)>;
+
+/// Part of the framework that is shared with scheduled frames
+pub struct State {
+    components: Registry,
+    instances: Vec<ComponentRuntime>,
+    backend: Rc<dyn DomBackend>,
+    /// Templates loaded ahead of time, they take precedence over `<template>` elements of the page
+    bundle: TemplateBundle,
+    /// Never read templates from the page nor add inline styles
+    strict_csp: bool,
+    /// Log accessibility problems of templates as components get registered
+    a11y_lint: bool,
+}
 
-        let wrapper = self.instantiate(component);
-        let mut runtime = wrapper.construct();
+impl State {
+    /// Render queued updates in order, nested components are rendered together
+    /// with the top level component they belong to, so each one is rendered at most once
+    fn process(&mut self, updates: Vec<Update>) {
+        for update in updates {
+            let runtime = self
+                .instances
+                .iter_mut()
+                .find(|runtime| runtime.contains(update.id));
 
-        let target = web_sys::window()
-            .expect("could not get js/window")
-            .document()
-            .expect("could not get js/document instance")
-            .get_element_by_id(target_id)
-            .expect(&*format!("could not find target element {}", target_id));
+            if let Some(runtime) = runtime {
+                if runtime.is_dirty() {
+                    runtime.update(&self.components, &self.backend);
+                    runtime.patch_portals(&self.backend);
+                    runtime.subscribe(&self.backend);
+                }
+            }
+        }
+    }
 
+    /// Render runtime in to target element and keep it up to date from now on
+    fn attach(&mut self, mut runtime: ComponentRuntime, target: DomNode) {
         // clear element
-        target.set_inner_html("");
+        self.backend.clear(&target);
+        self.backend.add_root(&target);
 
-        let elements = &*runtime.render();
+        let elements = &*runtime.render(&self.components, &*self.backend);
 
         for element in elements {
-            target
-                .append_child(&element.borrow())
-                .expect("colud not append child");
+            self.backend.append_child(&target, &element.borrow());
         }
 
+        runtime.patch_portals(&self.backend);
+        runtime.subscribe(&self.backend);
+        runtime.target = Some(target);
         self.instances.push(runtime);
+    }
 
-        Ok(())
+    fn hosted_by(&self, host: &DomNode) -> Option<&ComponentRuntime> {
+        self.instances.iter().find(|runtime| {
+            let dirty = runtime.dirty.borrow();
+            dirty.host.as_ref().is_some_and(|h| h.is_same(host))
+        })
+    }
+
+    /// Custom element got in to the document, component is rendered in to it
+    fn connect_element(
+        &mut self,
+        scheduler: &Rc<Scheduler>,
+        component: &'static str,
+        host: &DomNode,
+        options: &ElementOptions,
+    ) {
+        if self.hosted_by(host).is_some() {
+            return;
+        }
+
+        let runtime = self.instantiate(component).construct();
+        {
+            let mut dirty = runtime.dirty.borrow_mut();
+            dirty.scheduler = Some(Rc::downgrade(scheduler));
+            dirty.host = Some(host.clone());
+        }
+
+        for name in options.attributes() {
+            let value = self.backend.attribute(host, name);
+            runtime
+                .component
+                .borrow_mut()
+                .set_property(name, value.as_deref());
+        }
+
+        let target = match options.uses_shadow() {
+            true => self.backend.attach_shadow(host),
+            false => host.clone(),
+        };
+
+        self.attach(runtime, target.clone());
+
+        if options.uses_shadow() {
+            // components nested in this one render in to the same shadow tree
+            let mut components: Vec<_> = self.components.iter().collect();
+            components.sort_by_key(|(name, _)| **name);
+            for style in components.iter().filter_map(|(_, c)| c.style.as_ref()) {
+                self.add_style(Some(&target), style);
+            }
+        }
+    }
+
+    /// Custom element got removed from the document, its component goes away with it,
+    /// unless the element was only moved and is back in the document by now
+    fn disconnect_element(&mut self, host: &DomNode) {
+        if self.backend.is_connected(host) {
+            return;
+        }
+
+        let backend = Rc::clone(&self.backend);
+
+        self.instances.retain(|runtime| {
+            let hosted = runtime
+                .dirty
+                .borrow()
+                .host
+                .as_ref()
+                .is_some_and(|h| h.is_same(host));
+            if hosted {
+                if let Some(target) = &runtime.target {
+                    backend.clear(target);
+                    backend.remove_root(target);
+                }
+            }
+            !hosted
+        });
+    }
+
+    fn element_attribute_changed(&self, host: &DomNode, name: &str, value: Option<&str>) {
+        if let Some(runtime) = self.hosted_by(host) {
+            if runtime.component.borrow_mut().set_property(name, value) {
+                runtime.dirty.borrow_mut().mark(Priority::User);
+            }
+        }
+    }
+
+    fn find_element(&self, id: &str) -> DomNode {
+        self.backend
+            .get_element_by_id(id)
+            .unwrap_or_else(|| panic!("could not find target element {}", id))
+    }
+
+    /// Make component available to templates, its scoped style goes to the document head
+    fn register(&mut self, name: &'static str, wrapper: ComponentWrapper) {
+        if self.a11y_lint {
+            for warning in a11y::lint(&wrapper.template) {
+                log!("Accessibility of {}: {}", name, warning);
+            }
+        }
+
+        if let Some(style) = &wrapper.style {
+            self.add_style(None, style);
+        }
+
+        self.components.insert(name, wrapper);
+    }
+
+    /// Register component and return bindings of its template the component does not declare
+    fn register_checked(
+        &mut self,
+        name: &'static str,
+        wrapper: ComponentWrapper,
+    ) -> Vec<Diagnostic> {
+        let diagnostics = wrapper.check();
+        self.register(name, wrapper);
+
+        diagnostics
+    }
+
+    /// Add scoped style to the document or to a shadow root, document styles do not
+    /// reach in to shadow trees
+    fn add_style(&self, root: Option<&DomNode>, style: &ScopedStyle) {
+        match self.strict_csp {
+            true => self
+                .backend
+                .adopt_stylesheet(root, &style.attribute, &style.css),
+            false => self
+                .backend
+                .add_stylesheet(root, &style.attribute, &style.css),
+        }
+    }
+
+    fn instantiate(&self, component: &'static str) -> &ComponentWrapper {
+        self.components
+            .get(component)
+            .unwrap_or_else(|| panic!("Unknown component {}", component))
+    }
+}
+
+#[wasm_bindgen]
+pub struct Framework {
+    state: Rc<RefCell<State>>,
+    scheduler: Rc<Scheduler>,
+}
+
+#[wasm_bindgen]
+impl Framework {
+    /// Patch every mounted component that got dirty since the last tick
+    pub fn tick(&mut self) {
+        let updates = self.scheduler.take_queue(Priority::Background);
+        self.state.borrow_mut().process(updates);
+    }
+
+    /// Patch dirty components right away instead of waiting for the scheduled frame
+    pub fn flush_sync(&mut self) {
+        self.tick();
+    }
+
+    /// Turn off frame scheduling for hosts that call `tick` themselves
+    pub fn set_automatic_ticks(&mut self, enabled: bool) {
+        self.scheduler.automatic.set(enabled);
+    }
+
+    /// Listen for events once per event type on every mount point instead of on every element,
+    /// has to be turned on before anything is mounted
+    pub fn use_event_delegation(&mut self) {
+        let mut state = self.state.borrow_mut();
+
+        if !state.instances.is_empty() {
+            log!("Event delegation has to be turned on before mounting components");
+            return;
+        }
+
+        state.backend = DelegatingBackend::new(Rc::clone(&state.backend));
+    }
+
+    /// Work under Content Security Policy without inline scripts and styles: templates come
+    /// only from compiled-in strings or a template bundle and styles are constructed stylesheets
+    pub fn use_strict_csp(&mut self) {
+        self.state.borrow_mut().strict_csp = true;
+    }
+
+    /// Check templates of components registered from now on for accessibility problems,
+    /// they are logged, use `a11y::lint` to assert on them in tests
+    pub fn use_a11y_lint(&mut self) {
+        self.state.borrow_mut().a11y_lint = true;
+    }
+
+    /// Make `<template id="...">` elements of a prebuilt bundle available
+    /// to `register_component_wrapper`
+    pub fn load_template_bundle(&mut self, bundle: &str) {
+        let templates = extract_template_bundle(bundle);
+        self.state.borrow_mut().bundle.extend(templates);
+    }
+}
+
+impl Framework {
+    pub fn new() -> Self {
+        Self::with_backend(Rc::new(WebBackend))
+    }
+
+    /// Framework that renders through given backend, e.g. in-memory one in native tests
+    pub fn with_backend(backend: Rc<dyn DomBackend>) -> Self {
+        let state = Rc::new(RefCell::new(State {
+            components: Registry::new(),
+            instances: vec![],
+            backend: Rc::clone(&backend),
+            bundle: HashMap::new(),
+            strict_csp: false,
+            a11y_lint: false,
+        }));
+
+        let scheduler = Rc::new(Scheduler {
+            backend,
+            state: Rc::downgrade(&state),
+            queue: RefCell::new(vec![]),
+            microtask_requested: Cell::new(false),
+            frame_requested: Cell::new(false),
+            automatic: Cell::new(true),
+            store: RefCell::new(None),
+            router: RefCell::new(None),
+            errors: RefCell::new(None),
+        });
+
+        Framework { state, scheduler }
+    }
+
+    /// Share store with every component, templates read it with `$store.` prefix
+    pub fn use_store(&mut self, store: Rc<dyn SharedStore>) {
+        *self.scheduler.store.borrow_mut() = Some(store);
+    }
+
+    /// Get errors the framework recovered from, e.g. unsafe values of attribute bindings,
+    /// instead of having them logged
+    pub fn on_error(&mut self, handler: impl Fn(&FrameworkError) + 'static) {
+        *self.scheduler.errors.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Map urls to components, matched component is rendered in to `<router-view>`
+    pub fn use_router(&mut self, router: Rc<Router>) {
+        let backend = Rc::clone(&self.state.borrow().backend);
+
+        // lazy routes register their components on the first visit
+        let state = Rc::downgrade(&self.state);
+        let scheduler = Rc::downgrade(&self.scheduler);
+        let registrar = Box::new(move |name: &'static str, wrapper: ComponentWrapper| {
+            let (state, scheduler) = match (state.upgrade(), scheduler.upgrade()) {
+                (Some(state), Some(scheduler)) => (state, scheduler),
+                _ => return true,
+            };
+
+            // framework is busy, router tries again once it is done
+            let diagnostics = match state.try_borrow_mut() {
+                Ok(mut state) => state.register_checked(name, wrapper),
+                Err(_) => return false,
+            };
+
+            // nobody is there to take diagnostics of a lazy route, they go to the error handler
+            for diagnostic in diagnostics {
+                scheduler.report(FrameworkError::UndeclaredBinding {
+                    component: name,
+                    diagnostic,
+                });
+            }
+            true
+        });
+
+        router.start(backend, registrar);
+        *self.scheduler.router.borrow_mut() = Some(router);
+    }
+
+    fn load_template_data(&self, id: &str) -> Template {
+        let state = self.state.borrow();
+
+        if let Some(template) = state.bundle.get(id) {
+            return template.clone();
+        }
+
+        if state.strict_csp {
+            panic!(
+                "Template {} is not in the bundle, page is not read under strict CSP",
+                id
+            );
+        }
+
+        extract_html(&mut state.backend.inner_html(&state.find_element(id)))
+    }
+
+    /// Register component with template from the bundle or from `<template>` element of the page,
+    /// returns bindings of the template the component does not declare
+    pub fn register_component_wrapper(
+        &mut self,
+        name: &'static str,
+        wrapper: ComponentWrapper,
+        template_id: &'static str,
+    ) -> Vec<Diagnostic> {
+        let template = self.load_template_data(template_id);
+        self.state
+            .borrow_mut()
+            .register_checked(name, wrapper.parsed_template(template))
+    }
+
+    /// Register component with template markup provided directly instead of reading it from the page,
+    /// returns bindings of the template the component does not declare
+    pub fn register_component_template(
+        &mut self,
+        name: &'static str,
+        wrapper: ComponentWrapper,
+        template: &str,
+    ) -> Vec<Diagnostic> {
+        self.state
+            .borrow_mut()
+            .register_checked(name, wrapper.template(template))
+    }
+
+    /// Render fresh instance of a component to html markup, works without a browser,
+    /// content of `<portal>` elements is left out, it belongs to other parts of the page
+    pub fn render_to_string(&self, component: &'static str) -> String {
+        let mut runtime = self.construct(component);
+        let state = self.state.borrow();
+
+        runtime
+            .realize(&state.components, Slots::new())
+            .iter()
+            .map(|vnode| vnode.to_html_string())
+            .collect()
+    }
+
+    /// Runtime for a new top level component, that schedules frames when it gets dirty
+    fn construct(&self, component: &'static str) -> ComponentRuntime {
+        let runtime = self.state.borrow().instantiate(component).construct();
+        runtime.dirty.borrow_mut().scheduler = Some(Rc::downgrade(&self.scheduler));
+        runtime
+    }
+
+    pub fn mount(&mut self, target_id: &'static str, component: &'static str) -> io::Result<()> {
+        log!("Mounting {} into #{}", component, target_id);
+
+        let runtime = self.construct(component);
+        let state = &mut *self.state.borrow_mut();
+
+        let target = state.find_element(target_id);
+        state.attach(runtime, target);
+
+        Ok(())
+    }
+
+    /// Export registered component as a custom element, every `<tag>` in the page renders
+    /// its own instance of the component, no matter if it was there before or got added later
+    pub fn define_element(
+        &mut self,
+        tag: &'static str,
+        component: &'static str,
+        options: ElementOptions,
+    ) {
+        log!("Defining <{}> as {}", tag, component);
+
+        let options = Rc::new(options);
+        let backend = Rc::clone(&self.state.borrow().backend);
+        let attributes = options.attributes().to_vec();
+
+        // callbacks come while framework may be rendering, e.g. when the element is part of
+        // a template, so they are handled right after that
+        let later = {
+            let state = Rc::downgrade(&self.state);
+            let scheduler = Rc::downgrade(&self.scheduler);
+
+            move |callback: ElementUpdate| {
+                let scheduler = match scheduler.upgrade() {
+                    Some(scheduler) => scheduler,
+                    None => return,
+                };
+                let state = Weak::clone(&state);
+
+                let backend = Rc::clone(&scheduler.backend);
+                backend.queue_microtask(Box::new(move || {
+                    if let Some(state) = state.upgrade() {
+                        callback(&mut state.borrow_mut(), &scheduler);
+                    }
+                }));
+            }
+        };
+        let later = Rc::new(later);
+
+        let callbacks = ElementCallbacks {
+            connected: {
+                let later = Rc::clone(&later);
+                Box::new(move |host: &DomNode| {
+                    let host = host.clone();
+                    let options = Rc::clone(&options);
+                    later(Box::new(move |state, scheduler| {
+                        state.connect_element(scheduler, component, &host, &options)
+                    }))
+                })
+            },
+            disconnected: {
+                let later = Rc::clone(&later);
+                Box::new(move |host: &DomNode| {
+                    let host = host.clone();
+                    later(Box::new(move |state, _| state.disconnect_element(&host)))
+                })
+            },
+            attribute_changed: Box::new(move |host: &DomNode, name: &str, value| {
+                let (host, name) = (host.clone(), name.to_string());
+                later(Box::new(move |state, _| {
+                    state.element_attribute_changed(&host, &name, value.as_deref())
+                }))
+            }),
+        };
+
+        backend.define_element(tag, &attributes, callbacks);
+    }
+
+    /// Remove components mounted in to target element together with their dom,
+    /// subscriptions and pending commands of removed components are cancelled
+    pub fn unmount(&mut self, target_id: &'static str) -> io::Result<()> {
+        log!("Unmounting #{}", target_id);
+
+        let state = &mut *self.state.borrow_mut();
+        let target = state.find_element(target_id);
+
+        let mounted = state.instances.len();
+        state.instances.retain(|runtime| match &runtime.target {
+            Some(mounted) => !mounted.is_same(&target),
+            None => true,
+        });
+
+        if state.instances.len() == mounted {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("nothing is mounted in to #{}", target_id),
+            ));
+        }
+
+        state.backend.clear(&target);
+        state.backend.remove_root(&target);
+
+        Ok(())
+    }
+
+    /// Take over markup produced by `render_to_string` that is already inside of target element,
+    /// existing nodes are reused and only event listeners are attached
+    pub fn hydrate(&mut self, target_id: &'static str, component: &'static str) -> io::Result<()> {
+        log!("Hydrating {} in #{}", component, target_id);
+
+        let mut runtime = self.construct(component);
+        let state = &mut *self.state.borrow_mut();
+
+        let target = state.find_element(target_id);
+        let mut mismatches = vec![];
+        state.backend.add_root(&target);
+
+        runtime.hydrate(&state.components, &*state.backend, &target, &mut mismatches);
+
+        for mismatch in mismatches {
+            log!("Hydration mismatch in #{}: {}", target_id, mismatch);
+        }
+
+        runtime.patch_portals(&state.backend);
+        runtime.subscribe(&state.backend);
+        runtime.target = Some(target);
+        state.instances.push(runtime);
+
+        Ok(())
+    }
+}
+
+impl Default for Framework {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MemoryBackend, MemoryNode};
+    use crate::router::{Route, RouterMode};
+
+    struct Counter {
+        count: i32,
+    }
+
+    impl Component for Counter {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            self.count += 1;
+            true
+        }
+    }
+
+    impl Lookup for Counter {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "count" => Some(Box::new(self.count)),
+                "label" => Some(Box::new("<b>\"count\"</b>")),
+                "link" => Some(Box::new("javascript:alert(1)")),
+                _ => None,
+            }
+        }
+    }
+
+    fn counter() -> ComponentWrapper {
+        ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Counter { count: 3 }))))
+            .keys(&["count", "label", "link"])
+            .messages(&["increment"])
+    }
+
+    #[test]
+    fn render_to_string_basic() {
+        let mut framework = Framework::new();
+        framework.register_component_template(
+            "counter",
+            counter(),
+            "<p :class=\"count\" :title=\"label\">count</p><button @click=\"increment\">+</button>",
+        );
+
+        assert_eq!(
+            framework.render_to_string("counter"),
+            "<p class=\"3\" title=\"&lt;b&gt;&quot;count&quot;&lt;/b&gt;\">count</p>\
+             <button data-on-click=\"increment\">+</button>"
+        );
+    }
+
+    #[test]
+    fn render_to_string_nested() {
+        let mut framework = Framework::new();
+        framework.register_component_template(
+            "counter",
+            counter(),
+            "<i :id=\"count\"><slot></slot></i>",
+        );
+        framework.register_component_template(
+            "page",
+            counter(),
+            "<main><counter>a &lt; b</counter></main>",
+        );
+
+        assert_eq!(
+            framework.render_to_string("page"),
+            "<main><i id=\"3\">a &lt; b</i></main>"
+        );
+    }
+
+    #[test]
+    fn render_to_string_leaves_out_portals() {
+        let mut framework = Framework::new();
+        framework.register_component_template(
+            "counter",
+            counter(),
+            "<p>page</p><portal to=\"#modals\"><b>modal</b></portal>",
+        );
+
+        assert_eq!(framework.render_to_string("counter"), "<p>page</p>");
+    }
+
+    fn memory_framework(template: &str, page: &str) -> (Framework, Rc<MemoryBackend>) {
+        let backend = Rc::new(MemoryBackend::new());
+        let body = DomNode::Memory(backend.body());
+        backend.append_html(&body, page);
+
+        let mut framework = Framework::with_backend(backend.clone());
+        framework.register_component_template("counter", counter(), template);
+
+        (framework, backend)
+    }
+
+    fn find(backend: &MemoryBackend, tag: &str) -> MemoryNode {
+        backend
+            .body()
+            .find(&|node| node.tag().as_deref() == Some(tag))
+            .unwrap_or_else(|| panic!("could not find <{}>", tag))
+    }
+
+    fn container(backend: &MemoryBackend) -> String {
+        find(backend, "main").inner_html()
+    }
+
+    const TEMPLATE: &str = "<p :class=\"count\">count</p><button @click=\"increment\">+</button>";
+
+    #[test]
+    fn register_component_wrapper_reads_template_from_backend() {
+        let (mut framework, backend) = memory_framework(
+            "",
+            "<template id=\"tpl\"><b>hi</b></template><main id=\"app\"></main>",
+        );
+        framework.register_component_wrapper("other", counter(), "tpl");
+        framework.mount("app", "other").unwrap();
+
+        assert_eq!(container(&backend), "<b>hi</b>");
+    }
+
+    #[test]
+    fn strict_csp_uses_bundled_templates_and_adopted_stylesheets() {
+        let (mut framework, backend) = memory_framework(
+            "",
+            "<template id=\"tpl\"><b>page</b></template><main id=\"app\"></main>",
+        );
+        framework.use_strict_csp();
+        framework.load_template_bundle(
+            "<template id=\"tpl\"><style scoped>b { color: red }</style><b>bundle</b></template>",
+        );
+        framework.register_component_wrapper("other", counter(), "tpl");
+        framework.mount("app", "other").unwrap();
+
+        assert_eq!(find(&backend, "main").text_content(), "bundle");
+        assert!(backend.head().children().is_empty());
+
+        let adopted = backend.adopted_stylesheets();
+        assert_eq!(adopted.len(), 1);
+        assert!(adopted[0].0.starts_with(crate::styles::SCOPE_PREFIX));
+        assert!(adopted[0].1.contains("color: red"));
+    }
+
+    #[test]
+    fn registration_reports_undeclared_bindings() {
+        let (mut framework, _) = memory_framework(TEMPLATE, "<template id=\"tpl\"></template>");
+        assert!(framework
+            .register_component_template("valid", counter(), TEMPLATE)
+            .is_empty());
+
+        let diagnostics = framework.register_component_template(
+            "typos",
+            counter(),
+            "<section><p :class=\"cuont\">{{ count }}</p><button @click=\"decrement\">-</button></section>",
+        );
+        let diagnostics: Vec<_> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                "section > p: component has no key cuont",
+                "section > button: component does not handle message decrement",
+            ]
+        );
+
+        // components that inject values are checked without being constructed
+        let injecting = ComponentWrapper::with_injector(Box::new(|_: &Injector| {
+            panic!("component is constructed for the check")
+        }))
+        .keys(&["count"]);
+        let diagnostics =
+            framework.register_component_template("injecting", injecting, "{{ cuont }}");
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "#text: component has no key cuont"
+        );
+    }
+
+    #[test]
+    fn mount_replaces_target_content() {
+        let (mut framework, backend) =
+            memory_framework(TEMPLATE, "<main id=\"app\"><i>loading</i></main>");
+        framework.mount("app", "counter").unwrap();
+
+        assert_eq!(
+            container(&backend),
+            "<p class=\"3\">count</p><button>+</button>"
+        );
+        assert_eq!(find(&backend, "button").listener_count("click"), 1);
+    }
+
+    #[test]
+    fn tick_patches_dirty_components() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.mount("app", "counter").unwrap();
+
+        let paragraph = find(&backend, "p");
+        find(&backend, "button").dispatch("click");
+        assert_eq!(paragraph.attribute("class").unwrap(), "3");
+
+        framework.tick();
+        assert_eq!(paragraph.attribute("class").unwrap(), "4");
+        // existing nodes are patched, not recreated
+        assert!(paragraph.is_same(&find(&backend, "p")));
+        assert_eq!(find(&backend, "button").listener_count("click"), 1);
+
+        find(&backend, "button").dispatch("click");
+        find(&backend, "button").dispatch("click");
+        framework.tick();
+        assert_eq!(paragraph.attribute("class").unwrap(), "6");
+    }
+
+    #[test]
+    fn tick_skips_clean_components() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.mount("app", "counter").unwrap();
+
+        let paragraph = find(&backend, "p");
+        // nothing should touch the dom when component is not dirty
+        paragraph.dispatch("click");
+        framework.tick();
+        assert_eq!(paragraph.attribute("class").unwrap(), "3");
+    }
+
+    #[test]
+    fn hydrate_adopts_server_markup() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        let html = framework.render_to_string("counter");
+        backend.append_html(&DomNode::Memory(find(&backend, "main")), &html);
+
+        let paragraph = find(&backend, "p");
+        framework.hydrate("app", "counter").unwrap();
+
+        assert!(paragraph.is_same(&find(&backend, "p")));
+        assert_eq!(
+            container(&backend),
+            "<p class=\"3\">count</p><button>+</button>"
+        );
+
+        find(&backend, "button").dispatch("click");
+        framework.tick();
+        assert_eq!(paragraph.attribute("class").unwrap(), "4");
+    }
+
+    #[test]
+    fn hydrate_adopts_nested_components() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.register_component_template(
+            "page",
+            counter(),
+            "<counter></counter><footer>end</footer>",
+        );
+        let html = framework.render_to_string("page");
+        backend.append_html(&DomNode::Memory(find(&backend, "main")), &html);
+
+        let (paragraph, footer) = (find(&backend, "p"), find(&backend, "footer"));
+        framework.hydrate("app", "page").unwrap();
+
+        find(&backend, "button").dispatch("click");
+        framework.tick();
+        assert_eq!(
+            container(&backend),
+            "<p class=\"4\">count</p><button>+</button><footer>end</footer>"
+        );
+        assert!(paragraph.is_same(&find(&backend, "p")));
+        assert!(footer.is_same(&find(&backend, "footer")));
+    }
+
+    #[test]
+    fn unsafe_attribute_values_are_reported_and_left_out() {
+        let (mut framework, backend) = memory_framework(
+            "<a :href=\"link\" :title=\"link\" :onclick=\"label\">x</a><img :src=\"count\">",
+            "<main id=\"app\"></main>",
+        );
+        let errors = Rc::new(RefCell::new(vec![]));
+        let reported = Rc::clone(&errors);
+        framework.on_error(move |error| reported.borrow_mut().push(error.clone()));
+        framework.mount("app", "counter").unwrap();
+
+        assert_eq!(
+            container(&backend),
+            "<a title=\"javascript:alert(1)\">x</a><img src=\"3\">"
+        );
+
+        let mut errors = errors
+            .borrow()
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                "<a> attribute was not rendered: href has unsafe url \"javascript:alert(1)\"",
+                "<a> attribute was not rendered: onclick can not be bound, use @click instead",
+            ]
+        );
+    }
+
+    #[test]
+    fn hydrate_repairs_mismatches() {
+        let (mut framework, backend) = memory_framework(
+            TEMPLATE,
+            "<main id=\"app\"><p class=\"0\" title=\"x\">count</p><span>+</span><b></b></main>",
+        );
+        framework.hydrate("app", "counter").unwrap();
+
+        assert_eq!(
+            container(&backend),
+            "<p class=\"3\">count</p><button>+</button>"
+        );
+        assert_eq!(find(&backend, "button").listener_count("click"), 1);
+    }
+
+    #[test]
+    fn user_updates_flush_in_single_microtask() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.mount("app", "counter").unwrap();
+        assert_eq!(backend.pending_microtasks(), 0);
+
+        find(&backend, "button").dispatch("click");
+        find(&backend, "button").dispatch("click");
+        assert_eq!(backend.pending_microtasks(), 1);
+        assert_eq!(backend.pending_frames(), 0);
+
+        backend.run_microtasks();
+        assert_eq!(find(&backend, "p").attribute("class").unwrap(), "5");
+        assert_eq!(backend.pending_microtasks(), 0);
+
+        find(&backend, "button").dispatch("click");
+        assert_eq!(backend.pending_microtasks(), 1);
+    }
+
+    #[test]
+    fn background_updates_schedule_single_frame() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.mount("app", "counter").unwrap();
+
+        let dirty = Rc::clone(&framework.state.borrow().instances[0].dirty);
+        dirty.borrow_mut().mark(Priority::Background);
+        dirty.borrow_mut().mark(Priority::Background);
+        assert_eq!(backend.pending_frames(), 1);
+        assert_eq!(backend.pending_microtasks(), 0);
+
+        backend.run_frames();
+        assert!(!dirty.borrow().dirty);
+        assert_eq!(backend.pending_frames(), 0);
+    }
+
+    #[test]
+    fn microtask_leaves_background_updates_for_the_frame() {
+        let (mut framework, backend) = memory_framework(
+            TEMPLATE,
+            "<main id=\"app\"></main><aside id=\"other\"></aside>",
+        );
+        framework.mount("app", "counter").unwrap();
+        framework.mount("other", "counter").unwrap();
+
+        let background = Rc::clone(&framework.state.borrow().instances[1].dirty);
+        background.borrow_mut().mark(Priority::Background);
+        find(&backend, "button").dispatch("click");
+
+        backend.run_microtasks();
+        assert_eq!(find(&backend, "p").attribute("class").unwrap(), "4");
+        assert!(background.borrow().dirty);
+        assert_eq!(backend.pending_frames(), 1);
+
+        backend.run_frames();
+        assert!(!background.borrow().dirty);
+    }
+
+    #[test]
+    fn flush_sync_renders_without_waiting() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.mount("app", "counter").unwrap();
+
+        find(&backend, "button").dispatch("click");
+        framework.flush_sync();
+        assert_eq!(find(&backend, "p").attribute("class").unwrap(), "4");
+
+        // microtask queued by the click has nothing left to do
+        backend.run_microtasks();
+        assert_eq!(find(&backend, "p").attribute("class").unwrap(), "4");
+    }
+
+    #[test]
+    fn automatic_ticks_can_be_disabled() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.set_automatic_ticks(false);
+        framework.mount("app", "counter").unwrap();
+
+        find(&backend, "button").dispatch("click");
+        assert_eq!(backend.pending_microtasks(), 0);
+        assert_eq!(backend.pending_frames(), 0);
+
+        framework.tick();
+        assert_eq!(find(&backend, "p").attribute("class").unwrap(), "4");
+    }
+
+    #[test]
+    fn nested_components_schedule_updates() {
+        let (mut framework, backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        framework.register_component_template(
+            "page",
+            counter(),
+            "<section><counter></counter></section>",
+        );
+        framework.mount("app", "page").unwrap();
+
+        find(&backend, "button").dispatch("click");
+        assert_eq!(backend.pending_microtasks(), 1);
+
+        backend.run_microtasks();
+        assert_eq!(find(&backend, "p").attribute("class").unwrap(), "4");
+    }
+
+    #[test]
+    fn queue_orders_by_priority_and_depth() {
+        let framework = Framework::with_backend(Rc::new(MemoryBackend::new()));
+        framework.scheduler.automatic.set(false);
+
+        let update = |priority, depth, id| Update {
+            priority,
+            depth,
+            id,
+        };
+        framework
+            .scheduler
+            .enqueue(update(Priority::Background, 0, 1));
+        framework.scheduler.enqueue(update(Priority::User, 2, 2));
+        framework.scheduler.enqueue(update(Priority::User, 1, 3));
+        framework.scheduler.enqueue(update(Priority::User, 0, 1));
+        framework
+            .scheduler
+            .enqueue(update(Priority::Background, 2, 2));
+
+        assert_eq!(
+            framework.scheduler.take_queue(Priority::Background),
+            vec![
+                update(Priority::User, 0, 1),
+                update(Priority::User, 1, 3),
+                update(Priority::User, 2, 2),
+            ]
+        );
+    }
+
+    struct Probe {
+        renders: Rc<Cell<usize>>,
+    }
+
+    impl Component for Probe {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            true
+        }
+    }
+
+    impl Lookup for Probe {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "renders" => {
+                    self.renders.set(self.renders.get() + 1);
+                    Some(Box::new(self.renders.get()))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn parent_and_child_updates_render_once() {
+        let renders = Rc::new(Cell::new(0));
+        let probe = |renders: &Rc<Cell<usize>>| {
+            let renders = Rc::clone(renders);
+            ComponentWrapper::new(Box::new(move || {
+                Rc::new(RefCell::new(Probe {
+                    renders: Rc::clone(&renders),
+                }))
+            }))
+        };
+
+        let (mut framework, backend) = memory_framework("", "<main id=\"app\"></main>");
+        framework.register_component_template(
+            "child",
+            probe(&renders),
+            "<button :title=\"renders\" @click=\"poke\"></button>",
+        );
+        framework.register_component_template(
+            "parent",
+            probe(&renders),
+            "<div :title=\"renders\"><child></child></div>",
+        );
+        framework.mount("app", "parent").unwrap();
+        assert_eq!(renders.get(), 2);
+
+        let parent = Rc::clone(&framework.state.borrow().instances[0].dirty);
+        find(&backend, "button").dispatch("click");
+        parent.borrow_mut().mark(Priority::User);
+
+        backend.run_microtasks();
+        assert_eq!(renders.get(), 4);
+    }
+
+    /// Body that test provides by hand, stands in for a network response
+    #[derive(Default)]
+    struct Reply {
+        body: Option<String>,
+        waker: Option<Waker>,
+    }
+
+    type Response = Rc<RefCell<Reply>>;
+
+    fn respond(response: &Response, body: &str) {
+        let mut reply = response.borrow_mut();
+        reply.body = Some(body.to_string());
+
+        if let Some(waker) = reply.waker.take() {
+            waker.wake();
+        }
+    }
+
+    struct Loader {
+        response: Response,
+        status: String,
+    }
+
+    impl Component for Loader {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            false
+        }
+
+        fn update(&mut self, message: String, event: &Event, commands: &mut Commands) -> bool {
+            if message == "load" {
+                let response = Rc::clone(&self.response);
+                commands.perform(std::future::poll_fn(move |cx| {
+                    let mut reply = response.borrow_mut();
+
+                    match reply.body.take() {
+                        Some(body) => Poll::Ready(body),
+                        None => {
+                            reply.waker = Some(cx.waker().clone());
+                            Poll::Pending
+                        }
+                    }
+                }));
+                self.status = "loading".to_string();
+            } else {
+                assert_eq!(event.kind, COMMAND_EVENT);
+                self.status = message;
+            }
+            true
+        }
+    }
+
+    impl Lookup for Loader {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "status" => Some(Box::new(self.status.clone())),
+                _ => None,
+            }
+        }
+    }
+
+    fn loader_framework(response: &Response) -> (Framework, Rc<MemoryBackend>) {
+        let (mut framework, backend) = memory_framework("", "<main id=\"app\"></main>");
+        let response = Rc::clone(response);
+        let wrapper = ComponentWrapper::new(Box::new(move || {
+            Rc::new(RefCell::new(Loader {
+                response: Rc::clone(&response),
+                status: "idle".to_string(),
+            }))
+        }));
+        framework.register_component_template(
+            "loader",
+            wrapper,
+            "<button :title=\"status\" @click=\"load\"></button>",
+        );
+        framework.mount("app", "loader").unwrap();
+        (framework, backend)
+    }
+
+    #[test]
+    fn command_result_is_sent_back_to_component() {
+        let response = Response::default();
+        let (_framework, backend) = loader_framework(&response);
+
+        find(&backend, "button").dispatch("click");
+        backend.run_microtasks();
+        assert_eq!(
+            find(&backend, "button").attribute("title").unwrap(),
+            "loading"
+        );
+
+        backend.run_tasks();
+        assert_eq!(backend.pending_tasks(), 1);
+
+        // nothing happens until the response wakes the command up
+        backend.run_tasks();
+        assert_eq!(backend.pending_tasks(), 1);
+
+        respond(&response, "done");
+        backend.run_tasks();
+        assert_eq!(backend.pending_tasks(), 0);
+        assert_eq!(backend.pending_frames(), 1);
+
+        backend.run_frames();
+        assert_eq!(find(&backend, "button").attribute("title").unwrap(), "done");
+    }
+
+    #[test]
+    fn commands_are_dropped_after_unmount() {
+        let response = Response::default();
+        let (mut framework, backend) = loader_framework(&response);
+
+        find(&backend, "button").dispatch("click");
+        assert_eq!(backend.pending_tasks(), 1);
+        assert_eq!(Rc::strong_count(&response), 4);
+
+        framework.unmount("app").unwrap();
+        assert_eq!(container(&backend), "");
+
+        backend.run_tasks();
+        assert_eq!(backend.pending_tasks(), 0);
+        assert_eq!(backend.pending_frames(), 0);
+        // component and its command are gone, only the test and the constructor are left
+        assert_eq!(Rc::strong_count(&response), 2);
+    }
+
+    #[test]
+    fn pending_command_is_freed_on_unmount_without_polling() {
+        let response = Response::default();
+        let (mut framework, backend) = loader_framework(&response);
+
+        find(&backend, "button").dispatch("click");
+        backend.run_tasks();
+        assert_eq!(Rc::strong_count(&response), 4);
+
+        // command never resolves, it is freed by the unmount itself and its task gets woken,
+        // otherwise it would never be polled again
+        framework.unmount("app").unwrap();
+        assert_eq!(Rc::strong_count(&response), 2);
+
+        backend.run_tasks();
+        assert_eq!(backend.pending_tasks(), 0);
+    }
+
+    #[test]
+    fn unmount_unknown_target_fails() {
+        let (mut framework, _backend) = memory_framework(TEMPLATE, "<main id=\"app\"></main>");
+        assert!(framework.unmount("app").is_err());
+    }
+
+    struct Clock {
+        ticks: usize,
+        running: bool,
+        shortcuts: Vec<String>,
+    }
+
+    impl Component for Clock {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, message: String) -> bool {
+            match message.as_ref() {
+                "tick" => self.ticks += 1,
+                "toggle" => self.running = !self.running,
+                _ => return false,
+            }
+            true
+        }
+
+        fn handle_event(&mut self, message: String, event: &Event) -> bool {
+            match message.as_ref() {
+                "shortcut" => {
+                    self.shortcuts.push(event.key.clone().unwrap_or_default());
+                    true
+                }
+                _ => self.handle(message),
+            }
+        }
+
+        fn subscriptions(&self) -> Vec<Subscription> {
+            let mut subscriptions = vec![Subscription::document("keydown", "shortcut")];
+            if self.running {
+                subscriptions.push(Subscription::interval(1000, "tick"));
+            }
+            subscriptions
+        }
+    }
+
+    impl Lookup for Clock {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "ticks" => Some(Box::new(self.ticks)),
+                "shortcuts" => Some(Box::new(self.shortcuts.join(","))),
+                _ => None,
+            }
+        }
+    }
+
+    fn clock_framework() -> (Framework, Rc<MemoryBackend>) {
+        let (mut framework, backend) = memory_framework("", "<main id=\"app\"></main>");
+        let wrapper = ComponentWrapper::new(Box::new(|| {
+            Rc::new(RefCell::new(Clock {
+                ticks: 0,
+                running: true,
+                shortcuts: vec![],
+            }))
+        }));
+        framework.register_component_template(
+            "clock",
+            wrapper,
+            "<button :title=\"ticks\" :data-keys=\"shortcuts\" @click=\"toggle\"></button>",
+        );
+        framework.mount("app", "clock").unwrap();
+        (framework, backend)
+    }
+
+    #[test]
+    fn subscriptions_are_registered_on_mount() {
+        let (_framework, backend) = clock_framework();
+        assert_eq!(backend.subscription_count(), 2);
+
+        backend.advance(2500);
+        assert_eq!(backend.pending_frames(), 1);
+        backend.run_frames();
+        assert_eq!(find(&backend, "button").attribute("title").unwrap(), "2");
+
+        backend.dispatch_document(&Event::with_key("keydown", "k"));
+        backend.dispatch_document(&Event::with_key("keyup", "j"));
+        backend.run_microtasks();
+        assert_eq!(
+            find(&backend, "button").attribute("data-keys").unwrap(),
+            "k"
+        );
+    }
+
+    #[test]
+    fn subscriptions_are_diffed_on_update() {
+        let (_framework, backend) = clock_framework();
+        backend.advance(1000);
+
+        // stopping the clock drops the interval, but keeps the keyboard subscription
+        find(&backend, "button").dispatch("click");
+        backend.run_microtasks();
+        assert_eq!(backend.subscription_count(), 1);
+
+        backend.advance(5000);
+        backend.run_frames();
+        assert_eq!(find(&backend, "button").attribute("title").unwrap(), "1");
+
+        find(&backend, "button").dispatch("click");
+        backend.run_microtasks();
+        assert_eq!(backend.subscription_count(), 2);
+
+        backend.advance(1000);
+        backend.run_frames();
+        assert_eq!(find(&backend, "button").attribute("title").unwrap(), "2");
+    }
+
+    #[test]
+    fn subscriptions_are_torn_down_on_unmount() {
+        let (mut framework, backend) = clock_framework();
+
+        framework.unmount("app").unwrap();
+        assert_eq!(backend.subscription_count(), 0);
+
+        backend.advance(1000);
+        assert_eq!(backend.pending_frames(), 0);
+    }
+
+    #[test]
+    fn portals_render_in_to_other_elements() {
+        let (mut framework, backend) = memory_framework(
+            "<p :class=\"count\">count</p>\
+             <portal to=\"#modals\"><button @click=\"increment\" :title=\"count\">+</button></portal>",
+            "<main id=\"app\"></main><div id=\"modals\"><i>existing</i></div>",
+        );
+        framework.mount("app", "counter").unwrap();
+
+        assert_eq!(container(&backend), "<p class=\"3\">count</p>");
+        let modals = backend.get_element_by_id("modals").unwrap();
+        assert_eq!(
+            backend.inner_html(&modals),
+            "<i>existing</i><button title=\"3\">+</button>"
+        );
+
+        // handlers and lookups inside of the portal belong to the component
+        find(&backend, "button").dispatch("click");
+        backend.run_microtasks();
+        assert_eq!(container(&backend), "<p class=\"4\">count</p>");
+        assert_eq!(find(&backend, "button").attribute("title").unwrap(), "4");
+
+        framework.unmount("app").unwrap();
+        assert_eq!(backend.inner_html(&modals), "<i>existing</i>");
+    }
+
+    #[test]
+    fn portals_without_target_are_reported_and_skipped() {
+        let (mut framework, backend) = memory_framework(
+            "<portal to=\"#missing\"><b>lost</b></portal><p :class=\"count\">count</p>",
+            "<main id=\"app\"></main>",
+        );
+        let errors = Rc::new(RefCell::new(vec![]));
+        let collected = Rc::clone(&errors);
+        framework.on_error(move |error| collected.borrow_mut().push(error.clone()));
+        framework.mount("app", "counter").unwrap();
+
+        assert_eq!(container(&backend), "<p class=\"3\">count</p>");
+        assert_eq!(
+            *errors.borrow(),
+            vec![FrameworkError::MissingPortalTarget {
+                to: "#missing".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn unknown_interpolation_keys_are_reported_and_rendered_empty() {
+        let (mut framework, backend) = memory_framework(
+            "<p>{{ count }}{{ missing }}</p>",
+            "<main id=\"app\"></main>",
+        );
+        let errors = Rc::new(RefCell::new(vec![]));
+        let collected = Rc::clone(&errors);
+        framework.on_error(move |error| collected.borrow_mut().push(error.to_string()));
+        framework.mount("app", "counter").unwrap();
+
+        assert_eq!(container(&backend), "<p>3</p>");
+        assert_eq!(
+            *errors.borrow(),
+            vec!["{{ missing }} was not rendered: could not find the key"]
+        );
+    }
+
+    #[test]
+    fn lazy_route_is_registered_once_framework_is_idle() {
+        let (mut framework, backend) = memory_framework(
+            "<router-view><p>none</p></router-view>",
+            "<main id=\"app\"></main>",
+        );
+        let router = Rc::new(Router::new(RouterMode::History).add_route(Route::lazy(
+            "/reports",
+            "reports",
+            || counter().template("<b>reports</b>"),
+        )));
+        framework.use_router(Rc::clone(&router));
+        framework.mount("app", "counter").unwrap();
+
+        // e.g. a component navigates while it is being rendered
+        {
+            let _busy = framework.state.borrow_mut();
+            router.navigate("/reports");
+        }
+        assert_eq!(container(&backend), "<p>none</p>");
+        assert_eq!(router.path(), "/");
+
+        backend.run_microtasks();
+        assert_eq!(container(&backend), "<b>reports</b>");
+        assert_eq!(router.path(), "/reports");
+    }
+
+    #[test]
+    fn lazy_routes_report_undeclared_bindings() {
+        let (mut framework, _) =
+            memory_framework("<router-view></router-view>", "<main id=\"app\"></main>");
+        let errors = Rc::new(RefCell::new(vec![]));
+        let collected = Rc::clone(&errors);
+        framework.on_error(move |error| collected.borrow_mut().push(error.to_string()));
+
+        let router = Rc::new(Router::new(RouterMode::History).add_route(Route::lazy(
+            "/reports",
+            "reports",
+            || counter().template("<b>{{ cuont }}</b>"),
+        )));
+        framework.use_router(Rc::clone(&router));
+        framework.mount("app", "counter").unwrap();
+        assert!(errors.borrow().is_empty());
+
+        router.navigate("/reports");
+        assert_eq!(
+            *errors.borrow(),
+            ["template of reports: b > #text: component has no key cuont"]
+        );
     }
 }
@@ -2,23 +2,42 @@
 use html5ever::driver::ParseOpts;
 use html5ever::tendril::TendrilSink;
 use html5ever::tree_builder::TreeBuilderOpts;
-use html5ever::{parse_document, rcdom};
+use html5ever::{parse_document, parse_fragment, rcdom, LocalName, QualName};
 use std::cell::Ref;
+use std::collections::HashMap;
 use std::default::Default;
 use std::rc::Rc;
 
 pub use crate::templating::*;
+use crate::vdom::Namespace;
 
-fn parse_html(input: &mut String) -> rcdom::RcDom {
-    let opts = ParseOpts {
+/// Markup the application vouches for, the only kind of value `:html` bindings accept.
+/// Never trust anything that came from users without sanitizing it first
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustedHtml(String);
+
+impl TrustedHtml {
+    pub fn trust(markup: &str) -> Self {
+        TrustedHtml(markup.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn options() -> ParseOpts {
+    ParseOpts {
         tree_builder: TreeBuilderOpts {
             drop_doctype: true,
             ..Default::default()
         },
         ..Default::default()
-    };
+    }
+}
 
-    parse_document(rcdom::RcDom::default(), opts)
+fn parse_html(input: &mut String) -> rcdom::RcDom {
+    parse_document(rcdom::RcDom::default(), options())
         .from_utf8()
         .read_from(&mut input.as_bytes())
         .expect("could not parse html input")
@@ -36,15 +55,36 @@ fn extract_attribute(attr: &html5ever::Attribute) -> (String, Attribute) {
     }
 }
 
-fn extract_attributes(attributes: Ref<'_, Vec<html5ever::Attribute>>) -> Attributes {
-    attributes.iter().map(extract_attribute).collect()
+/// Attributes of trusted markup are kept as they are, `:` and `@` included
+fn extract_static_attribute(attr: &html5ever::Attribute) -> (String, Attribute) {
+    (
+        attr.name.local.to_string(),
+        Attribute::Static(attr.value.to_string()),
+    )
+}
+
+fn extract_attributes(
+    attributes: Ref<'_, Vec<html5ever::Attribute>>,
+    bindings: bool,
+) -> Attributes {
+    match bindings {
+        true => attributes.iter().map(extract_attribute).collect(),
+        false => attributes.iter().map(extract_static_attribute).collect(),
+    }
 }
 
-fn extract_children(children: Ref<'_, Vec<Rc<rcdom::Node>>>) -> Template {
+fn extract_children(children: Ref<'_, Vec<Rc<rcdom::Node>>>, bindings: bool) -> Template {
     let mut res = Vec::new();
 
     for child in children.iter() {
-        let children = extract_children(child.children.borrow());
+        let children = match &child.data {
+            // content of <template> lives in a separate document fragment
+            rcdom::NodeData::Element {
+                template_contents: Some(contents),
+                ..
+            } => extract_children(contents.children.borrow(), bindings),
+            _ => extract_children(child.children.borrow(), bindings),
+        };
 
         match &child.data {
             rcdom::NodeData::Element { name, .. }
@@ -52,11 +92,12 @@ fn extract_children(children: Ref<'_, Vec<Rc<rcdom::Node>>>) -> Template {
                     || name.local.to_string() == "head"
                     || name.local.to_string() == "body" =>
             {
-                res = children;
+                res.extend(children);
             }
             rcdom::NodeData::Element { name, attrs, .. } => res.push(Node {
                 data: NodeData::Element {
-                    attributes: extract_attributes(attrs.borrow()),
+                    attributes: extract_attributes(attrs.borrow(), bindings),
+                    namespace: Namespace::from_uri(&name.ns),
                     tag: name.local.to_string(),
                 },
                 children,
@@ -68,6 +109,10 @@ fn extract_children(children: Ref<'_, Vec<Rc<rcdom::Node>>>) -> Template {
                 },
                 children,
             }),
+            // nothing of these is rendered
+            rcdom::NodeData::Comment { .. }
+            | rcdom::NodeData::Doctype { .. }
+            | rcdom::NodeData::ProcessingInstruction { .. } => {}
             _ => panic!("Unhandled NodeData type"),
         }
     }
@@ -78,7 +123,47 @@ fn extract_children(children: Ref<'_, Vec<Rc<rcdom::Node>>>) -> Template {
 pub fn extract_html(input: &mut String) -> Template {
     let dom = parse_html(input);
 
-    extract_children(dom.document.children.borrow())
+    extract_children(dom.document.children.borrow(), true)
+}
+
+/// Templates of a prebuilt bundle by id of the `<template>` element they came from
+pub type TemplateBundle = HashMap<String, Template>;
+
+/// Parse bundle of `<template id="...">` elements, so templates do not have to be inlined in the page
+pub fn extract_template_bundle(input: &str) -> TemplateBundle {
+    let mut bundle = HashMap::new();
+    collect_templates(extract_html(&mut input.to_string()), &mut bundle);
+
+    bundle
+}
+
+fn collect_templates(template: Template, bundle: &mut TemplateBundle) {
+    for node in template {
+        if let NodeData::Element {
+            tag, attributes, ..
+        } = &node.data
+        {
+            if let (true, Some(Attribute::Static(id))) = (tag == "template", attributes.get("id")) {
+                bundle.insert(id.clone(), node.children);
+                continue;
+            }
+        }
+
+        collect_templates(node.children, bundle);
+    }
+}
+
+/// Parse trusted markup the way browser parses `innerHTML` of an element with given tag,
+/// nothing in it is treated as a binding
+pub fn extract_trusted_html(html: &TrustedHtml, tag: &str, namespace: Namespace) -> Template {
+    let context = QualName::new(None, namespace.uri().into(), LocalName::from(tag));
+
+    let dom = parse_fragment(rcdom::RcDom::default(), options(), context, vec![])
+        .from_utf8()
+        .read_from(&mut html.as_str().as_bytes())
+        .expect("could not parse html input");
+
+    extract_children(dom.document.children.borrow(), false)
 }
 
 #[cfg(test)]
@@ -101,6 +186,13 @@ mod tests {
             }
         }
 
+        fn namespace(&self) -> Option<Namespace> {
+            match self {
+                Self::Element { namespace, .. } => Some(*namespace),
+                _ => None,
+            }
+        }
+
         fn content(&self) -> Option<&String> {
             match self {
                 Self::Text { content } => Some(content),
@@ -112,10 +204,7 @@ mod tests {
     impl Attribute {
         //{{{
         pub fn is_handler(&self) -> bool {
-            match self {
-                Self::Handler(_) => true,
-                _ => false,
-            }
+            matches!(self, Self::Handler(_))
         }
 
         pub fn is_attribute(&self) -> bool {
@@ -181,6 +270,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn extract_html_template_content() {
+        let dom = extract_html(&mut "<template id=\"t\"><b>hi</b></template><p></p>".to_string());
+        assert_eq!(dom.len(), 2);
+        assert_eq!(dom[0].data.tag().unwrap(), &"template");
+        assert_eq!(dom[0].children[0].data.tag().unwrap(), &"b");
+        assert_eq!(dom[1].data.tag().unwrap(), &"p");
+    }
+
+    #[test]
+    fn extract_template_bundle_by_id() {
+        let bundle = extract_template_bundle(
+            "<template id=\"a\"><b :title=\"x\">hi</b></template>\
+             <div><template id=\"b\"><i></i></template><template><u></u></template></div>",
+        );
+
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle["a"][0].data.tag().unwrap(), &"b");
+        assert!(matches!(
+            bundle["a"][0].data.attributes().unwrap()["title"],
+            Attribute::Dynamic(_)
+        ));
+        assert_eq!(bundle["b"][0].data.tag().unwrap(), &"i");
+    }
+
     #[test]
     fn extract_html_text_node() {
         let dom = extract_html(&mut "<p>im a text</p>".to_string());
@@ -188,4 +302,57 @@ mod tests {
         assert_eq!(dom[0].children.len(), 1);
         assert_eq!(dom[0].children[0].data.content().unwrap(), &"im a text");
     }
+
+    #[test]
+    fn extract_html_namespaces() {
+        let dom = extract_html(
+            &mut "<div><svg><foreignObject><p></p></foreignObject></svg><math><mi>x</mi></math></div>"
+                .to_string(),
+        );
+        let (svg, math) = (&dom[0].children[0], &dom[0].children[1]);
+
+        assert_eq!(dom[0].data.namespace().unwrap(), Namespace::Html);
+        assert_eq!(svg.data.namespace().unwrap(), Namespace::Svg);
+        // svg tags keep their case
+        assert_eq!(svg.children[0].data.tag().unwrap(), &"foreignObject");
+        assert_eq!(svg.children[0].data.namespace().unwrap(), Namespace::Svg);
+        assert_eq!(
+            svg.children[0].children[0].data.namespace().unwrap(),
+            Namespace::Html
+        );
+        assert_eq!(math.data.namespace().unwrap(), Namespace::MathMl);
+        assert_eq!(
+            math.children[0].data.namespace().unwrap(),
+            Namespace::MathMl
+        );
+    }
+
+    #[test]
+    fn extract_trusted_html_has_no_bindings() {
+        let html = TrustedHtml::trust("<b @click=\"x\" :title=\"y\">hi</b><circle></circle>");
+
+        let dom = extract_trusted_html(&html, "div", Namespace::Html);
+        assert_eq!(dom.len(), 2);
+        let attributes = dom[0].data.attributes().unwrap();
+        assert_eq!(attributes["@click"].value(), &"x");
+        assert_eq!(attributes[":title"].value(), &"y");
+        assert!(attributes.values().all(Attribute::is_attribute));
+        assert_eq!(dom[1].data.namespace().unwrap(), Namespace::Html);
+
+        // content of an svg element is parsed as svg
+        let dom = extract_trusted_html(&html, "svg", Namespace::Svg);
+        assert_eq!(dom[1].data.namespace().unwrap(), Namespace::Svg);
+    }
+
+    #[test]
+    fn extract_html_leaves_out_comments() {
+        let html = TrustedHtml::trust("<b>hi</b><!-- note --><?php echo 1 ?>");
+        let dom = extract_trusted_html(&html, "div", Namespace::Html);
+        assert_eq!(dom.len(), 1);
+        assert_eq!(dom[0].data.tag().unwrap(), "b");
+
+        let dom = extract_html(&mut "<!DOCTYPE html><p><!-- note -->a</p>".to_string());
+        assert_eq!(dom.len(), 1);
+        assert_eq!(dom[0].children.len(), 1);
+    }
 }
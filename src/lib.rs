@@ -7,16 +7,33 @@ use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
 macro_rules! log {
-    ( $( $t:tt )* ) => {
+    ( $( $t:tt )* ) => {{
+        #[cfg(target_arch = "wasm32")]
         web_sys::console::log_1(&format!( $( $t )* ).into());
-    }
+        // library code does not print outside of the browser, arguments are still checked
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = format_args!( $( $t )* );
+    }}
 }
 
-mod framework;
-mod html;
-mod templating;
+pub mod a11y;
+pub mod analysis;
+pub mod backend;
+pub mod context;
+pub mod delegation;
+pub mod diagnostics;
+pub mod elements;
+pub mod framework;
+pub mod html;
+pub mod router;
+pub mod selector;
+pub mod store;
+pub mod styles;
+pub mod templating;
+pub mod testing;
+pub mod transition;
 mod utils;
-mod vdom;
+pub mod vdom;
 
 use framework::*;
 use vdom::SharableDomNode;
@@ -46,7 +63,7 @@ impl Msg {
     fn from_str(message: String) -> Self {
         match message.as_ref() {
             "increment" => Self::Increment,
-            _ => panic!(format!("Unknown message type {}!", message)),
+            _ => panic!("Unknown message type {}!", message),
         }
     }
 }
@@ -67,6 +84,16 @@ impl Component for Root {
             }
         }
     }
+
+    fn set_property(&mut self, name: &str, value: Option<&str>) -> bool {
+        match name {
+            "count" => {
+                self.count = value.and_then(|v| v.parse().ok()).unwrap_or(0);
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Lookup for Root {
@@ -76,18 +103,51 @@ impl Lookup for Root {
             _ => None,
         }
     }
+
+    fn lookup_list(&self, k: &String) -> Option<Vec<Rc<dyn Lookup>>> {
+        match k.as_ref() {
+            // one element for every click
+            "collection_to_loop" => Some(
+                (0..self.count)
+                    .map(|index| Rc::new(Looped { index }) as Rc<dyn Lookup>)
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+struct Looped {
+    index: i32,
+}
+
+impl Lookup for Looped {
+    fn lookup(&self, k: &String) -> Option<LookupValue> {
+        match k.as_str() {
+            "index" => Some(Box::new(self.index)),
+            _ => None,
+        }
+    }
 }
 
 // ************** Entrypoint **************
+fn register_components(framework: &mut Framework) {
+    let wrapper = ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Root::new()))))
+        .keys(&["count", "collection_to_loop"])
+        .messages(&["increment"]);
+
+    for diagnostic in framework.register_component_wrapper("root", wrapper, "main") {
+        log!("Template of root: {}", diagnostic);
+    }
+}
+
 #[wasm_bindgen]
 pub fn run() -> Framework {
     utils::set_panic_hook();
 
     let mut framework = Framework::new();
 
-    let wrapper = ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Root::new()))));
-
-    framework.register_component_wrapper("root", wrapper, "main");
+    register_components(&mut framework);
 
     framework
         .mount("main-container", "root")
@@ -95,3 +155,26 @@ pub fn run() -> Framework {
 
     framework
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+
+    #[test]
+    fn root_increments_count() {
+        let mut harness = Harness::with_page(include_str!("../www/index.html"));
+        register_components(&mut harness.framework);
+        harness.mount_into("main-container", "root");
+
+        assert_eq!(harness.attribute("#inner-p", "class").unwrap(), "0");
+
+        harness.click("button").tick();
+        assert_eq!(harness.attribute("#inner-p", "class").unwrap(), "1");
+
+        harness.click("button").click("button").tick();
+        assert_eq!(harness.attribute("#inner-p", "class").unwrap(), "3");
+        assert_eq!(harness.text("#inner-p"), "count is 3");
+        assert_eq!(harness.find_all("p").len(), 3 + 3);
+    }
+}
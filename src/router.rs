@@ -0,0 +1,1007 @@
+/// This package maps urls to registered components,
+/// matched component is rendered in to `<router-view>` and `<router-link>` navigates without reloading
+use crate::backend::DomBackend;
+use crate::framework::{ComponentWrapper, Dirty, DirtyInstance, Priority, Subscription};
+use crate::vdom::{Event, Handler};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Prefix of template keys that are looked up in the current route,
+/// e.g. `:title="$route.id"` for `/users/:id`, `$route.path` is the whole path,
+/// params the current route does not bind are empty
+pub const ROUTE_PREFIX: &str = "$route.";
+
+/// Tag of the element matched component is rendered in place of
+pub const ROUTER_VIEW: &str = "router-view";
+/// Tag of the link that navigates to the path in its `to` attribute
+pub const ROUTER_LINK: &str = "router-link";
+
+/// Redirects that lead to another redirect are followed at most this many times
+const MAX_REDIRECTS: usize = 10;
+
+/// How current path is stored in the url
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterMode {
+    /// `/users/1`, server has to serve the app for every route
+    History,
+    /// `/#/users/1`, works with any static server
+    Hash,
+}
+
+/// What guard decided to do with the navigation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Navigation {
+    Allow,
+    /// Stay on the current route, e.g. when there are unsaved changes
+    Cancel,
+    /// Go to another path instead
+    Redirect(String),
+}
+
+/// Navigation guards are asked about
+pub struct Transition<'a> {
+    pub path: &'a str,
+    pub from: Option<&'a RouteMatch>,
+    /// `None` when nothing matches the path
+    pub to: Option<&'a RouteMatch>,
+}
+
+pub type Guard = Rc<dyn Fn(&Transition) -> Navigation>;
+
+/// Registers component of a lazy route, called on the first visit of the route
+pub type Factory = Rc<dyn Fn() -> ComponentWrapper>;
+
+/// Path pattern mapped to a registered component, segments starting with `:` are params,
+/// `*` as the last segment matches the rest of the path,
+/// patterns of child routes are relative to their parent, child with empty pattern
+/// is rendered when the parent path matches exactly
+pub struct Route {
+    pub pattern: String,
+    pub component: &'static str,
+    pub children: Vec<Rc<Route>>,
+    before_enter: Option<Guard>,
+    before_leave: Option<Guard>,
+    factory: Option<Factory>,
+    loaded: Cell<bool>,
+}
+
+impl Route {
+    pub fn new(pattern: &str, component: &'static str) -> Self {
+        Route {
+            pattern: pattern.to_string(),
+            component,
+            children: vec![],
+            before_enter: None,
+            before_leave: None,
+            factory: None,
+            loaded: Cell::new(false),
+        }
+    }
+
+    /// Route that registers its component on the first visit instead of at startup
+    pub fn lazy(
+        pattern: &str,
+        component: &'static str,
+        factory: impl Fn() -> ComponentWrapper + 'static,
+    ) -> Self {
+        Route {
+            factory: Some(Rc::new(factory)),
+            ..Route::new(pattern, component)
+        }
+    }
+
+    /// Nested route, rendered in to `<router-view>` of this route's component
+    pub fn child(mut self, route: Route) -> Self {
+        self.children.push(Rc::new(route));
+        self
+    }
+
+    /// Ask guard before navigating to this route from anywhere outside of it
+    pub fn before_enter(mut self, guard: impl Fn(&Transition) -> Navigation + 'static) -> Self {
+        self.before_enter = Some(Rc::new(guard));
+        self
+    }
+
+    /// Ask guard before navigating away from this route
+    pub fn before_leave(mut self, guard: impl Fn(&Transition) -> Navigation + 'static) -> Self {
+        self.before_leave = Some(Rc::new(guard));
+        self
+    }
+
+    /// Names of params this route's own pattern binds
+    fn param_names(&self) -> impl Iterator<Item = &str> {
+        split(&self.pattern).filter_map(|part| part.strip_prefix(':'))
+    }
+
+    /// Match pattern against the beginning of the path, returns number of matched segments
+    fn match_prefix(
+        &self,
+        segments: &[&str],
+        params: &mut HashMap<String, String>,
+    ) -> Option<usize> {
+        let parts: Vec<&str> = split(&self.pattern).collect();
+
+        for (index, part) in parts.iter().enumerate() {
+            if *part == "*" {
+                return Some(segments.len());
+            }
+
+            let segment = segments.get(index)?;
+
+            match part.strip_prefix(':') {
+                Some(name) => {
+                    params.insert(name.to_string(), segment.to_string());
+                }
+                None if part == segment => {}
+                None => return None,
+            }
+        }
+
+        Some(parts.len())
+    }
+}
+
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Find chain of routes from top level to the innermost one that matches all segments
+fn resolve_in(
+    routes: &[Rc<Route>],
+    segments: &[&str],
+    chain: &mut Vec<Rc<Route>>,
+    params: &mut HashMap<String, String>,
+) -> bool {
+    for route in routes {
+        let mut route_params = params.clone();
+        let consumed = match route.match_prefix(segments, &mut route_params) {
+            Some(consumed) => consumed,
+            None => continue,
+        };
+
+        chain.push(Rc::clone(route));
+        let rest = &segments[consumed..];
+
+        let found = resolve_in(&route.children, rest, chain, &mut route_params) || rest.is_empty();
+
+        if found {
+            *params = route_params;
+            return true;
+        }
+
+        chain.pop();
+    }
+
+    false
+}
+
+/// Routes that match current path, from the top level one to the innermost one,
+/// with values of their params
+#[derive(Clone)]
+pub struct RouteMatch {
+    pub path: String,
+    pub params: HashMap<String, String>,
+    pub routes: Vec<Rc<Route>>,
+}
+
+impl RouteMatch {
+    /// Component rendered in to `<router-view>` nested `depth` outlets deep
+    pub fn component(&self, depth: usize) -> Option<&'static str> {
+        self.routes.get(depth).map(|route| route.component)
+    }
+
+    /// Number of leading routes both matches have in common, route with different
+    /// values of its params counts as left and entered again, e.g. `/users/1` to `/users/2`
+    fn common(&self, other: Option<&RouteMatch>) -> usize {
+        let other = match other {
+            Some(other) => other,
+            None => return 0,
+        };
+
+        self.routes
+            .iter()
+            .zip(other.routes.iter())
+            .take_while(|(a, b)| {
+                Rc::ptr_eq(a, b)
+                    && a.param_names()
+                        .all(|name| self.params.get(name) == other.params.get(name))
+            })
+            .count()
+    }
+}
+
+/// Depth of `<router-view>` provided through context to components rendered in outlets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutletDepth(pub usize);
+
+/// Registers component of a lazy route in the framework,
+/// returns `false` when the framework is busy and could not take it
+pub type Registrar = Box<dyn Fn(&'static str, ComponentWrapper) -> bool>;
+
+pub struct Router {
+    mode: RouterMode,
+    routes: Vec<Rc<Route>>,
+    current: RefCell<Option<RouteMatch>>,
+    path: RefCell<String>,
+    /// Position of the history entry of the current route, see `DomBackend::history_position`
+    position: Cell<usize>,
+    /// Navigation cancelled by a guard is being undone, events it fires are ignored
+    restoring: Cell<bool>,
+    /// Components that render something that depends on the current route
+    watchers: RefCell<Vec<Weak<RefCell<Dirty>>>>,
+    backend: RefCell<Option<Rc<dyn DomBackend>>>,
+    registrar: RefCell<Option<Registrar>>,
+    /// Handle to this router for callbacks that run later, set by `start`
+    this: RefCell<Weak<Router>>,
+    /// Window listeners that keep router in sync with back and forward buttons
+    listeners: RefCell<Vec<(Subscription, Handler)>>,
+}
+
+impl Router {
+    pub fn new(mode: RouterMode) -> Self {
+        Router {
+            mode,
+            routes: vec![],
+            current: RefCell::new(None),
+            path: RefCell::new("/".to_string()),
+            position: Cell::new(0),
+            restoring: Cell::new(false),
+            watchers: RefCell::new(vec![]),
+            backend: RefCell::new(None),
+            registrar: RefCell::new(None),
+            this: RefCell::new(Weak::new()),
+            listeners: RefCell::new(vec![]),
+        }
+    }
+
+    /// Add route, routes are matched in the order they were added
+    pub fn route(self, pattern: &str, component: &'static str) -> Self {
+        self.add_route(Route::new(pattern, component))
+    }
+
+    /// Add route with guards, children or a lazy factory
+    pub fn add_route(mut self, route: Route) -> Self {
+        self.routes.push(Rc::new(route));
+        self
+    }
+
+    pub fn mode(&self) -> RouterMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> String {
+        self.path.borrow().clone()
+    }
+
+    pub fn current(&self) -> Option<RouteMatch> {
+        self.current.borrow().clone()
+    }
+
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.current.borrow().as_ref()?.params.get(name).cloned()
+    }
+
+    /// Value for `$route.` template keys
+    pub fn lookup(&self, key: &str) -> Option<String> {
+        match key {
+            "path" => Some(self.path()),
+            _ => self.param(key),
+        }
+    }
+
+    /// Url of a link that leads to given path
+    pub fn href(&self, path: &str) -> String {
+        match self.mode {
+            RouterMode::History => path.to_string(),
+            RouterMode::Hash => format!("#{}", path),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Option<RouteMatch> {
+        // query and fragment are kept in the url but take no part in matching
+        let route = path.split(['?', '#']).next().unwrap_or_default();
+        let segments: Vec<&str> = split(route).collect();
+        let mut routes = vec![];
+        let mut params = HashMap::new();
+
+        if resolve_in(&self.routes, &segments, &mut routes, &mut params) {
+            Some(RouteMatch {
+                path: path.to_string(),
+                params,
+                routes,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Path stored in the url of the current page
+    fn location_path(&self, backend: &dyn DomBackend) -> String {
+        let location = backend.location();
+
+        let path = match self.mode {
+            RouterMode::History => location.split('#').next().unwrap_or_default(),
+            RouterMode::Hash => location.split_once('#').map_or("", |(_, hash)| hash),
+        };
+
+        if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{}", path)
+        }
+    }
+
+    /// Ask guards of routes that are left and entered, following redirects,
+    /// returns where navigation should end up or `None` when it is cancelled
+    fn transition(&self, path: &str) -> Option<(String, Option<RouteMatch>)> {
+        let from = self.current();
+        let mut path = path.to_string();
+
+        for _ in 0..MAX_REDIRECTS {
+            let to = self.resolve(&path);
+            let transition = Transition {
+                path: &path,
+                from: from.as_ref(),
+                to: to.as_ref(),
+            };
+
+            let common = from.as_ref().map_or(0, |from| from.common(to.as_ref()));
+            let leaving = from
+                .iter()
+                .flat_map(|from| from.routes[common..].iter().rev())
+                .filter_map(|route| route.before_leave.clone());
+            let entering = to
+                .iter()
+                .flat_map(|to| to.routes[common..].iter())
+                .filter_map(|route| route.before_enter.clone());
+
+            let decision = leaving
+                .chain(entering)
+                .map(|guard| guard(&transition))
+                .find(|navigation| *navigation != Navigation::Allow)
+                .unwrap_or(Navigation::Allow);
+
+            match decision {
+                Navigation::Allow => return Some((path, to)),
+                Navigation::Cancel => return None,
+                Navigation::Redirect(redirect) => path = redirect,
+            }
+        }
+
+        log!("Too many redirects navigating to {}", path);
+        None
+    }
+
+    /// Register components of lazy routes that are about to be rendered,
+    /// returns `false` when some of them could not be registered yet
+    fn load(&self, matched: &RouteMatch) -> bool {
+        let registrar = self.registrar.borrow();
+        let mut loaded = true;
+
+        for route in matched.routes.iter() {
+            if let (Some(factory), Some(registrar)) = (&route.factory, registrar.as_ref()) {
+                if !route.loaded.get() {
+                    let registered = registrar(route.component, factory());
+                    route.loaded.set(registered);
+                    loaded &= registered;
+                }
+            }
+        }
+
+        loaded
+    }
+
+    /// Render components that depend on the route again
+    fn set_route(&self, path: &str, matched: Option<RouteMatch>) {
+        if let Some(matched) = matched {
+            if !self.load(&matched) {
+                self.set_route_later(path, matched);
+                return;
+            }
+            *self.current.borrow_mut() = Some(matched);
+        } else {
+            *self.current.borrow_mut() = None;
+        }
+
+        *self.path.borrow_mut() = path.to_string();
+
+        let watchers: Vec<_> = self
+            .watchers
+            .borrow()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+
+        for dirty in watchers {
+            dirty.borrow_mut().mark(Priority::User);
+        }
+    }
+
+    /// Switch route once the framework is done with what it is doing, e.g. rendering
+    /// a component that navigated, so components of lazy routes can be registered
+    fn set_route_later(&self, path: &str, matched: RouteMatch) {
+        let backend = match self.backend.borrow().clone() {
+            Some(backend) => backend,
+            None => return,
+        };
+
+        let router = self.this.borrow().clone();
+        let path = path.to_string();
+        let url = backend.location();
+
+        backend.clone().queue_microtask(Box::new(move || {
+            // navigation that happened in the meantime wins
+            if let Some(router) = router.upgrade() {
+                if backend.location() == url {
+                    router.set_route(&path, Some(matched));
+                }
+            }
+        }));
+    }
+
+    /// Go to given path and add it to the browser history, unless a guard cancels it
+    pub fn navigate(&self, path: &str) {
+        if *self.path.borrow() == path {
+            return;
+        }
+
+        let (path, matched) = match self.transition(path) {
+            Some(target) => target,
+            None => return,
+        };
+
+        if *self.path.borrow() == path {
+            return;
+        }
+
+        if let Some(backend) = self.backend.borrow().as_ref() {
+            self.position.set(self.position.get() + 1);
+            backend.push_history(&self.href(&path), self.position.get());
+        }
+
+        self.set_route(&path, matched);
+    }
+
+    /// Take over path from the url when user goes back or forward,
+    /// user is moved back to the entry they came from when a guard cancels the navigation
+    fn sync(&self) {
+        let backend = match self.backend.borrow().clone() {
+            Some(backend) => backend,
+            None => return,
+        };
+
+        let path = self.location_path(&*backend);
+        if *self.path.borrow() == path {
+            self.restoring.set(false);
+            return;
+        }
+
+        // hash mode hears about the same navigation from both popstate and hashchange
+        if self.restoring.get() {
+            return;
+        }
+
+        let stamped = backend.history_position();
+
+        match self.transition(&path) {
+            Some((target, matched)) => {
+                let position = stamped.unwrap_or(self.position.get() + 1);
+                if target != path {
+                    backend.replace_history(&self.href(&target), position);
+                } else if stamped.is_none() {
+                    backend.replace_history(&backend.location(), position);
+                }
+
+                self.position.set(position);
+                self.set_route(&target, matched);
+            }
+            None => {
+                // entries the page did not add come right after the one user was on
+                let delta = match stamped {
+                    Some(position) => self.position.get() as i32 - position as i32,
+                    None => -1,
+                };
+
+                self.restoring.set(true);
+                backend.go(delta);
+            }
+        }
+    }
+
+    /// Mark component dirty when the route changes
+    pub fn watch(&self, dirty: &DirtyInstance) {
+        let mut watchers = self.watchers.borrow_mut();
+        watchers.retain(|watcher| watcher.strong_count() > 0);
+
+        if !watchers
+            .iter()
+            .any(|watcher| watcher.as_ptr() == Rc::as_ptr(dirty))
+        {
+            watchers.push(Rc::downgrade(dirty));
+        }
+    }
+
+    /// Read initial path from the url and follow history changes,
+    /// components of lazy routes are registered through the registrar
+    pub fn start(self: &Rc<Self>, backend: Rc<dyn DomBackend>, registrar: Registrar) {
+        *self.registrar.borrow_mut() = Some(registrar);
+        *self.this.borrow_mut() = Rc::downgrade(self);
+        *self.backend.borrow_mut() = Some(Rc::clone(&backend));
+
+        // entry page was opened with gets a position too, so user can be brought back to it
+        let position = backend.history_position().unwrap_or_default();
+        backend.replace_history(&backend.location(), position);
+        self.position.set(position);
+
+        let path = self.location_path(&*backend);
+        match self.transition(&path) {
+            Some((target, matched)) => {
+                if target != path {
+                    backend.replace_history(&self.href(&target), position);
+                }
+                self.set_route(&target, matched);
+            }
+            None => self.set_route(&path, None),
+        }
+
+        let events: &[&str] = match self.mode {
+            RouterMode::History => &["popstate"],
+            RouterMode::Hash => &["popstate", "hashchange"],
+        };
+
+        for event in events {
+            let router = Rc::downgrade(self);
+            let subscription = Subscription::window(event, "navigate");
+            let handler = Handler::new("navigate", move |_: &Event| {
+                if let Some(router) = router.upgrade() {
+                    router.sync();
+                }
+            });
+
+            backend.subscribe(&subscription, &handler);
+            self.listeners.borrow_mut().push((subscription, handler));
+        }
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        if let Some(backend) = self.backend.borrow().as_ref() {
+            for (subscription, handler) in self.listeners.borrow().iter() {
+                backend.unsubscribe(subscription, handler);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{stub, Harness};
+    use crate::vdom::{Event, Modifiers};
+    use std::cell::Cell;
+
+    fn matches(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+        Router::new(RouterMode::History)
+            .route(pattern, "page")
+            .resolve(path)
+            .map(|matched| matched.params)
+    }
+
+    #[test]
+    fn patterns_match_params_and_wildcards() {
+        let params = matches("/users/:id/posts/:post", "/users/7/posts/42");
+        let params = params.unwrap();
+        assert_eq!(params["id"], "7");
+        assert_eq!(params["post"], "42");
+
+        assert!(matches("/", "/").is_some());
+        assert!(matches("/users", "/users/").is_some());
+        assert!(matches("/users", "/users/7").is_none());
+        assert!(matches("/users/:id", "/users").is_none());
+        assert!(matches("/files/*", "/files/a/b").is_some());
+    }
+
+    #[test]
+    fn routes_are_matched_in_order() {
+        let router = Router::new(RouterMode::History)
+            .route("/users/new", "signup")
+            .route("/users/:id", "profile");
+
+        assert_eq!(
+            router.resolve("/users/new").unwrap().component(0),
+            Some("signup")
+        );
+        assert_eq!(
+            router.resolve("/users/3").unwrap().component(0),
+            Some("profile")
+        );
+        assert!(router.resolve("/posts").is_none());
+    }
+
+    #[test]
+    fn href_depends_on_mode() {
+        assert_eq!(Router::new(RouterMode::History).href("/a"), "/a");
+        assert_eq!(Router::new(RouterMode::Hash).href("/a"), "#/a");
+    }
+
+    fn harness(mode: RouterMode, url: &str) -> Harness {
+        let mut harness = Harness::new();
+        harness.backend().visit(url);
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<nav><router-link to=\"/\">home</router-link>\
+             <router-link to=\"/users/7\" class=\"user\">user</router-link></nav>\
+             <router-view><p>not found</p></router-view>",
+        );
+        framework.register_component_template("home", stub(), "<h1>home</h1>");
+        framework.register_component_template(
+            "profile",
+            stub(),
+            "<h1 :title=\"$route.id\">profile</h1>",
+        );
+        framework.use_router(Rc::new(
+            Router::new(mode)
+                .route("/", "home")
+                .route("/users/:id", "profile"),
+        ));
+
+        harness.mount("shell");
+        harness
+    }
+
+    #[test]
+    fn history_mode_renders_matched_component() {
+        let mut harness = harness(RouterMode::History, "/");
+        assert_eq!(harness.text("h1"), "home");
+        assert_eq!(harness.attribute("a.user", "href").unwrap(), "/users/7");
+
+        harness.click("a.user").settle();
+        assert_eq!(harness.text("h1"), "profile");
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "7");
+        assert_eq!(harness.backend().location(), "/users/7");
+
+        // following the link to the current page does not add history entries
+        harness.click("a.user").settle();
+        assert_eq!(harness.backend().history_length(), 3);
+
+        harness.backend().back();
+        harness.settle();
+        assert_eq!(harness.text("h1"), "home");
+    }
+
+    #[test]
+    fn params_of_other_routes_are_empty() {
+        let mut harness = Harness::new();
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<h1 :title=\"$route.id\">{{ $route.id }}</h1>\
+             <router-link to=\"/users/7\">user</router-link><router-view></router-view>",
+        );
+        framework.register_component_template("home", stub(), "<p>home</p>");
+        framework.register_component_template("profile", stub(), "<p>profile</p>");
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History)
+                .route("/", "home")
+                .route("/users/:id", "profile"),
+        ));
+        harness.mount("shell");
+
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "");
+        assert_eq!(harness.text("h1"), "");
+
+        harness.click("a").settle();
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "7");
+        assert_eq!(harness.text("h1"), "7");
+    }
+
+    #[test]
+    fn only_plain_left_clicks_are_intercepted() {
+        let mut harness = harness(RouterMode::History, "/");
+
+        let modified = Event::with_modifiers(
+            "click",
+            Modifiers {
+                ctrl: true,
+                ..Modifiers::default()
+            },
+        );
+        let middle = Event::with_button("click", 1);
+        for event in [modified, middle] {
+            harness.dispatch("a.user", event.clone()).settle();
+            assert!(!event.default_prevented());
+            assert_eq!(harness.text("h1"), "home");
+            assert_eq!(harness.backend().location(), "/");
+        }
+
+        let plain = Event::new("click");
+        harness.dispatch("a.user", plain.clone()).settle();
+        assert!(plain.default_prevented());
+        assert_eq!(harness.text("h1"), "profile");
+    }
+
+    #[test]
+    fn unsafe_dynamic_links_are_not_rendered() {
+        let mut harness = Harness::new();
+        harness.backend().visit("/go/javascript:alert(1)");
+
+        let errors = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&errors);
+        let framework = &mut harness.framework;
+        framework.on_error(move |_| counter.set(counter.get() + 1));
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<router-link :to=\"$route.to\">next</router-link><router-view></router-view>",
+        );
+        framework.register_component_template("page", stub(), "<h1>page</h1>");
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History).route("/go/:to", "page"),
+        ));
+        harness.mount("shell");
+
+        assert_eq!(errors.get(), 1);
+        assert_eq!(harness.attribute("a", "href"), None);
+
+        let click = Event::new("click");
+        harness.dispatch("a", click.clone()).settle();
+        assert!(!click.default_prevented());
+        assert_eq!(harness.backend().location(), "/go/javascript:alert(1)");
+    }
+
+    #[test]
+    fn query_is_kept_but_not_matched() {
+        let mut harness = harness(RouterMode::History, "/users/4?tab=posts#top");
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "4");
+
+        harness.backend().visit("/users/5?tab=likes");
+        harness.backend().visit("/");
+        harness.backend().back();
+        harness.settle();
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "5");
+        assert_eq!(harness.backend().location(), "/users/5?tab=likes");
+    }
+
+    #[test]
+    fn hash_mode_follows_url_fragment() {
+        let mut harness = harness(RouterMode::Hash, "/#/users/3");
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "3");
+        assert_eq!(harness.attribute("a", "href").unwrap(), "#/");
+
+        harness.click("a").settle();
+        assert_eq!(harness.text("h1"), "home");
+        assert_eq!(harness.backend().location(), "/#/");
+
+        harness.backend().visit("#/missing");
+        harness.settle();
+        assert!(harness.find("h1").is_none());
+        assert_eq!(harness.text("p"), "not found");
+    }
+
+    #[test]
+    fn nested_routes_render_in_child_outlets() {
+        let mut harness = Harness::new();
+        harness.backend().visit("/users/7");
+
+        let framework = &mut harness.framework;
+        framework.register_component_template("shell", stub(), "<router-view></router-view>");
+        framework.register_component_template(
+            "profile",
+            stub(),
+            "<h1 :title=\"$route.id\">profile</h1><router-view></router-view>",
+        );
+        framework.register_component_template(
+            "summary",
+            stub(),
+            "<p>summary</p><router-link to=\"/users/7/posts\">posts</router-link>",
+        );
+        framework.register_component_template(
+            "posts",
+            stub(),
+            "<p>posts</p><router-link to=\"/users/7\">summary</router-link>",
+        );
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History).add_route(
+                Route::new("/users/:id", "profile")
+                    .child(Route::new("", "summary"))
+                    .child(Route::new("posts", "posts")),
+            ),
+        ));
+        harness.mount("shell");
+
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "7");
+        assert_eq!(harness.text("p"), "summary");
+
+        harness.click("a").settle();
+        assert_eq!(harness.attribute("h1", "title").unwrap(), "7");
+        assert_eq!(harness.text("p"), "posts");
+
+        harness.click("a").settle();
+        assert_eq!(harness.text("p"), "summary");
+
+        harness.backend().back();
+        harness.settle();
+        assert_eq!(harness.text("p"), "posts");
+    }
+
+    #[test]
+    fn pages_with_several_roots_keep_following_siblings() {
+        let mut harness = Harness::new();
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<router-view></router-view><footer><router-link to=\"/about\">about</router-link></footer>",
+        );
+        framework.register_component_template("home", stub(), "<h1>home</h1>");
+        framework.register_component_template("about", stub(), "<h1>about</h1><p>more</p>");
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History)
+                .route("/", "home")
+                .route("/about", "about"),
+        ));
+        harness.mount("shell");
+
+        let footer = harness.find("footer").unwrap();
+        harness.click("a").settle();
+        assert_eq!(
+            harness.html_of("app"),
+            "<h1>about</h1><p>more</p><footer><a href=\"/about\">about</a></footer>"
+        );
+        assert!(footer.is_same(&harness.find("footer").unwrap()));
+
+        harness.backend().back();
+        harness.settle();
+        assert_eq!(
+            harness.html_of("app"),
+            "<h1>home</h1><footer><a href=\"/about\">about</a></footer>"
+        );
+        assert!(footer.is_same(&harness.find("footer").unwrap()));
+    }
+
+    #[test]
+    fn guards_cancel_and_redirect() {
+        let unsaved = Rc::new(Cell::new(true));
+        let mut harness = Harness::new();
+        harness.backend().visit("/editor");
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<router-link id=\"home\" to=\"/\">home</router-link>\
+             <router-link id=\"admin\" to=\"/admin\">admin</router-link>\
+             <router-view></router-view>",
+        );
+        framework.register_component_template("home", stub(), "<h1>home</h1>");
+        framework.register_component_template("editor", stub(), "<h1>editor</h1>");
+        framework.register_component_template("admin", stub(), "<h1>admin</h1>");
+
+        let guarded = Rc::clone(&unsaved);
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History)
+                .route("/", "home")
+                .add_route(Route::new("/editor", "editor").before_leave(move |_| {
+                    if guarded.get() {
+                        Navigation::Cancel
+                    } else {
+                        Navigation::Allow
+                    }
+                }))
+                .add_route(
+                    Route::new("/admin", "admin")
+                        .before_enter(|_| Navigation::Redirect("/".to_string())),
+                ),
+        ));
+        harness.mount("shell");
+
+        harness.click("#home").settle();
+        assert_eq!(harness.text("h1"), "editor");
+        assert_eq!(harness.backend().location(), "/editor");
+
+        unsaved.set(false);
+        harness.click("#admin").settle();
+        assert_eq!(harness.text("h1"), "home");
+        assert_eq!(harness.backend().location(), "/");
+    }
+
+    #[test]
+    fn guards_run_when_params_change() {
+        let entered = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&entered);
+        let router = Router::new(RouterMode::History).add_route(
+            Route::new("/users/:id", "layout")
+                .before_enter(move |_| {
+                    counter.set(counter.get() + 1);
+                    Navigation::Allow
+                })
+                .child(Route::new("", "profile"))
+                .child(Route::new("posts", "posts")),
+        );
+        router.set_route("/users/1", router.resolve("/users/1"));
+
+        assert!(router.transition("/users/1/posts").is_some());
+        assert_eq!(entered.get(), 0);
+
+        assert!(router.transition("/users/2").is_some());
+        assert_eq!(entered.get(), 1);
+    }
+
+    #[test]
+    fn back_button_respects_guards() {
+        let mut harness = Harness::new();
+        harness.backend().visit("/");
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<router-link to=\"/editor\">edit</router-link><router-view></router-view>",
+        );
+        framework.register_component_template("home", stub(), "<h1>home</h1>");
+        framework.register_component_template("editor", stub(), "<h1>editor</h1>");
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History)
+                .route("/", "home")
+                .add_route(Route::new("/editor", "editor").before_leave(|_| Navigation::Cancel)),
+        ));
+        harness.mount("shell");
+
+        harness.click("a").settle();
+        assert_eq!(harness.text("h1"), "editor");
+
+        harness.backend().back();
+        harness.settle();
+        assert_eq!(harness.text("h1"), "editor");
+        assert_eq!(harness.backend().location(), "/editor");
+        // user is moved forward again, the entry they went back to is left as it was
+        let urls = ["/", "/", "/editor"].map(String::from).to_vec();
+        assert_eq!(harness.backend().history(), (urls.clone(), 2));
+
+        harness.backend().back();
+        harness.settle();
+        assert_eq!(harness.backend().history(), (urls, 2));
+    }
+
+    #[test]
+    fn lazy_routes_register_components_on_first_visit() {
+        let loads = Rc::new(Cell::new(0));
+        let mut harness = Harness::new();
+        harness.backend().visit("/");
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<router-link to=\"/reports\">reports</router-link><router-view></router-view>",
+        );
+        framework.register_component_template("home", stub(), "<h1>home</h1>");
+
+        let counter = Rc::clone(&loads);
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History)
+                .route("/", "home")
+                .add_route(Route::lazy("/reports", "reports", move || {
+                    counter.set(counter.get() + 1);
+                    stub().template("<h1>reports</h1>")
+                })),
+        ));
+        harness.mount("shell");
+        assert_eq!(loads.get(), 0);
+
+        harness.click("a").settle();
+        assert_eq!(harness.text("h1"), "reports");
+
+        harness.backend().back();
+        harness.click("a").settle();
+        assert_eq!(loads.get(), 1);
+    }
+}
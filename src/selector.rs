@@ -0,0 +1,143 @@
+/// This package matches in-memory nodes against css selectors, it is what
+/// `querySelector` of the in-memory document and test harness lookups are built on
+use crate::backend::MemoryNode;
+
+/// Part of a selector that describes single element, e.g. `button#save.primary[type=submit]`
+#[derive(Debug, Default)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Compound {
+    fn parse(input: &str) -> Self {
+        let mut compound = Compound::default();
+        let mut chars = input.chars().peekable();
+        let mut tag = String::new();
+
+        while let Some(&c) = chars.peek() {
+            if c == '#' || c == '.' || c == '[' {
+                break;
+            }
+            tag.push(c);
+            chars.next();
+        }
+
+        if !tag.is_empty() && tag != "*" {
+            compound.tag = Some(tag.to_lowercase());
+        }
+
+        while let Some(c) = chars.next() {
+            match c {
+                '#' | '.' => {
+                    let mut name = String::new();
+                    while let Some(&n) = chars.peek() {
+                        if n == '#' || n == '.' || n == '[' {
+                            break;
+                        }
+                        name.push(n);
+                        chars.next();
+                    }
+
+                    if c == '#' {
+                        compound.id = Some(name);
+                    } else {
+                        compound.classes.push(name);
+                    }
+                }
+                '[' => {
+                    let body: String = chars.by_ref().take_while(|&n| n != ']').collect();
+                    let attribute = match body.find('=') {
+                        Some(i) => (
+                            body[..i].trim().to_string(),
+                            Some(
+                                body[i + 1..]
+                                    .trim()
+                                    .trim_matches(|q| q == '"' || q == '\'')
+                                    .to_string(),
+                            ),
+                        ),
+                        None => (body.trim().to_string(), None),
+                    };
+                    compound.attributes.push(attribute);
+                }
+                _ => panic!("unexpected {:?} in selector {:?}", c, input),
+            }
+        }
+
+        compound
+    }
+
+    fn matches(&self, node: &MemoryNode) -> bool {
+        let tag = match node.tag() {
+            Some(tag) => tag,
+            None => return false,
+        };
+
+        if self.tag.as_ref().is_some_and(|t| *t != tag) {
+            return false;
+        }
+
+        if self.id.is_some() && node.attribute("id") != self.id {
+            return false;
+        }
+
+        let classes = node.attribute("class").unwrap_or_default();
+        if !self
+            .classes
+            .iter()
+            .all(|class| classes.split_whitespace().any(|c| c == class))
+        {
+            return false;
+        }
+
+        self.attributes
+            .iter()
+            .all(|(name, value)| match (node.attribute(name), value) {
+                (Some(actual), Some(expected)) => actual == *expected,
+                (Some(_), None) => true,
+                (None, _) => false,
+            })
+    }
+}
+
+/// Small subset of css selectors: tag, `#id`, `.class`, `[attr]`, `[attr=value]`
+/// and descendant combinator
+#[derive(Debug)]
+pub struct Selector {
+    compounds: Vec<Compound>,
+}
+
+impl Selector {
+    pub fn parse(input: &str) -> Self {
+        Selector {
+            compounds: input.split_whitespace().map(Compound::parse).collect(),
+        }
+    }
+
+    pub fn matches(&self, node: &MemoryNode) -> bool {
+        let (last, ancestors) = match self.compounds.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        if !last.matches(node) {
+            return false;
+        }
+
+        // match remaining compounds against ancestors, closest first
+        let mut remaining = ancestors.iter().rev().peekable();
+        let mut current = node.parent();
+
+        while let (Some(compound), Some(ancestor)) = (remaining.peek(), current) {
+            if compound.matches(&ancestor) {
+                remaining.next();
+            }
+            current = ancestor.parent();
+        }
+
+        remaining.peek().is_none()
+    }
+}
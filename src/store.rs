@@ -0,0 +1,288 @@
+/// This package contains global application state that is shared between components,
+/// state is changed only by actions passed through a reducer
+use crate::framework::{Dirty, DirtyInstance, LookupValue, Priority};
+use std::any::Any;
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::{Rc, Weak};
+
+/// Prefix of template keys that are looked up in the store instead of the component,
+/// e.g. `:title="$store.user.name"`
+pub const STORE_PREFIX: &str = "$store.";
+
+/// Value produced by a selector, compared with the previous one to find out if it changed
+pub trait Selected: Display {
+    fn as_any(&self) -> &dyn Any;
+    fn same(&self, other: &dyn Selected) -> bool;
+}
+
+impl<T: PartialEq + Display + 'static> Selected for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn same(&self, other: &dyn Selected) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+}
+
+type Selector<S> = Box<dyn Fn(&S) -> Box<dyn Selected>>;
+type Reducer<S, A> = Box<dyn Fn(&mut S, A)>;
+
+/// Component that rendered a selected value, with the value it has seen
+struct Watcher {
+    key: String,
+    last: Box<dyn Selected>,
+    dirty: Weak<RefCell<Dirty>>,
+}
+
+/// Store as seen by templates, independent of state and action types
+pub trait SharedStore {
+    /// Current value of a named selector
+    fn lookup(&self, key: &str) -> Option<LookupValue>;
+    /// Mark component dirty once value of a named selector changes
+    fn watch(&self, key: &str, dirty: &DirtyInstance);
+}
+
+pub struct Store<S, A> {
+    state: RefCell<S>,
+    reducer: Reducer<S, A>,
+    selectors: HashMap<String, Selector<S>>,
+    watchers: RefCell<Vec<Watcher>>,
+}
+
+impl<S: 'static, A: 'static> Store<S, A> {
+    pub fn new(state: S, reducer: impl Fn(&mut S, A) + 'static) -> Self {
+        Store {
+            state: RefCell::new(state),
+            reducer: Box::new(reducer),
+            selectors: HashMap::new(),
+            watchers: RefCell::new(vec![]),
+        }
+    }
+
+    /// Name a slice of the state, templates refer to it as `$store.<key>`
+    pub fn selector<T: PartialEq + Display + 'static>(
+        mut self,
+        key: &str,
+        selector: impl Fn(&S) -> T + 'static,
+    ) -> Self {
+        self.selectors.insert(
+            key.to_string(),
+            Box::new(move |state| Box::new(selector(state))),
+        );
+        self
+    }
+
+    pub fn state(&self) -> Ref<'_, S> {
+        self.state.borrow()
+    }
+
+    fn select(&self, key: &str) -> Option<Box<dyn Selected>> {
+        let selector = self.selectors.get(key)?;
+        Some(selector(&self.state.borrow()))
+    }
+
+    /// Run action through the reducer and mark components whose selected values changed
+    pub fn dispatch(&self, action: A) {
+        (self.reducer)(&mut self.state.borrow_mut(), action);
+
+        let mut changed = vec![];
+
+        self.watchers.borrow_mut().retain_mut(|watcher| {
+            let dirty = match watcher.dirty.upgrade() {
+                Some(dirty) => dirty,
+                None => return false,
+            };
+
+            if let Some(value) = self.select(&watcher.key) {
+                if !value.same(&*watcher.last) {
+                    watcher.last = value;
+                    changed.push(dirty);
+                }
+            }
+
+            true
+        });
+
+        // watchers are released before scheduling renders of changed components
+        for dirty in changed {
+            dirty.borrow_mut().mark(Priority::User);
+        }
+    }
+}
+
+impl<S: 'static, A: 'static> SharedStore for Store<S, A> {
+    fn lookup(&self, key: &str) -> Option<LookupValue> {
+        self.select(key).map(|value| Box::new(value) as LookupValue)
+    }
+
+    fn watch(&self, key: &str, dirty: &DirtyInstance) {
+        let last = match self.select(key) {
+            Some(value) => value,
+            None => return,
+        };
+
+        let id = dirty.borrow().id;
+        let mut watchers = self.watchers.borrow_mut();
+        let existing = watchers.iter_mut().find(|watcher| {
+            watcher.key == key && watcher.dirty.upgrade().is_some_and(|d| d.borrow().id == id)
+        });
+
+        match existing {
+            Some(watcher) => watcher.last = last,
+            None => watchers.push(Watcher {
+                key: key.to_string(),
+                last,
+                dirty: Rc::downgrade(dirty),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::*;
+    use crate::testing::Harness;
+    use crate::vdom::SharableDomNode;
+    use std::cell::Cell;
+
+    struct User {
+        name: String,
+        visits: u32,
+    }
+
+    enum Action {
+        Rename(String),
+        Visit,
+    }
+
+    fn store() -> Store<User, Action> {
+        let user = User {
+            name: "bob".to_string(),
+            visits: 0,
+        };
+
+        Store::new(user, |user: &mut User, action| match action {
+            Action::Rename(name) => user.name = name,
+            Action::Visit => user.visits += 1,
+        })
+        .selector("user.name", |user: &User| user.name.clone())
+        .selector("user.visits", |user: &User| user.visits)
+    }
+
+    #[test]
+    fn dispatch_runs_reducer() {
+        let store = store();
+        store.dispatch(Action::Visit);
+        store.dispatch(Action::Rename("alice".to_string()));
+
+        assert_eq!(store.state().visits, 1);
+        assert_eq!(store.lookup("user.name").unwrap().to_string(), "alice");
+        assert!(store.lookup("user.missing").is_none());
+    }
+
+    #[test]
+    fn watchers_are_marked_only_when_slice_changes() {
+        let store = store();
+        let dirty = Rc::new(RefCell::new(Dirty::new()));
+        dirty.borrow_mut().dirty = false;
+        store.watch("user.name", &dirty);
+
+        store.dispatch(Action::Visit);
+        assert!(!dirty.borrow().dirty);
+
+        store.dispatch(Action::Rename("bob".to_string()));
+        assert!(!dirty.borrow().dirty);
+
+        store.dispatch(Action::Rename("alice".to_string()));
+        assert!(dirty.borrow().dirty);
+    }
+
+    #[test]
+    fn dropped_watchers_are_forgotten() {
+        let store = store();
+        let dirty = Rc::new(RefCell::new(Dirty::new()));
+        store.watch("user.visits", &dirty);
+        store.watch("user.visits", &dirty);
+        assert_eq!(store.watchers.borrow().len(), 1);
+
+        drop(dirty);
+        store.dispatch(Action::Visit);
+        assert!(store.watchers.borrow().is_empty());
+    }
+
+    /// Component that counts its renders and sends actions to the store on click
+    struct Visitor {
+        store: Rc<Store<User, Action>>,
+        renders: Cell<usize>,
+    }
+
+    impl Component for Visitor {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, message: String) -> bool {
+            match message.as_ref() {
+                "visit" => self.store.dispatch(Action::Visit),
+                "rename" => self.store.dispatch(Action::Rename("alice".to_string())),
+                _ => {}
+            }
+            false
+        }
+    }
+
+    impl Lookup for Visitor {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "renders" => {
+                    self.renders.set(self.renders.get() + 1);
+                    Some(Box::new(self.renders.get()))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn templates_read_store_and_render_on_change() {
+        let store = Rc::new(store());
+        let mut harness = Harness::with_page("<div id=\"badge\"></div><div id=\"app\"></div>");
+        harness.framework.use_store(store.clone());
+
+        let wrapper = |store: &Rc<Store<User, Action>>| {
+            let store = Rc::clone(store);
+            ComponentWrapper::new(Box::new(move || {
+                Rc::new(RefCell::new(Visitor {
+                    store: Rc::clone(&store),
+                    renders: Cell::new(0),
+                }))
+            }))
+        };
+
+        harness.framework.register_component_template(
+            "badge",
+            wrapper(&store),
+            "<span :title=\"$store.user.name\" :data-renders=\"renders\"></span>",
+        );
+        harness.framework.register_component_template(
+            "page",
+            wrapper(&store),
+            "<button id=\"visit\" @click=\"visit\" :title=\"$store.user.visits\"></button>\
+             <button id=\"rename\" @click=\"rename\"></button>",
+        );
+        harness.mount_into("badge", "badge");
+        harness.mount("page");
+
+        harness.click("#visit").settle();
+        assert_eq!(harness.attribute("#visit", "title").unwrap(), "1");
+        assert_eq!(harness.attribute("span", "data-renders").unwrap(), "1");
+
+        harness.click("#rename").settle();
+        assert_eq!(harness.attribute("span", "title").unwrap(), "alice");
+        assert_eq!(harness.attribute("span", "data-renders").unwrap(), "2");
+    }
+}
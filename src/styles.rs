@@ -0,0 +1,308 @@
+/// This package implements `<style scoped>` blocks of component templates,
+/// selectors are rewritten to match only elements realized from the same template
+use crate::templating::{Attribute, Node, NodeData, Template};
+
+/// Prefix of the attribute every element of a component with scoped style carries
+pub const SCOPE_PREFIX: &str = "data-s-";
+
+/// Stylesheet taken out of a template, with the attribute its selectors are scoped by
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopedStyle {
+    pub attribute: String,
+    pub css: String,
+}
+
+/// Remove `<style scoped>` blocks from the template, mark every element of the template
+/// with a scope attribute and return stylesheet that only applies to marked elements
+pub fn extract_scoped_style(template: &mut Template) -> Option<ScopedStyle> {
+    let mut blocks = vec![];
+    take_scoped_blocks(template, &mut blocks);
+
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let source = blocks.concat();
+    // same style always gets the same attribute, so server and browser renders agree
+    let attribute = format!("{}{:08x}", SCOPE_PREFIX, fnv1a(&source));
+
+    mark(template, &attribute);
+
+    Some(ScopedStyle {
+        css: scope_css(&source, &attribute),
+        attribute,
+    })
+}
+
+fn is_scoped_style(node: &Node) -> bool {
+    match &node.data {
+        NodeData::Element {
+            tag, attributes, ..
+        } => tag == "style" && attributes.contains_key("scoped"),
+        NodeData::Text { .. } => false,
+    }
+}
+
+fn take_scoped_blocks(nodes: &mut Vec<Node>, blocks: &mut Vec<String>) {
+    for node in nodes.iter().filter(|node| is_scoped_style(node)) {
+        for child in node.children.iter() {
+            if let NodeData::Text { content } = &child.data {
+                blocks.push(content.clone());
+            }
+        }
+    }
+
+    nodes.retain(|node| !is_scoped_style(node));
+
+    for node in nodes.iter_mut() {
+        take_scoped_blocks(&mut node.children, blocks);
+    }
+}
+
+fn mark(nodes: &mut [Node], attribute: &str) {
+    for node in nodes.iter_mut() {
+        if let NodeData::Element { attributes, .. } = &mut node.data {
+            attributes.insert(attribute.to_string(), Attribute::Static(String::new()));
+        }
+
+        mark(&mut node.children, attribute);
+    }
+}
+
+fn fnv1a(input: &str) -> u32 {
+    input.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Rewrite selectors of every rule, rules nested in `@media` and `@supports` included,
+/// other at-rules like `@keyframes` are kept as they are
+pub fn scope_css(css: &str, attribute: &str) -> String {
+    let mut result = String::new();
+    let source = strip_comments(css);
+    let mut rest = source.trim();
+
+    while let Some(open) = rest.find('{') {
+        let prelude = rest[..open].trim();
+        let close = matching_brace(rest, open);
+        let body = &rest[open + 1..close];
+
+        if prelude.starts_with("@media") || prelude.starts_with("@supports") {
+            result.push_str(&format!("{}{{{}}}", prelude, scope_css(body, attribute)));
+        } else if prelude.starts_with('@') {
+            result.push_str(&format!("{}{{{}}}", prelude, body.trim()));
+        } else {
+            let mut selectors = vec![];
+            let mut start = 0;
+            let commas = top_level(prelude)
+                .filter(|(_, c)| *c == ',')
+                .map(|(i, _)| i);
+
+            for end in commas.chain(std::iter::once(prelude.len())) {
+                selectors.push(scope_selector(prelude[start..end].trim(), attribute));
+                start = end + 1;
+            }
+            result.push_str(&format!("{}{{{}}}", selectors.join(","), body.trim()));
+        }
+
+        rest = rest[close + 1..].trim();
+    }
+
+    result
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut result = String::new();
+    let mut rest = css;
+
+    while let Some(start) = rest.find("/*") {
+        result.push_str(&rest[..start]);
+        rest = match rest[start..].find("*/") {
+            Some(end) => &rest[start + end + 2..],
+            None => "",
+        };
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Position of the brace that closes the one at `open`, end of input if it is never closed
+fn matching_brace(css: &str, open: usize) -> usize {
+    let mut depth = 0;
+
+    for (i, c) in css.char_indices().skip_while(|(i, _)| *i < open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    css.len()
+}
+
+/// Characters of a selector that are not inside of `()`, `[]` or quotes, with their positions
+fn top_level(selector: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut depth = 0;
+    let mut quote = None;
+
+    selector.char_indices().filter(move |&(_, c)| {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '(') => depth += 1,
+            (None, ']' | ')') => depth -= 1,
+            (None, _) => return depth == 0,
+        }
+        false
+    })
+}
+
+/// Add attribute selector to the last compound selector, before its pseudo classes,
+/// e.g. `ul > li.active:hover` becomes `ul > li.active[data-s-1]:hover`
+fn scope_selector(selector: &str, attribute: &str) -> String {
+    let start = top_level(selector)
+        .filter(|(_, c)| c.is_whitespace() || ['>', '+', '~'].contains(c))
+        .last()
+        .map_or(0, |(i, c)| i + c.len_utf8());
+
+    let insert = top_level(selector)
+        .find(|&(i, c)| i >= start && c == ':')
+        .map_or(selector.len(), |(i, _)| i);
+
+    format!(
+        "{}[{}]{}",
+        &selector[..insert],
+        attribute,
+        &selector[insert..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elements::ElementOptions;
+    use crate::html::extract_html;
+    use crate::testing::{stub, Harness};
+
+    #[test]
+    fn selectors_get_scope_attribute() {
+        assert_eq!(scope_css("p { color: red }", "s"), "p[s]{color: red}");
+        assert_eq!(
+            scope_css("ul > li.active:hover, .a .b::before {x: y}", "s"),
+            "ul > li.active[s]:hover,.a .b[s]::before{x: y}"
+        );
+        assert_eq!(
+            scope_css("a[href^='http:']:not(.x) {}", "s"),
+            "a[href^='http:'][s]:not(.x){}"
+        );
+        assert_eq!(
+            scope_css("p:is(.a, .b) > [title=\"a, b\"] {}", "s"),
+            "p:is(.a, .b) > [title=\"a, b\"][s]{}"
+        );
+        assert_eq!(
+            scope_css(":is(ul, ol) li, [title='x y'] {}", "s"),
+            ":is(ul, ol) li[s],[title='x y'][s]{}"
+        );
+    }
+
+    #[test]
+    fn media_rules_are_scoped_and_keyframes_are_kept() {
+        let css = "/* narrow */ @media (max-width: 10px) { p { a: b } }\
+                   @keyframes spin { from { x: y } to { x: z } }";
+
+        assert_eq!(
+            scope_css(css, "s"),
+            "@media (max-width: 10px){p[s]{a: b}}@keyframes spin{from { x: y } to { x: z }}"
+        );
+    }
+
+    #[test]
+    fn scoped_style_is_taken_out_of_template() {
+        let mut template = extract_html(
+            &mut "<style scoped>p { color: red }</style><div><p>hi</p></div>".to_string(),
+        );
+
+        let style = extract_scoped_style(&mut template).unwrap();
+        assert!(style.attribute.starts_with(SCOPE_PREFIX));
+        assert_eq!(style.css, format!("p[{}]{{color: red}}", style.attribute));
+
+        assert_eq!(template.len(), 1);
+        for node in [&template[0], &template[0].children[0]] {
+            match &node.data {
+                NodeData::Element { attributes, .. } => {
+                    assert!(attributes.contains_key(&style.attribute))
+                }
+                NodeData::Text { .. } => panic!("expected element"),
+            }
+        }
+    }
+
+    #[test]
+    fn templates_without_scoped_style_are_left_alone() {
+        let mut template = extract_html(&mut "<style>p {}</style><p></p>".to_string());
+        assert_eq!(extract_scoped_style(&mut template), None);
+        assert_eq!(template.len(), 2);
+    }
+
+    #[test]
+    fn scoped_style_is_injected_once_and_elements_are_marked() {
+        let mut harness = Harness::with_page(
+            "<template id=\"card\"><style scoped>ul > li { color: red }</style>\
+             <ul><li>a</li></ul></template><div id=\"app\"></div><div id=\"other\"></div>",
+        );
+        for name in ["card", "other-card"] {
+            harness
+                .framework
+                .register_component_wrapper(name, stub(), "card");
+        }
+        harness.mount("card").mount_into("other", "other-card");
+
+        let head = harness.backend().head();
+        assert_eq!(head.children().len(), 1);
+
+        let style = &head.children()[0];
+        let attribute = style.attribute("id").unwrap();
+        assert_eq!(
+            style.text_content(),
+            format!("ul > li[{}]{{color: red}}", attribute)
+        );
+
+        assert_eq!(harness.find_all("li").len(), 2);
+        for li in harness.find_all("li") {
+            assert_eq!(li.attribute(&attribute).unwrap(), "");
+        }
+        assert!(harness.find("style").is_none());
+    }
+
+    #[test]
+    fn shadow_roots_get_scoped_styles() {
+        let mut harness = Harness::with_page("<my-card></my-card>");
+        harness.framework.register_component_template(
+            "card",
+            stub(),
+            "<style scoped>p { color: red }</style><p>a</p>",
+        );
+        harness
+            .framework
+            .define_element("my-card", "card", ElementOptions::new().shadow());
+        harness.settle();
+
+        let shadow = harness.find("my-card").unwrap().shadow_root().unwrap();
+        let (p, style) = (&shadow.children()[0], &shadow.children()[1]);
+        let attribute = style.attribute("id").unwrap();
+        assert_eq!(style.tag().unwrap(), "style");
+        assert_eq!(
+            style.text_content(),
+            format!("p[{}]{{color: red}}", attribute)
+        );
+        assert_eq!(p.attribute(&attribute).unwrap(), "");
+    }
+}
@@ -1,10 +1,17 @@
 /// This package represents intermidiate not evaluated dom tree
 /// that should be stored within a component as a templating language
-use crate::framework::{ComponentInstance, DirtyInstance};
+use crate::context::{ContextNode, Injector, Provider, CONTEXT_PREFIX};
+use crate::framework::{
+    dispatch, ChildRuntimes, ComponentInstance, DirtyInstance, FrameworkError, Lookup, Priority,
+    Registry,
+};
+use crate::html::extract_trusted_html;
+use crate::router::{OutletDepth, Router, ROUTER_LINK, ROUTER_VIEW, ROUTE_PREFIX};
+use crate::store::{SharedStore, STORE_PREFIX};
+use crate::transition::{Transition, TRANSITION};
 use crate::vdom::*;
 use std::collections::HashMap;
 use std::rc::Rc;
-use wasm_bindgen::prelude::*;
 
 #[derive(Debug, Clone)]
 pub enum Attribute {
@@ -17,8 +24,14 @@ pub type Attributes = HashMap<String, Attribute>;
 
 #[derive(Debug, Clone)]
 pub enum NodeData {
-    Element { tag: String, attributes: Attributes },
-    Text { content: String },
+    Element {
+        tag: String,
+        namespace: Namespace,
+        attributes: Attributes,
+    },
+    Text {
+        content: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -27,31 +40,525 @@ pub struct Node {
     pub children: Vec<Node>,
 }
 
+/// Tag of the element that renders its children in to another element, e.g. `<portal to="#modals">`
+pub const PORTAL: &str = "portal";
+
+/// Tag of the element that renders its children once for every item of a list,
+/// e.g. `<for collection="todos" as="todo" key="id">`, items with a key keep their dom nodes
+/// and nested components when the list is reordered
+pub const FOR: &str = "for";
+
+/// Name items of `<for>` are bound to when it has no `as` attribute
+pub const DEFAULT_ALIAS: &str = "item";
+
+/// Name of the slot that receives content without explicit `slot="..."` attribute
+pub const DEFAULT_SLOT: &str = "default";
+
+/// Content projected by a parent in to `<slot>` elements of a child template,
+/// already realized in the parent scope
+pub type Slots = HashMap<String, Vec<VNode>>;
+
+/// Everything template realization needs to know about the component it is rendering
+pub struct RenderContext<'a> {
+    pub component: ComponentInstance,
+    pub dirty: DirtyInstance,
+    /// Values provided by the component and its ancestors
+    pub context: Rc<ContextNode>,
+    pub registry: &'a Registry,
+    /// Nested component runtimes, reused between renders by position
+    pub children: &'a mut ChildRuntimes,
+    pub slots: Slots,
+    pub store: Option<Rc<dyn SharedStore>>,
+    pub router: Option<Rc<Router>>,
+    /// Content of `<portal>` elements with selectors of their targets, in template order
+    pub portals: Vec<(String, VDom)>,
+    next_child: usize,
+    /// Items of enclosing `<for>` elements, innermost last
+    items: Vec<ListItem>,
+}
+
+/// Item of a `<for>` list that is being rendered, its fields are looked up as `alias.field`
+struct ListItem {
+    alias: String,
+    value: Rc<dyn Lookup>,
+    key: Option<String>,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(
+        component: ComponentInstance,
+        dirty: DirtyInstance,
+        context: Rc<ContextNode>,
+        registry: &'a Registry,
+        children: &'a mut ChildRuntimes,
+        slots: Slots,
+    ) -> Self {
+        let store = dirty.borrow().store();
+        let router = dirty.borrow().router();
+
+        RenderContext {
+            component,
+            dirty,
+            context,
+            store,
+            router,
+            registry,
+            children,
+            slots,
+            portals: vec![],
+            next_child: 0,
+            items: vec![],
+        }
+    }
+
+    /// Drop runtimes of nested components that were not rendered this time
+    pub fn finish(self) {
+        self.children.truncate(self.next_child);
+    }
+}
+
+fn is_blank(nodes: &[VNode]) -> bool {
+    nodes.iter().all(|node| match &node.data {
+        VNodeData::Text { content } => content.trim().is_empty(),
+        _ => false,
+    })
+}
+
 impl Node {
+    fn attribute(&self, name: &str) -> Option<&Attribute> {
+        match &self.data {
+            NodeData::Element { attributes, .. } => attributes.get(name),
+            _ => None,
+        }
+    }
+
+    fn static_attribute(&self, name: &str) -> Option<&String> {
+        match &self.data {
+            NodeData::Element { attributes, .. } => match attributes.get(name) {
+                Some(Attribute::Static(value)) => Some(value),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn slot_name(&self) -> String {
+        self.static_attribute("slot")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_SLOT.to_string())
+    }
+
+    fn realize_children(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        self.children
+            .iter()
+            .flat_map(|ch| ch.realize(ctx))
+            .collect()
+    }
+
+    /// Replace `<slot>` with content provided by the parent or with slot's own fallback children
+    fn realize_slot(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        let name = self
+            .static_attribute("name")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_SLOT.to_string());
+
+        match ctx.slots.remove(&name) {
+            Some(content) if !is_blank(&content) => content,
+            _ => self.realize_children(ctx),
+        }
+    }
+
+    /// Render nested component in place of its tag,
+    /// children of the tag are realized in current scope and projected in to child's slots
+    fn realize_component(&self, tag: &str, ctx: &mut RenderContext) -> Vec<VNode> {
+        let mut slots = Slots::new();
+
+        for child in self.children.iter() {
+            let mut content = child.realize(ctx);
+
+            // `slot` only tells where content goes, it is not rendered
+            for vnode in content.iter_mut() {
+                if let VNodeData::Element { attributes, .. } = &mut vnode.data {
+                    attributes.remove("slot");
+                }
+            }
+
+            slots.entry(child.slot_name()).or_default().extend(content);
+        }
+
+        Self::realize_child(tag, slots, None, ctx)
+    }
+
+    /// Render registered component with given slots, runtime is reused if it rendered
+    /// the same component at this position last time, new runtime's context is linked
+    /// to the given one or to the context of the current component
+    fn realize_child(
+        tag: &str,
+        slots: Slots,
+        parent: Option<Rc<ContextNode>>,
+        ctx: &mut RenderContext,
+    ) -> Vec<VNode> {
+        let index = ctx.next_child;
+        ctx.next_child += 1;
+
+        // runtimes inside keyed items go with their item when the list is reordered
+        let keys: Vec<&str> = ctx
+            .items
+            .iter()
+            .filter_map(|item| item.key.as_deref())
+            .collect();
+        let keyed = !keys.is_empty();
+        let identity = match keyed {
+            true => format!("{}@{}", tag, keys.join("/")),
+            false => tag.to_string(),
+        };
+
+        if keyed {
+            let found = ctx
+                .children
+                .iter()
+                .skip(index)
+                .position(|(name, _)| *name == identity);
+
+            if let Some(offset) = found {
+                let runtime = ctx.children.remove(index + offset);
+                ctx.children.insert(index, runtime);
+            }
+        }
+
+        let reusable = match ctx.children.get(index) {
+            Some((name, _)) => *name == identity,
+            None => false,
+        };
+
+        if !reusable {
+            let parent = parent.unwrap_or_else(|| Rc::clone(&ctx.context));
+            let runtime = ctx.registry[tag].construct_in(ContextNode::child(&parent));
+            runtime.dirty.borrow_mut().nest_in(&ctx.dirty.borrow());
+
+            match keyed {
+                // runtimes that follow may belong to items that were moved, they are kept
+                true => ctx.children.insert(index, (identity, runtime)),
+                false => {
+                    ctx.children.truncate(index);
+                    ctx.children.push((identity, runtime));
+                }
+            }
+        }
+
+        // roots of the component stay together when its number of roots changes
+        let roots = ctx.children[index].1.realize(ctx.registry, slots);
+        vec![VNode::fragment(roots)]
+    }
+
+    fn router(ctx: &RenderContext) -> Rc<Router> {
+        ctx.router
+            .clone()
+            .unwrap_or_else(|| panic!("router is not attached to the framework"))
+    }
+
+    /// Render component that matches current route, children of the outlet are rendered
+    /// when nothing matches, components in outlets get depth of nested outlets through context
+    fn realize_router_view(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        let router = Self::router(ctx);
+        router.watch(&ctx.dirty);
+
+        let depth = Injector::new(&ctx.context, &ctx.dirty)
+            .inject::<OutletDepth>()
+            .map_or(0, |OutletDepth(depth)| depth);
+
+        match router
+            .current()
+            .and_then(|matched| matched.component(depth))
+        {
+            Some(component) if ctx.registry.contains_key(component) => {
+                let outlet = ContextNode::child(&ctx.context);
+                Provider::new(&outlet).provide(OutletDepth(depth + 1));
+
+                Self::realize_child(component, Slots::new(), Some(outlet), ctx)
+            }
+            Some(component) => panic!("route points at unknown component {}", component),
+            None => self.realize_children(ctx),
+        }
+    }
+
+    /// Children of a portal are realized in the scope of the current component,
+    /// but rendered in to the element that `to` selector points at
+    fn realize_portal(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        let to = match self.attribute("to") {
+            Some(Attribute::Static(selector)) => selector.clone(),
+            Some(Attribute::Dynamic(key)) => Node::lookup(key, ctx),
+            _ => panic!("<{}> needs a `to` attribute", PORTAL),
+        };
+
+        let content = self.realize_children(ctx);
+        ctx.portals.push((to, content));
+
+        vec![]
+    }
+
+    /// Children of `<transition>` are rendered in its place and animated
+    /// when a patch inserts or removes them
+    fn realize_transition(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        let name = self
+            .static_attribute("name")
+            .unwrap_or_else(|| panic!("<{}> needs a `name` attribute", TRANSITION));
+        let mut transition = Transition::new(name);
+
+        if let Some(duration) = self.static_attribute("duration") {
+            transition = transition.duration(
+                duration
+                    .parse()
+                    .unwrap_or_else(|_| panic!("<{}> duration is not a number", TRANSITION)),
+            );
+        }
+
+        let mut vnodes = self.realize_children(ctx);
+        set_transition(&mut vnodes, &transition);
+
+        vnodes
+    }
+
+    /// Render children once for every item of the list in `collection`, every item gets its own
+    /// fragment with the value of `key` field as its key
+    fn realize_for(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        let collection = self
+            .static_attribute("collection")
+            .unwrap_or_else(|| panic!("<{}> needs a `collection` attribute", FOR));
+        let alias = self
+            .static_attribute("as")
+            .map_or(DEFAULT_ALIAS, String::as_str);
+        let key = self.static_attribute("key");
+
+        let mut items = vec![];
+
+        for value in Node::lookup_list(collection, ctx) {
+            let key = key.map(|field| {
+                value
+                    .lookup(field)
+                    .unwrap_or_else(|| panic!("item of {} has no key {}", collection, field))
+                    .to_string()
+            });
+
+            ctx.items.push(ListItem {
+                alias: alias.to_string(),
+                value,
+                key: key.clone(),
+            });
+            let mut item = VNode::fragment(self.realize_children(ctx));
+            ctx.items.pop();
+
+            item.key = key;
+            items.push(item);
+        }
+
+        vec![VNode::fragment(items)]
+    }
+
+    /// Render `<a>` that navigates to the path in `to` attribute without reloading the page
+    fn realize_router_link(&self, ctx: &mut RenderContext) -> Vec<VNode> {
+        let router = Self::router(ctx);
+
+        let mut link = self.clone();
+        let to = match &mut link.data {
+            NodeData::Element {
+                tag, attributes, ..
+            } => {
+                *tag = "a".to_string();
+                attributes.remove("to")
+            }
+            NodeData::Text { .. } => None,
+        };
+
+        let path = match to {
+            Some(Attribute::Static(path)) => Some(path),
+            Some(Attribute::Dynamic(key)) => {
+                let path = Node::lookup(&key, ctx);
+
+                // same as any other dynamic url, e.g. `javascript:` is not rendered
+                match sanitize_attribute("href", &path) {
+                    Ok(()) => Some(path),
+                    Err(violation) => {
+                        ctx.dirty.borrow().report(FrameworkError::UnsafeAttribute {
+                            tag: ROUTER_LINK.to_string(),
+                            violation,
+                        });
+                        None
+                    }
+                }
+            }
+            _ => panic!("<{}> needs a `to` attribute", ROUTER_LINK),
+        };
+
+        let mut vnodes = link.realize(ctx);
+        let path = match path {
+            Some(path) => path,
+            None => return vnodes,
+        };
+
+        if let VNodeData::Element { attributes, .. } = &mut vnodes[0].data {
+            attributes.insert(
+                "href".to_string(),
+                VAttribute::Attribute(router.href(&path)),
+            );
+
+            let router = Rc::downgrade(&router);
+            let message = format!("navigate {}", path);
+            let handler = Handler::new(&message, move |event: &Event| {
+                // ctrl-click, middle click and the like open the link the way browser does it
+                if event.button != 0 || event.modifiers.any() {
+                    return;
+                }
+
+                if let Some(router) = router.upgrade() {
+                    event.prevent_default();
+                    router.navigate(&path);
+                }
+            });
+            attributes.insert("click".to_string(), VAttribute::Handler(handler));
+        }
+
+        vnodes
+    }
+
+    /// Value of a dynamic attribute, see `find`
+    fn lookup(key: &str, ctx: &RenderContext) -> String {
+        Self::find(key, ctx).unwrap_or_else(|| panic!("could not find key {}", key))
+    }
+
+    /// Value of a key, keys with `$store.` prefix are read from the store,
+    /// keys with `$context.` prefix from values provided by ancestors,
+    /// in both cases the component gets rendered again when they change
+    fn find(key: &str, ctx: &RenderContext) -> Option<String> {
+        if let Some(name) = key.strip_prefix(ROUTE_PREFIX) {
+            let router = Self::router(ctx);
+            router.watch(&ctx.dirty);
+
+            // params of other routes, e.g. `$route.id` in a layout shared with `/users`,
+            // are just not there yet
+            return Some(router.lookup(name).unwrap_or_default());
+        }
+
+        if let Some(name) = key.strip_prefix(CONTEXT_PREFIX) {
+            return Injector::new(&ctx.context, &ctx.dirty).lookup(name);
+        }
+
+        if let Some(selector) = key.strip_prefix(STORE_PREFIX) {
+            let store = ctx.store.as_ref()?;
+            store.watch(selector, &ctx.dirty);
+
+            return store.lookup(selector).map(|value| value.to_string());
+        }
+
+        if let Some((item, field)) = Self::item(key, ctx) {
+            return item
+                .lookup(&field.to_string())
+                .map(|value| value.to_string());
+        }
+
+        ctx.component
+            .borrow()
+            .lookup(&key.to_string())
+            .map(|value| value.to_string())
+    }
+
+    /// Items of a list for `<for>`, read from an item of an enclosing `<for>` when key starts
+    /// with its alias, e.g. `todo.tags`, otherwise from the component
+    fn lookup_list(key: &str, ctx: &RenderContext) -> Vec<Rc<dyn Lookup>> {
+        let list = match Self::item(key, ctx) {
+            Some((item, field)) => item.lookup_list(&field.to_string()),
+            None => ctx.component.borrow().lookup_list(&key.to_string()),
+        };
+
+        list.unwrap_or_else(|| panic!("could not find list {}", key))
+    }
+
+    /// Innermost item of enclosing `<for>` elements that key points at with the rest of the key
+    fn item<'k>(key: &'k str, ctx: &RenderContext) -> Option<(Rc<dyn Lookup>, &'k str)> {
+        let (alias, field) = key.split_once('.')?;
+
+        ctx.items
+            .iter()
+            .rev()
+            .find(|item| item.alias == alias)
+            .map(|item| (Rc::clone(&item.value), field))
+    }
+
+    /// Content of an element with `:html` binding, only markup the component vouches for
+    /// with `Lookup::lookup_html` is accepted, bindings in it are not evaluated
+    fn trusted_html(key: &str, tag: &str, namespace: Namespace, ctx: &RenderContext) -> Vec<VNode> {
+        let html = ctx
+            .component
+            .borrow()
+            .lookup_html(&key.to_string())
+            .unwrap_or_else(|| panic!("{} is not trusted html, see `Lookup::lookup_html`", key));
+
+        realize_static(&extract_trusted_html(&html, tag, namespace))
+    }
+
+    /// Text with every `{{ key }}` replaced by the value of the key,
+    /// keys that can not be found are reported and rendered empty
+    fn interpolate(content: &str, ctx: &RenderContext) -> String {
+        interpolations(content)
+            .map(|segment| match segment {
+                Segment::Text(text) => text.to_string(),
+                Segment::Key(key) => Node::find(key, ctx).unwrap_or_else(|| {
+                    ctx.dirty.borrow().report(FrameworkError::UnknownKey {
+                        key: key.to_string(),
+                    });
+                    String::new()
+                }),
+            })
+            .collect()
+    }
+
     /// What have I done...
-    pub fn realize(&self, component: ComponentInstance, dirty: DirtyInstance) -> VNode {
+    pub fn realize(&self, ctx: &mut RenderContext) -> Vec<VNode> {
         let data = match &self.data {
             NodeData::Text { content } => VNodeData::Text {
-                content: content.clone(),
+                content: Node::interpolate(content, ctx),
             },
-            NodeData::Element { tag, attributes } => VNodeData::Element {
+            NodeData::Element { tag, .. } if tag == "slot" => return self.realize_slot(ctx),
+            NodeData::Element { tag, .. } if tag == PORTAL => return self.realize_portal(ctx),
+            NodeData::Element { tag, .. } if tag == ROUTER_VIEW => {
+                return self.realize_router_view(ctx)
+            }
+            NodeData::Element { tag, .. } if tag == ROUTER_LINK => {
+                return self.realize_router_link(ctx)
+            }
+            NodeData::Element { tag, .. } if tag == TRANSITION => {
+                return self.realize_transition(ctx)
+            }
+            NodeData::Element { tag, .. } if tag == FOR => return self.realize_for(ctx),
+            NodeData::Element { tag, .. } if ctx.registry.contains_key(tag.as_str()) => {
+                return self.realize_component(tag, ctx)
+            }
+            NodeData::Element {
+                tag,
+                namespace,
+                attributes,
+            } => VNodeData::Element {
                 tag: tag.clone(),
+                namespace: *namespace,
                 attributes: attributes
                     .iter()
-                    .map(|(k, v)| {
+                    .filter(|(k, v)| *k != TRANSITION && !is_html_binding(k, v))
+                    .filter_map(|(k, v)| {
                         let newv = match v {
                             Attribute::Static(value) => VAttribute::Attribute(value.clone()),
                             Attribute::Dynamic(value) => {
-                                let v = component
-                                    .borrow()
-                                    .lookup(value)
-                                    .expect(&*format!(
-                                        "could not find key {} in a component",
-                                        value
-                                    ))
-                                    .to_string();
-
-                                VAttribute::Attribute(v)
+                                let value = Node::lookup(value, ctx);
+
+                                // values come from users more often than not
+                                if let Err(violation) = sanitize_attribute(k, &value) {
+                                    ctx.dirty.borrow().report(FrameworkError::UnsafeAttribute {
+                                        tag: tag.clone(),
+                                        violation,
+                                    });
+                                    return None;
+                                }
+
+                                VAttribute::Attribute(value)
                             }
                             Attribute::Handler(value) => {
                                 // weak references for closure
@@ -59,57 +566,566 @@ impl Node {
                                 // in ideal scenario closure should not exist with component not
                                 // being in memory
                                 // if this is the case everything is fucked anyways, so whatever
-                                let component_instance = Rc::downgrade(&component);
-                                let dirty_instance = Rc::downgrade(&dirty);
+                                let component_instance = Rc::downgrade(&ctx.component);
+                                let dirty_instance = Rc::downgrade(&ctx.dirty);
                                 // need to clone this and move this in to closure
                                 let message_value = value.clone();
 
-                                let closure = Closure::wrap(Box::new(move |e: web_sys::Event| {
-                                    // Try to upgrade component weak reference to a strong one
-                                    let cmp = component_instance.upgrade();
-                                    if let Some(cmp_rc) = cmp {
-                                        // execute handle method on component and get bool value
-                                        let is_dirty =
-                                            cmp_rc.borrow_mut().handle(message_value.clone());
-                                        // Try to upgrade dirty weak reference to a strong one
-                                        let dirty = dirty_instance.upgrade();
-
-                                        // if we are able to acquire pointer to dirty config
-                                        // and if we should mark our thing dirty we will set
-                                        // the flag via interrior mutability of RefCell
-                                        if is_dirty {
-                                            if let Some(dirty) = dirty {
-                                                dirty.borrow_mut().dirty = true;
-                                            }
-                                        }
-                                    } else {
-                                        log!(
-                                            "Could not get instance of commponent, might be freed"
-                                        );
-                                    };
-
-                                    log!("Got value {:?} and did send {}", e, message_value);
-                                })
-                                    as Box<dyn FnMut(_)>);
-
-                                VAttribute::Handler(closure)
+                                let callback = move |event: &Event| {
+                                    dispatch(
+                                        &component_instance,
+                                        &dirty_instance,
+                                        message_value.clone(),
+                                        event,
+                                        Priority::User,
+                                    );
+
+                                    log!("Did send {}", message_value);
+                                };
+
+                                let owner = ctx.dirty.borrow().id;
+                                VAttribute::Handler(Handler::new(value, callback).owner(owner))
                             }
                         };
 
-                        (k.clone(), newv)
+                        Some((k.clone(), newv))
                     })
                     .collect(),
             },
         };
 
-        let children = self
-            .children
-            .iter()
-            .map(|ch| ch.realize(Rc::clone(&component), Rc::clone(&dirty)))
-            .collect();
+        let children = match (self.attribute(HTML_BINDING), &self.data) {
+            (Some(Attribute::Dynamic(key)), NodeData::Element { tag, namespace, .. }) => {
+                Node::trusted_html(key, tag, *namespace, ctx)
+            }
+            _ => self.realize_children(ctx),
+        };
+        let mut vnode = VNode::new(data, children);
+        vnode.transition = self
+            .static_attribute(TRANSITION)
+            .map(|name| Transition::new(name));
+
+        vec![vnode]
+    }
+}
+
+/// Part of a text node, `{{ key }}` interpolations are looked up the same way
+/// dynamic attributes are, e.g. `{{ count }}` or `{{ todo.title }}`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Key(&'a str),
+}
+
+/// Split text in to plain parts and keys of interpolations, `{{` without `}}` is plain text
+pub fn interpolations(content: &str) -> impl Iterator<Item = Segment<'_>> {
+    let mut rest = content;
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let interpolation = rest
+            .find("{{")
+            .and_then(|start| Some((start, start + 2 + rest[start + 2..].find("}}")?)));
+
+        let segment = match interpolation {
+            Some((0, end)) => {
+                let key = rest[2..end].trim();
+                rest = &rest[end + 2..];
+                Segment::Key(key)
+            }
+            Some((start, _)) => {
+                let text = &rest[..start];
+                rest = &rest[start..];
+                Segment::Text(text)
+            }
+            None => Segment::Text(std::mem::take(&mut rest)),
+        };
 
-        VNode { data, children }
+        Some(segment)
+    })
+}
+
+/// Name of the binding that renders trusted markup as content of an element,
+/// e.g. `<article :html="body"></article>`
+pub const HTML_BINDING: &str = "html";
+
+fn is_html_binding(name: &str, attribute: &Attribute) -> bool {
+    name == HTML_BINDING && matches!(attribute, Attribute::Dynamic(_))
+}
+
+/// Turn markup without bindings in to virtual nodes as it is,
+/// scripts are left out the same way `innerHTML` never runs them,
+/// attributes the dom does not accept, e.g. `@click`, are left out as well
+fn realize_static(nodes: &[Node]) -> Vec<VNode> {
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let data = match &node.data {
+                NodeData::Text { content } => VNodeData::Text {
+                    content: content.clone(),
+                },
+                NodeData::Element { tag, .. } if tag == "script" => return None,
+                NodeData::Element {
+                    tag,
+                    namespace,
+                    attributes,
+                } => VNodeData::Element {
+                    tag: tag.clone(),
+                    namespace: *namespace,
+                    attributes: attributes
+                        .iter()
+                        .filter(|(name, _)| is_valid_attribute_name(name))
+                        .map(|(name, attribute)| {
+                            let value = match attribute {
+                                Attribute::Static(value)
+                                | Attribute::Dynamic(value)
+                                | Attribute::Handler(value) => value.clone(),
+                            };
+                            (name.clone(), VAttribute::Attribute(value))
+                        })
+                        .collect(),
+                },
+            };
+
+            Some(VNode::new(data, realize_static(&node.children)))
+        })
+        .collect()
+}
+
+/// Animate elements, elements of fragments included, e.g. roots of nested components
+fn set_transition(vnodes: &mut [VNode], transition: &Transition) {
+    for vnode in vnodes.iter_mut() {
+        match vnode.data {
+            VNodeData::Element { .. } => vnode.transition = Some(transition.clone()),
+            VNodeData::Fragment => set_transition(&mut vnode.children, transition),
+            VNodeData::Text { .. } => {}
+        }
     }
 }
 
 pub type Template = Vec<Node>;
+
+/// Call visitor with every node of the template, its path and its ancestors, outermost first.
+/// Parsed templates have no line numbers, so diagnostics point at nodes by path instead,
+/// e.g. `div > p[2] > #text` is the text of the second paragraph in a div,
+/// the first one is just `div > p`
+pub fn visit_template<'t>(
+    template: &'t [Node],
+    visitor: &mut dyn FnMut(&'t Node, &str, &[&'t Node]),
+) {
+    visit_nodes(template, "", &mut vec![], visitor);
+}
+
+fn visit_nodes<'t>(
+    nodes: &'t [Node],
+    parent: &str,
+    ancestors: &mut Vec<&'t Node>,
+    visitor: &mut dyn FnMut(&'t Node, &str, &[&'t Node]),
+) {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+
+    for node in nodes {
+        let name = match &node.data {
+            NodeData::Element { tag, .. } => tag.as_str(),
+            NodeData::Text { .. } => "#text",
+        };
+
+        // nodes are told apart by position among siblings with the same name, counted from one
+        let count = seen.entry(name).or_insert(0);
+        *count += 1;
+        let segment = match *count {
+            1 => name.to_string(),
+            n => format!("{}[{}]", name, n),
+        };
+
+        let path = match parent.is_empty() {
+            true => segment,
+            false => format!("{} > {}", parent, segment),
+        };
+
+        visitor(node, &path, ancestors);
+
+        ancestors.push(node);
+        visit_nodes(&node.children, &path, ancestors, visitor);
+        ancestors.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framework::*;
+    use crate::html::{extract_html, TrustedHtml};
+    use crate::testing::Harness;
+    use std::cell::RefCell;
+
+    struct Static {
+        title: &'static str,
+    }
+
+    impl Component for Static {
+        fn render(&self) -> Vec<crate::vdom::SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            false
+        }
+    }
+
+    impl Lookup for Static {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "title" => Some(Box::new(self.title)),
+                _ => None,
+            }
+        }
+
+        fn lookup_html(&self, k: &String) -> Option<TrustedHtml> {
+            match k.as_str() {
+                "body" => Some(TrustedHtml::trust(
+                    "<b :title=\"title\" @click=\"open\">bold</b><script>alert(1)</script> text",
+                )),
+                _ => None,
+            }
+        }
+    }
+
+    fn wrapper(title: &'static str, template: &str) -> ComponentWrapper {
+        let mut wrapper =
+            ComponentWrapper::new(Box::new(move || Rc::new(RefCell::new(Static { title }))));
+        wrapper.template = extract_html(&mut template.to_string());
+        wrapper
+    }
+
+    fn render(registry: &Registry, name: &str) -> VDom {
+        registry[name].construct().realize(registry, Slots::new())
+    }
+
+    fn text(nodes: &[VNode]) -> String {
+        nodes
+            .iter()
+            .map(|node| match &node.data {
+                VNodeData::Text { content } => content.clone(),
+                VNodeData::Element { .. } | VNodeData::Fragment => text(&node.children),
+            })
+            .collect()
+    }
+
+    fn attribute(node: &VNode, name: &str) -> String {
+        match &node.data {
+            VNodeData::Element { attributes, .. } => match &attributes[name] {
+                VAttribute::Attribute(value) => value.clone(),
+                _ => panic!("not an attribute"),
+            },
+            _ => panic!("not an element"),
+        }
+    }
+
+    #[test]
+    fn realize_nested_component_in_place() {
+        let mut registry = Registry::new();
+        registry.insert("child", wrapper("child", "<b>child</b>"));
+        registry.insert("parent", wrapper("parent", "<div><child></child></div>"));
+
+        let vdom = render(&registry, "parent");
+        assert_eq!(vdom.len(), 1);
+        assert_eq!(vdom[0].children.len(), 1);
+        assert!(matches!(vdom[0].children[0].data, VNodeData::Fragment));
+        assert_eq!(text(&vdom), "child");
+    }
+
+    #[test]
+    fn realize_default_slot_in_parent_scope() {
+        let mut registry = Registry::new();
+        registry.insert(
+            "card",
+            wrapper("card", "<div :class=\"title\"><slot></slot></div>"),
+        );
+        registry.insert(
+            "parent",
+            wrapper("parent", "<card><i :class=\"title\">body</i></card>"),
+        );
+
+        let vdom = render(&registry, "parent");
+        assert_eq!(vdom.len(), 1);
+
+        // roots of the nested component are kept together in a fragment
+        let roots = &vdom[0].children;
+        assert_eq!(roots.len(), 1);
+        assert_eq!(attribute(&roots[0], "class"), "card");
+        assert_eq!(attribute(&roots[0].children[0], "class"), "parent");
+        assert_eq!(text(&vdom), "body");
+    }
+
+    #[test]
+    fn realize_named_slots() {
+        let mut registry = Registry::new();
+        registry.insert(
+            "modal",
+            wrapper(
+                "modal",
+                "<header><slot name=\"header\"></slot></header><main><slot></slot></main>",
+            ),
+        );
+        registry.insert(
+            "parent",
+            wrapper(
+                "parent",
+                "<modal><b>body</b><h1 slot=\"header\">title</h1></modal>",
+            ),
+        );
+
+        let vdom = render(&registry, "parent");
+        assert_eq!(vdom.len(), 1);
+
+        let roots = &vdom[0].children;
+        assert_eq!(roots.len(), 2);
+        assert_eq!(text(&roots[0].children), "title");
+        assert_eq!(text(&roots[1].children), "body");
+        assert_eq!(roots[0].children[0].to_html_string(), "<h1>title</h1>");
+    }
+
+    #[test]
+    fn realize_slot_fallback() {
+        let mut registry = Registry::new();
+        registry.insert(
+            "card",
+            wrapper("card", "<div><slot name=\"footer\">no footer</slot></div>"),
+        );
+        registry.insert("parent", wrapper("parent", "<card> </card>"));
+
+        let vdom = render(&registry, "parent");
+        assert_eq!(text(&vdom), "no footer");
+    }
+
+    #[test]
+    fn realize_transitions() {
+        let mut registry = Registry::new();
+        registry.insert(
+            "list",
+            wrapper(
+                "list",
+                "<p transition=\"fade\">a</p>\
+                 <transition name=\"slide\" duration=\"200\"><b>b</b> <i>c</i></transition>",
+            ),
+        );
+
+        let vdom = render(&registry, "list");
+        assert_eq!(text(&vdom), "ab c");
+
+        let transitions: Vec<_> = vdom.iter().map(|vnode| vnode.transition.clone()).collect();
+        assert_eq!(
+            transitions,
+            vec![
+                Some(Transition::new("fade")),
+                Some(Transition::new("slide").duration(200)),
+                None,
+                Some(Transition::new("slide").duration(200)),
+            ]
+        );
+
+        match &vdom[0].data {
+            VNodeData::Element { attributes, .. } => assert!(attributes.is_empty()),
+            _ => panic!("expected element"),
+        }
+    }
+
+    #[test]
+    fn realize_trusted_html() {
+        let mut registry = Registry::new();
+        registry.insert(
+            "post",
+            wrapper("post", "<article :html=\"body\"><i>ignored</i></article>"),
+        );
+
+        let vdom = render(&registry, "post");
+        assert_eq!(
+            vdom[0].to_html_string(),
+            "<article><b :title=\"title\">bold</b> text</article>"
+        );
+    }
+
+    #[test]
+    fn realize_interpolations() {
+        let mut registry = Registry::new();
+        registry.insert(
+            "title",
+            wrapper("hello", "<h1>{{title}}, {{ title }}! {{ not closed</h1>"),
+        );
+
+        let vdom = render(&registry, "title");
+        assert_eq!(
+            vdom[0].to_html_string(),
+            "<h1>hello, hello! {{ not closed</h1>"
+        );
+    }
+
+    #[test]
+    fn interpolations_split_text() {
+        let segments: Vec<_> = interpolations("{{a}}b{{ c.d }}").collect();
+        assert_eq!(
+            segments,
+            vec![Segment::Key("a"), Segment::Text("b"), Segment::Key("c.d")]
+        );
+
+        let segments: Vec<_> = interpolations("a {{ not closed").collect();
+        assert_eq!(segments, vec![Segment::Text("a {{ not closed")]);
+    }
+
+    #[test]
+    fn visit_template_with_paths() {
+        let template = extract_html(&mut "<div><p>a</p><i></i><p>b</p></div>".to_string());
+        let mut visited = vec![];
+
+        visit_template(&template, &mut |_, path, ancestors| {
+            visited.push((path.to_string(), ancestors.len()))
+        });
+
+        let expected = [
+            ("div", 0),
+            ("div > p", 1),
+            ("div > p > #text", 2),
+            ("div > i", 1),
+            ("div > p[2]", 1),
+            ("div > p[2] > #text", 2),
+        ];
+        let expected: Vec<_> = expected.iter().map(|(p, d)| (p.to_string(), *d)).collect();
+        assert_eq!(visited, expected);
+    }
+    struct Todos {
+        todos: Vec<(u32, &'static str)>,
+    }
+
+    impl Component for Todos {
+        fn render(&self) -> Vec<crate::vdom::SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, message: String) -> bool {
+            match message.as_ref() {
+                "rotate" => self.todos.rotate_right(1),
+                _ => return false,
+            }
+            true
+        }
+    }
+
+    impl Lookup for Todos {
+        fn lookup(&self, _k: &String) -> Option<LookupValue> {
+            None
+        }
+
+        fn lookup_list(&self, k: &String) -> Option<Vec<Rc<dyn Lookup>>> {
+            match k.as_str() {
+                "todos" => Some(
+                    self.todos
+                        .iter()
+                        .map(|&(id, title)| Rc::new(Todo { id, title }) as Rc<dyn Lookup>)
+                        .collect(),
+                ),
+                _ => None,
+            }
+        }
+    }
+
+    struct Todo {
+        id: u32,
+        title: &'static str,
+    }
+
+    impl Lookup for Todo {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "id" => Some(Box::new(self.id)),
+                "title" => Some(Box::new(self.title)),
+                _ => None,
+            }
+        }
+    }
+
+    struct Clicks(u32);
+
+    impl Component for Clicks {
+        fn render(&self) -> Vec<crate::vdom::SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, _message: String) -> bool {
+            self.0 += 1;
+            true
+        }
+    }
+
+    impl Lookup for Clicks {
+        fn lookup(&self, _k: &String) -> Option<LookupValue> {
+            Some(Box::new(self.0))
+        }
+    }
+
+    #[test]
+    fn keyed_items_move_with_their_nodes_and_components() {
+        let mut harness = Harness::new();
+        let todos = ComponentWrapper::new(Box::new(|| {
+            Rc::new(RefCell::new(Todos {
+                todos: vec![(1, "a"), (2, "b"), (3, "c")],
+            }))
+        }));
+        let clicks = ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Clicks(0)))));
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "todos",
+            todos,
+            "<button id=\"rotate\" @click=\"rotate\"></button>\
+             <ul><for collection=\"todos\" as=\"todo\" key=\"id\">\
+               <li transition=\"list\" :title=\"todo.title\">{{ todo.title }}<clicks></clicks></li>\
+             </for></ul>",
+        );
+        framework.register_component_template(
+            "clicks",
+            clicks,
+            "<button :data-clicks=\"clicks\" @click=\"click\"></button>",
+        );
+        harness.mount("todos");
+
+        let titles = |harness: &Harness| -> Vec<_> {
+            harness
+                .find_all("li")
+                .iter()
+                .map(|li| li.attribute("title").unwrap())
+                .collect()
+        };
+        assert_eq!(titles(&harness), ["a", "b", "c"]);
+
+        let c = harness.find("li[title=c]").unwrap();
+        harness.click("li[title=a] button").settle();
+        harness.click("#rotate").settle();
+
+        assert_eq!(titles(&harness), ["c", "a", "b"]);
+        assert_eq!(harness.text("ul"), "cab");
+        assert!(harness.find("li").unwrap().is_same(&c));
+        // state of the nested component went along with its item
+        assert_eq!(
+            harness
+                .attribute("li[title=a] button", "data-clicks")
+                .unwrap(),
+            "1"
+        );
+        assert_eq!(
+            harness
+                .attribute("li[title=c] button", "data-clicks")
+                .unwrap(),
+            "0"
+        );
+
+        harness.frame().frame();
+        assert_eq!(
+            harness.attribute("li[title=c]", "class").unwrap(),
+            "list-move"
+        );
+    }
+}
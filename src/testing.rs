@@ -0,0 +1,298 @@
+/// This package contains helpers to test components without a browser,
+/// components are mounted in to in-memory document and driven with simulated events
+use crate::backend::{DomNode, MemoryBackend, MemoryNode};
+use crate::framework::{Component, ComponentWrapper, Framework, Lookup, LookupValue};
+use crate::selector::Selector;
+use crate::vdom::{Event, SharableDomNode};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Id of the element components are mounted in to by `Harness::mount`
+pub const MOUNT_POINT: &str = "app";
+
+fn collect(node: &MemoryNode, selector: &Selector, result: &mut Vec<MemoryNode>) {
+    // same as in the browser, template content is not a part of the document
+    if node.tag().as_deref() == Some("template") {
+        return;
+    }
+
+    for child in node.children() {
+        if selector.matches(&child) {
+            result.push(child.clone());
+        }
+        collect(&child, selector, result);
+    }
+}
+
+// ************** Harness **************
+
+/// In-memory page with a framework attached to it
+pub struct Harness {
+    pub framework: Framework,
+    backend: Rc<MemoryBackend>,
+}
+
+impl Harness {
+    /// Empty page with `<div id="app">` mount point
+    pub fn new() -> Self {
+        Self::with_page(&format!("<div id=\"{}\"></div>", MOUNT_POINT))
+    }
+
+    /// Page with given markup, handy to provide `<template>` elements for components
+    pub fn with_page(html: &str) -> Self {
+        let backend = Rc::new(MemoryBackend::new());
+        backend.append_html(&DomNode::Memory(backend.body()), html);
+
+        Harness {
+            framework: Framework::with_backend(backend.clone()),
+            backend,
+        }
+    }
+
+    pub fn backend(&self) -> &MemoryBackend {
+        &self.backend
+    }
+
+    /// Mount registered component in to the default mount point
+    pub fn mount(&mut self, component: &'static str) -> &mut Self {
+        self.mount_into(MOUNT_POINT, component)
+    }
+
+    pub fn mount_into(&mut self, target_id: &'static str, component: &'static str) -> &mut Self {
+        self.framework
+            .mount(target_id, component)
+            .expect("could not mount component");
+        self
+    }
+
+    pub fn tick(&mut self) -> &mut Self {
+        self.framework.tick();
+        self
+    }
+
+    /// Run microtasks queued by the framework, e.g. renders caused by user input
+    pub fn settle(&mut self) -> &mut Self {
+        self.backend.run_microtasks();
+        self
+    }
+
+    /// Poll futures started by component commands that were woken since the last call
+    pub fn tasks(&mut self) -> &mut Self {
+        self.backend.run_tasks();
+        self
+    }
+
+    /// Run animation frames requested by the framework since the last call,
+    /// pending microtasks run first, the same way they do in the browser
+    pub fn frame(&mut self) -> &mut Self {
+        self.settle();
+        self.backend.run_frames();
+        self
+    }
+
+    pub fn find(&self, selector: &str) -> Option<MemoryNode> {
+        self.find_all(selector).into_iter().next()
+    }
+
+    pub fn find_all(&self, selector: &str) -> Vec<MemoryNode> {
+        let mut result = vec![];
+        collect(
+            &self.backend.body(),
+            &Selector::parse(selector),
+            &mut result,
+        );
+        result
+    }
+
+    fn expect(&self, selector: &str) -> MemoryNode {
+        self.find(selector)
+            .unwrap_or_else(|| panic!("nothing matches {:?} in {}", selector, self.html()))
+    }
+
+    /// Fire event of given type on the first element that matches selector
+    pub fn dispatch(&mut self, selector: &str, event: Event) -> &mut Self {
+        self.expect(selector).dispatch_event(&event);
+        self
+    }
+
+    pub fn click(&mut self, selector: &str) -> &mut Self {
+        self.dispatch(selector, Event::new("click"))
+    }
+
+    /// Change value of a form control and fire `input` event on it
+    pub fn input(&mut self, selector: &str, value: &str) -> &mut Self {
+        self.expect(selector).set_attribute("value", value);
+        self.dispatch(selector, Event::with_value("input", value))
+    }
+
+    /// Text content of the first element that matches selector
+    pub fn text(&self, selector: &str) -> String {
+        self.expect(selector).text_content()
+    }
+
+    pub fn attribute(&self, selector: &str, name: &str) -> Option<String> {
+        self.expect(selector).attribute(name)
+    }
+
+    /// Markup of the whole page
+    pub fn html(&self) -> String {
+        self.backend.body().inner_html()
+    }
+
+    /// Markup rendered inside of the element with given id
+    pub fn html_of(&self, id: &str) -> String {
+        self.expect(&format!("#{}", id)).inner_html()
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ************** Fixtures **************
+
+/// Component without state that handles no messages, its template is rendered as it is,
+/// handy for pages and layouts
+pub struct Stub;
+
+impl Component for Stub {
+    fn render(&self) -> Vec<SharableDomNode> {
+        vec![]
+    }
+
+    fn handle(&mut self, _message: String) -> bool {
+        false
+    }
+}
+
+impl Lookup for Stub {
+    fn lookup(&self, _k: &String) -> Option<LookupValue> {
+        None
+    }
+}
+
+pub fn stub() -> ComponentWrapper {
+    ComponentWrapper::new(Box::new(|| Rc::new(RefCell::new(Stub))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Form {
+        name: String,
+        submitted: bool,
+    }
+
+    impl Component for Form {
+        fn render(&self) -> Vec<SharableDomNode> {
+            vec![]
+        }
+
+        fn handle(&mut self, message: String) -> bool {
+            match message.as_ref() {
+                "submit" => self.submitted = true,
+                _ => return false,
+            }
+            true
+        }
+
+        fn handle_event(&mut self, message: String, event: &Event) -> bool {
+            match message.as_ref() {
+                "rename" => {
+                    self.name = event.value.clone().unwrap_or_default();
+                    true
+                }
+                _ => self.handle(message),
+            }
+        }
+    }
+
+    impl Lookup for Form {
+        fn lookup(&self, k: &String) -> Option<LookupValue> {
+            match k.as_str() {
+                "name" => Some(Box::new(self.name.clone())),
+                "submitted" => Some(Box::new(self.submitted)),
+                _ => None,
+            }
+        }
+    }
+
+    const TEMPLATE: &str = "<form :data-submitted=\"submitted\">\
+        <input id=\"name\" @input=\"rename\">\
+        <p class=\"greeting big\" :title=\"name\">hello</p>\
+        <button type=\"submit\" @click=\"submit\">save</button>\
+        </form>";
+
+    fn harness() -> Harness {
+        let mut harness = Harness::new();
+        let wrapper = ComponentWrapper::new(Box::new(|| {
+            Rc::new(RefCell::new(Form {
+                name: "nobody".to_string(),
+                submitted: false,
+            }))
+        }));
+        harness
+            .framework
+            .register_component_template("signup", wrapper, TEMPLATE);
+        harness.mount("signup");
+        harness
+    }
+
+    #[test]
+    fn selector_matching() {
+        let harness = harness();
+
+        assert_eq!(harness.find_all("p").len(), 1);
+        assert!(harness.find("#name").is_some());
+        assert!(harness.find(".greeting.big").is_some());
+        assert!(harness.find("p.missing").is_none());
+        assert!(harness.find("button[type=submit]").is_some());
+        assert!(harness.find("button[type='reset']").is_none());
+        assert!(harness.find("#app form input[id]").is_some());
+        assert!(harness.find("p form").is_none());
+    }
+
+    #[test]
+    fn click_and_tick() {
+        let mut harness = harness();
+        assert_eq!(
+            harness.attribute("form", "data-submitted").unwrap(),
+            "false"
+        );
+
+        harness.click("button").tick();
+        assert_eq!(harness.attribute("form", "data-submitted").unwrap(), "true");
+    }
+
+    #[test]
+    fn click_and_settle() {
+        let mut harness = harness();
+
+        harness.click("button").settle();
+        assert_eq!(harness.attribute("form", "data-submitted").unwrap(), "true");
+    }
+
+    #[test]
+    fn input_passes_value() {
+        let mut harness = harness();
+        harness.input("#name", "bob").tick();
+
+        assert_eq!(harness.attribute("p", "title").unwrap(), "bob");
+        assert_eq!(harness.text("p"), "hello");
+    }
+
+    #[test]
+    fn html_snapshot() {
+        let harness = harness();
+
+        assert_eq!(
+            harness.html_of(MOUNT_POINT),
+            "<form data-submitted=\"false\"><input id=\"name\">\
+             <p class=\"greeting big\" title=\"nobody\">hello</p>\
+             <button type=\"submit\">save</button></form>"
+        );
+    }
+}
@@ -0,0 +1,447 @@
+/// This package animates elements that get inserted in to or removed from the dom,
+/// css classes are added in phases, the same way Vue does it:
+/// `<name>-enter-from`, `<name>-enter-active` and `<name>-enter-to` when element comes in,
+/// `<name>-leave-from`, `<name>-leave-active` and `<name>-leave-to` when it goes away,
+/// `<name>-move` while keyed element slides to its new place
+use crate::backend::{DomBackend, DomNode};
+use crate::vdom::{Event, Handler};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+/// Tag of the wrapper that animates its children, e.g. `<transition name="fade">`,
+/// also name of the attribute that animates single element, e.g. `<p transition="fade">`
+pub const TRANSITION: &str = "transition";
+
+/// Longest time transition may take in milliseconds, element is removed after it
+/// even if browser never fires `transitionend`, e.g. because there are no styles for it
+pub const DEFAULT_DURATION: i32 = 500;
+
+/// Events that tell css transition or animation of an element is over
+const END_EVENTS: [&str; 2] = ["transitionend", "animationend"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub name: String,
+    pub duration: i32,
+}
+
+impl Transition {
+    pub fn new(name: &str) -> Self {
+        Transition {
+            name: name.to_string(),
+            duration: DEFAULT_DURATION,
+        }
+    }
+
+    pub fn duration(mut self, millis: i32) -> Self {
+        self.duration = millis;
+        self
+    }
+
+    fn class(&self, phase: &str) -> String {
+        format!("{}-{}", self.name, phase)
+    }
+
+    /// Animate element that was just inserted
+    pub fn enter(&self, backend: &Rc<dyn DomBackend>, node: &DomNode) {
+        self.run(backend, node, "enter", None);
+    }
+
+    /// Animate element out, it is removed from the parent once animation is over
+    pub fn leave(&self, backend: &Rc<dyn DomBackend>, parent: &DomNode, node: &DomNode) {
+        self.run(backend, node, "leave", Some(parent.clone()));
+    }
+
+    /// Animate element that was moved from `first` to `last` position, it is put back
+    /// where it was with a transform and then slides to its place (FLIP)
+    pub fn slide(
+        &self,
+        backend: &Rc<dyn DomBackend>,
+        node: &DomNode,
+        first: (f64, f64),
+        last: (f64, f64),
+    ) {
+        let (dx, dy) = (first.0 - last.0, first.1 - last.1);
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+
+        let class = self.class("move");
+        let transform = format!("translate({}px, {}px)", dx, dy);
+        backend.set_style(node, "transform", Some(&transform));
+        backend.set_style(node, "transition-duration", Some("0s"));
+
+        let duration = self.duration;
+        let frame_backend = Rc::clone(backend);
+        let node = node.clone();
+
+        // element has to be rendered where it was before it is let go
+        after_next_frame(
+            backend,
+            Box::new(move || {
+                let backend = frame_backend;
+                add_class(&*backend, &node, &class);
+                backend.set_style(&node, "transform", None);
+                backend.set_style(&node, "transition-duration", None);
+
+                let done_backend = Rc::clone(&backend);
+                let done_node = node.clone();
+                on_end(
+                    &backend,
+                    &node,
+                    duration,
+                    Box::new(move || remove_class(&*done_backend, &done_node, &class)),
+                );
+            }),
+        );
+    }
+
+    fn run(
+        &self,
+        backend: &Rc<dyn DomBackend>,
+        node: &DomNode,
+        phase: &str,
+        remove_from: Option<DomNode>,
+    ) {
+        let (from, active, to) = (
+            self.class(&format!("{}-from", phase)),
+            self.class(&format!("{}-active", phase)),
+            self.class(&format!("{}-to", phase)),
+        );
+
+        add_class(&**backend, node, &from);
+        add_class(&**backend, node, &active);
+
+        let duration = self.duration;
+        let frame_backend = Rc::clone(backend);
+        let node = node.clone();
+
+        // styles of the first phase have to be applied before they are changed
+        after_next_frame(
+            backend,
+            Box::new(move || {
+                let backend = frame_backend;
+                remove_class(&*backend, &node, &from);
+                add_class(&*backend, &node, &to);
+
+                let done_backend = Rc::clone(&backend);
+                let done_node = node.clone();
+                on_end(
+                    &backend,
+                    &node,
+                    duration,
+                    Box::new(move || match remove_from {
+                        Some(parent) => {
+                            let attached = done_backend
+                                .parent(&done_node)
+                                .is_some_and(|p| p.is_same(&parent));
+
+                            // parent may have been cleared in the meantime
+                            if attached {
+                                done_backend.remove_child(&parent, &done_node);
+                            }
+                        }
+                        None => {
+                            remove_class(&*done_backend, &done_node, &active);
+                            remove_class(&*done_backend, &done_node, &to);
+                        }
+                    }),
+                );
+            }),
+        );
+    }
+}
+
+/// Run callback once the browser rendered the next frame, classes set now are computed
+/// by then; callback of a single frame runs before that frame is rendered
+fn after_next_frame(backend: &Rc<dyn DomBackend>, callback: Box<dyn FnOnce()>) {
+    let inner = Rc::downgrade(backend);
+    backend.request_frame(Box::new(move || {
+        if let Some(backend) = inner.upgrade() {
+            backend.request_frame(callback);
+        }
+    }));
+}
+
+/// Callback that waits for the end of a transition together with listeners that call it
+struct Pending {
+    done: Option<Box<dyn FnOnce()>>,
+    handlers: Vec<Handler>,
+}
+
+/// Call `done` once transition or animation of the node itself ends or duration elapses,
+/// whatever comes first
+fn on_end(backend: &Rc<dyn DomBackend>, node: &DomNode, duration: i32, done: Box<dyn FnOnce()>) {
+    let pending = Rc::new(RefCell::new(Pending {
+        done: Some(done),
+        handlers: vec![],
+    }));
+
+    for event in END_EVENTS {
+        let weak = Rc::downgrade(&pending);
+        let handler_backend = Rc::downgrade(backend);
+        let target = node.clone();
+
+        let handler = Handler::new(event, move |event: &Event| {
+            // events of descendants bubble up, they are not the end of this transition
+            let own = event.target.as_ref().is_some_and(|t| t.0.is_same(&target));
+
+            if let (true, Some(backend)) = (own, handler_backend.upgrade()) {
+                finish(&weak, &backend, &target);
+            }
+        });
+
+        backend.add_listener(node, event, &handler);
+        pending.borrow_mut().handlers.push(handler);
+    }
+
+    // timeout keeps pending transition alive until it is over one way or another
+    let weak = Rc::downgrade(&pending);
+    let timeout_backend = Rc::downgrade(backend);
+    let target = node.clone();
+    backend.set_timeout(
+        duration,
+        Box::new(move || {
+            if let Some(backend) = timeout_backend.upgrade() {
+                finish(&weak, &backend, &target);
+            }
+            drop(pending);
+        }),
+    );
+}
+
+fn finish(pending: &Weak<RefCell<Pending>>, backend: &Rc<dyn DomBackend>, node: &DomNode) {
+    let pending = match pending.upgrade() {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    let (done, handlers) = {
+        let mut pending = pending.borrow_mut();
+        (pending.done.take(), std::mem::take(&mut pending.handlers))
+    };
+
+    for (event, handler) in END_EVENTS.iter().zip(handlers.iter()) {
+        backend.remove_listener(node, event, handler);
+    }
+
+    // one of the handlers may be running right now, it is dropped once it returns
+    backend.queue_microtask(Box::new(move || drop(handlers)));
+
+    if let Some(done) = done {
+        done();
+    }
+}
+
+fn classes(backend: &dyn DomBackend, node: &DomNode) -> Vec<String> {
+    backend
+        .attribute(node, "class")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+fn add_class(backend: &dyn DomBackend, node: &DomNode, class: &str) {
+    let mut classes = classes(backend, node);
+
+    if !classes.iter().any(|c| c == class) {
+        classes.push(class.to_string());
+        backend.set_attribute(node, "class", &classes.join(" "));
+    }
+}
+
+fn remove_class(backend: &dyn DomBackend, node: &DomNode, class: &str) {
+    let classes: Vec<_> = classes(backend, node)
+        .into_iter()
+        .filter(|c| c != class)
+        .collect();
+
+    match classes.is_empty() {
+        true => backend.remove_attribute(node, "class"),
+        false => backend.set_attribute(node, "class", &classes.join(" ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MemoryBackend, MemoryNode};
+    use crate::router::{Router, RouterMode};
+    use crate::testing::{stub, Harness};
+    use crate::vdom::{apply_changes, diff_children, Namespace, VNode, VNodeData};
+    use std::collections::HashMap;
+
+    fn backend() -> (Rc<MemoryBackend>, Rc<dyn DomBackend>) {
+        let memory = Rc::new(MemoryBackend::new());
+        let backend: Rc<dyn DomBackend> = Rc::clone(&memory) as _;
+        (memory, backend)
+    }
+
+    fn fading(tag: &str, children: Vec<VNode>) -> VNode {
+        let data = VNodeData::Element {
+            tag: tag.to_string(),
+            namespace: Namespace::Html,
+            attributes: HashMap::new(),
+        };
+        let mut vnode = VNode::new(data, children);
+        vnode.transition = Some(Transition::new("fade").duration(100));
+        vnode
+    }
+
+    fn patch(backend: &Rc<dyn DomBackend>, parent: &DomNode, old: &[VNode], new: &[VNode]) {
+        apply_changes(diff_children(old, new, parent), backend);
+    }
+
+    fn keyed(key: &str) -> VNode {
+        let mut vnode = fading("li", vec![]);
+        vnode.key = Some(key.to_string());
+        vnode
+    }
+
+    fn class(node: &MemoryNode) -> Option<String> {
+        node.attribute("class")
+    }
+
+    #[test]
+    fn entering_element_gets_classes_in_phases() {
+        let (memory, backend) = backend();
+        let body = DomNode::Memory(memory.body());
+
+        let new = vec![fading("p", vec![])];
+        patch(&backend, &body, &[], &new);
+
+        let p = memory.body().children()[0].clone();
+        assert_eq!(class(&p).unwrap(), "fade-enter-from fade-enter-active");
+
+        // the first frame renders classes of the first phase as they are
+        memory.run_frames();
+        assert_eq!(class(&p).unwrap(), "fade-enter-from fade-enter-active");
+
+        memory.run_frames();
+        assert_eq!(class(&p).unwrap(), "fade-enter-active fade-enter-to");
+
+        p.dispatch("transitionend");
+        assert_eq!(class(&p), None);
+
+        // listeners are dropped once the transition is over
+        memory.run_microtasks();
+        assert_eq!(p.listener_count("transitionend"), 0);
+    }
+
+    #[test]
+    fn leaving_element_stays_until_its_own_transition_ends() {
+        let (memory, backend) = backend();
+        let body = DomNode::Memory(memory.body());
+
+        let old = vec![fading("ul", vec![fading("li", vec![])])];
+        for vnode in old.iter() {
+            backend.append_child(&body, &vnode.to_dom(&*backend).borrow());
+        }
+        patch(&backend, &body, &old, &[]);
+
+        let ul = memory.body().children()[0].clone();
+        assert_eq!(class(&ul).unwrap(), "fade-leave-from fade-leave-active");
+
+        memory.run_frames();
+        memory.run_frames();
+        assert_eq!(class(&ul).unwrap(), "fade-leave-active fade-leave-to");
+
+        // transition of a child bubbles up, but it does not end the one of the list
+        ul.children()[0].dispatch("animationend");
+        assert_eq!(memory.body().children().len(), 1);
+
+        ul.dispatch("animationend");
+        assert!(memory.body().children().is_empty());
+    }
+
+    #[test]
+    fn leaving_element_is_removed_once_duration_elapses() {
+        let (memory, backend) = backend();
+        let body = DomNode::Memory(memory.body());
+
+        let old = vec![fading("p", vec![])];
+        backend.append_child(&body, &old[0].to_dom(&*backend).borrow());
+        patch(&backend, &body, &old, &[]);
+        memory.run_frames();
+        memory.run_frames();
+
+        memory.advance(99);
+        assert_eq!(memory.body().children().len(), 1);
+
+        memory.advance(1);
+        assert!(memory.body().children().is_empty());
+    }
+
+    #[test]
+    fn moved_element_slides_to_its_place() {
+        let (memory, backend) = backend();
+        let body = DomNode::Memory(memory.body());
+
+        let old = vec![keyed("a"), keyed("b"), keyed("c")];
+        for vnode in old.iter() {
+            backend.append_child(&body, &vnode.to_dom(&*backend).borrow());
+        }
+        let c = memory.body().children()[2].clone();
+
+        let new = vec![keyed("c"), keyed("a"), keyed("b")];
+        patch(&backend, &body, &old, &new);
+
+        // moved from the third place to the first one, it is put back where it was
+        assert!(memory.body().children()[0].is_same(&c));
+        assert_eq!(
+            c.attribute("style").unwrap(),
+            "transform: translate(0px, 2px); transition-duration: 0s"
+        );
+        assert_eq!(
+            memory.body().children()[1].attribute("style").unwrap(),
+            "transform: translate(0px, -1px); transition-duration: 0s"
+        );
+
+        memory.run_frames();
+        memory.run_frames();
+        assert_eq!(c.attribute("style"), None);
+        assert_eq!(class(&c).unwrap(), "fade-move");
+
+        c.dispatch("transitionend");
+        assert_eq!(class(&c), None);
+    }
+
+    #[test]
+    fn pages_of_router_view_fade_in_and_out() {
+        let mut harness = Harness::new();
+
+        let framework = &mut harness.framework;
+        framework.register_component_template(
+            "shell",
+            stub(),
+            "<router-link to=\"/about\">about</router-link>\
+             <transition name=\"page\" duration=\"300\"><router-view></router-view></transition>",
+        );
+        framework.register_component_template("home", stub(), "<h1>home</h1>");
+        framework.register_component_template("about", stub(), "<h2>about</h2>");
+        framework.use_router(Rc::new(
+            Router::new(RouterMode::History)
+                .route("/", "home")
+                .route("/about", "about"),
+        ));
+        harness.mount("shell");
+
+        // nothing is animated on the first render
+        assert_eq!(harness.attribute("h1", "class"), None);
+
+        harness.click("a").frame().frame();
+        assert_eq!(
+            harness.html_of("app"),
+            "<a href=\"/about\">about</a>\
+             <h2 class=\"page-enter-active page-enter-to\">about</h2>\
+             <h1 class=\"page-leave-active page-leave-to\">home</h1>"
+        );
+
+        harness.backend().advance(300);
+        assert_eq!(
+            harness.html_of("app"),
+            "<a href=\"/about\">about</a><h2>about</h2>"
+        );
+    }
+}
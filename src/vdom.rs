@@ -1,133 +1,1871 @@
 /// This package should represent virtual dom structures and diffing and changeset generation
 /// functionality
-use std::cell::RefCell;
+pub use crate::backend::{DomBackend, DomNode};
+use crate::transition::Transition;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::ops::Deref;
-use std::rc::Rc;
+use std::fmt;
+use std::rc::{Rc, Weak};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
 pub type SharableDomNode = Rc<RefCell<DomNode>>;
 
-pub enum DomNode {
-    Text(web_sys::Text),
-    Element(web_sys::Element),
+pub type HandlerClosure = Closure<dyn FnMut(web_sys::Event)>;
+
+/// Node event was originally fired on
+#[derive(Clone)]
+pub struct EventTarget(pub DomNode);
+
+impl fmt::Debug for EventTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EventTarget")
+    }
 }
 
-impl Deref for DomNode {
-    type Target = web_sys::Node;
+impl PartialEq for EventTarget {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.is_same(&other.0)
+    }
+}
 
-    fn deref(&self) -> &web_sys::Node {
-        match self {
-            DomNode::Text(txt) => txt.as_ref(),
-            DomNode::Element(el) => el.as_ref(),
+/// Modifier keys held down while mouse or keyboard event was fired
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub meta: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    pub fn any(&self) -> bool {
+        self.ctrl || self.meta || self.shift || self.alt
+    }
+}
+
+/// Browser independent details of a dom event that are passed to components
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: String,
+    /// Current value of form control event was fired on
+    pub value: Option<String>,
+    /// Key that was pressed for keyboard events, e.g. `Enter` or `k`
+    pub key: Option<String>,
+    /// Mouse button that was pressed, `0` is the main button
+    pub button: i16,
+    pub modifiers: Modifiers,
+    pub target: Option<EventTarget>,
+    /// Set by handlers that do not want the browser to do its default action
+    default_prevented: Rc<Cell<bool>>,
+}
+
+impl Event {
+    pub fn new(kind: &str) -> Self {
+        Event {
+            kind: kind.to_string(),
+            value: None,
+            key: None,
+            button: 0,
+            modifiers: Modifiers::default(),
+            target: None,
+            default_prevented: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// Stop browser from doing what it does by default, e.g. following a link,
+    /// shared between clones of the event, so every handler sees it
+    pub fn prevent_default(&self) {
+        self.default_prevented.set(true);
+    }
+
+    pub fn default_prevented(&self) -> bool {
+        self.default_prevented.get()
+    }
+
+    pub fn with_value(kind: &str, value: &str) -> Self {
+        Event {
+            value: Some(value.to_string()),
+            ..Event::new(kind)
+        }
+    }
+
+    pub fn with_key(kind: &str, key: &str) -> Self {
+        Event {
+            key: Some(key.to_string()),
+            ..Event::new(kind)
+        }
+    }
+
+    pub fn with_button(kind: &str, button: i16) -> Self {
+        Event {
+            button,
+            ..Event::new(kind)
+        }
+    }
+
+    pub fn with_modifiers(kind: &str, modifiers: Modifiers) -> Self {
+        Event {
+            modifiers,
+            ..Event::new(kind)
+        }
+    }
+
+    fn from_web(event: &web_sys::Event) -> Self {
+        let target = event.target();
+        let target = target.as_ref();
+
+        let value = target
+            .and_then(|t| t.dyn_ref::<web_sys::HtmlInputElement>())
+            .map(|input| input.value())
+            .or_else(|| {
+                target
+                    .and_then(|t| t.dyn_ref::<web_sys::HtmlTextAreaElement>())
+                    .map(|area| area.value())
+            })
+            .or_else(|| {
+                target
+                    .and_then(|t| t.dyn_ref::<web_sys::HtmlSelectElement>())
+                    .map(|select| select.value())
+            });
+
+        let key = event
+            .dyn_ref::<web_sys::KeyboardEvent>()
+            .map(|keyboard| keyboard.key());
+
+        let mouse = event.dyn_ref::<web_sys::MouseEvent>();
+        let button = mouse.map_or(0, |mouse| mouse.button());
+        let modifiers = match (mouse, event.dyn_ref::<web_sys::KeyboardEvent>()) {
+            (Some(mouse), _) => Modifiers {
+                ctrl: mouse.ctrl_key(),
+                meta: mouse.meta_key(),
+                shift: mouse.shift_key(),
+                alt: mouse.alt_key(),
+            },
+            (None, Some(keyboard)) => Modifiers {
+                ctrl: keyboard.ctrl_key(),
+                meta: keyboard.meta_key(),
+                shift: keyboard.shift_key(),
+                alt: keyboard.alt_key(),
+            },
+            (None, None) => Modifiers::default(),
+        };
+
+        let target = event
+            .target()
+            .and_then(|t| t.dyn_into::<web_sys::Node>().ok())
+            .map(|node| EventTarget(DomNode::Web(node)));
+
+        Event {
+            value,
+            key,
+            button,
+            modifiers,
+            target,
+            ..Event::new(&event.type_())
         }
     }
 }
 
-pub type HandlerClosure = Closure<dyn FnMut(web_sys::Event)>;
+pub type Listener = Rc<dyn Fn(&Event)>;
+pub type WeakListener = Weak<dyn Fn(&Event)>;
+
+/// Js closure listening on a target, the listener is removed together with the closure
+struct Attached {
+    target: web_sys::EventTarget,
+    event: String,
+    closure: HandlerClosure,
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        // target may be gone already, there is nothing left to clean up then
+        let _ = self.target.remove_event_listener_with_callback(
+            &self.event,
+            self.closure.as_ref().unchecked_ref(),
+        );
+    }
+}
+
+/// Js closure called by a browser interval, the interval is cleared together with the closure
+struct Interval {
+    window: web_sys::Window,
+    id: i32,
+    _closure: HandlerClosure,
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        self.window.clear_interval_with_handle(self.id);
+    }
+}
+
+/// Event handler produced from `@event` binding,
+/// js closure is created only when handler gets attached to a real dom element
+/// and lives exactly as long as the handler, or the handler of a newer render that adopted it
+pub struct Handler {
+    pub message: String,
+    callback: RefCell<Listener>,
+    attached: RefCell<Option<Attached>>,
+    interval: RefCell<Option<Interval>>,
+    /// Stop browser from doing what it does by default, e.g. following a link
+    prevent_default: bool,
+    /// Id of the component the callback dispatches to, handlers of different components
+    /// are never mistaken for each other even if their messages are the same
+    owner: usize,
+}
+
+impl Handler {
+    pub fn new(message: &str, callback: impl Fn(&Event) + 'static) -> Self {
+        Handler {
+            message: message.to_string(),
+            callback: RefCell::new(Rc::new(callback)),
+            attached: RefCell::new(None),
+            interval: RefCell::new(None),
+            prevent_default: false,
+            owner: 0,
+        }
+    }
+
+    pub fn prevent_default(mut self) -> Self {
+        self.prevent_default = true;
+        self
+    }
+
+    pub fn owner(mut self, owner: usize) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    pub fn prevents_default(&self) -> bool {
+        self.prevent_default
+    }
+
+    pub fn call(&self, event: &Event) {
+        (self.listener())(event);
+    }
+
+    /// Shared callback, backends that are not browser based call it directly
+    pub fn listener(&self) -> Listener {
+        Rc::clone(&self.callback.borrow())
+    }
+
+    /// Handlers that would do the same thing when called
+    pub fn same(&self, other: &Handler) -> bool {
+        self.message == other.message
+            && self.owner == other.owner
+            && self.prevent_default == other.prevent_default
+    }
+
+    /// Take over callback and attached listener of the same handler from previous render,
+    /// so nothing has to be detached and attached again
+    pub fn adopt(&self, old: &Handler) {
+        *self.callback.borrow_mut() = old.listener();
+        *self.attached.borrow_mut() = old.attached.borrow_mut().take();
+    }
+
+    fn closure(&self) -> HandlerClosure {
+        let callback = self.listener();
+        let prevent_default = self.prevent_default;
+
+        Closure::wrap(Box::new(move |e: web_sys::Event| {
+            let event = Event::from_web(&e);
+            if prevent_default {
+                event.prevent_default();
+            }
+
+            callback(&event);
+
+            if event.default_prevented() {
+                e.prevent_default();
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>)
+    }
+
+    pub fn attach(&self, target: &web_sys::EventTarget, event: &str) {
+        let closure = self.closure();
+
+        target
+            .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())
+            .expect("colud not add event listener");
+
+        // replaces listener handler had before, if any
+        *self.attached.borrow_mut() = Some(Attached {
+            target: target.clone(),
+            event: event.to_string(),
+            closure,
+        });
+    }
+
+    /// Remove listener added by `attach`, unless handler was attached elsewhere since
+    pub fn detach(&self, target: &web_sys::EventTarget, event: &str) {
+        let mut attached = self.attached.borrow_mut();
+
+        if attached
+            .as_ref()
+            .is_some_and(|a| a.target == *target && a.event == event)
+        {
+            // listener is removed from the target when dropped
+            attached.take();
+        }
+    }
+
+    /// Call handler with `interval` event every `millis` milliseconds
+    pub fn start_interval(&self, window: &web_sys::Window, millis: i32) {
+        let callback = self.listener();
+        let closure =
+            Closure::wrap(
+                Box::new(move |_: web_sys::Event| callback(&Event::new(INTERVAL_EVENT)))
+                    as Box<dyn FnMut(web_sys::Event)>,
+            );
+
+        let id = window
+            .set_interval_with_callback_and_timeout_and_arguments_0(
+                closure.as_ref().unchecked_ref(),
+                millis,
+            )
+            .expect("could not set interval");
+
+        *self.interval.borrow_mut() = Some(Interval {
+            window: window.clone(),
+            id,
+            _closure: closure,
+        });
+    }
+
+    /// Clear interval started by `start_interval`, the window it runs in is kept with it
+    pub fn stop_interval(&self) {
+        self.interval.borrow_mut().take();
+    }
+}
+
+/// Kind of the event handlers receive from intervals
+pub const INTERVAL_EVENT: &str = "interval";
 
 pub enum VAttribute {
     Attribute(String),
-    Handler(HandlerClosure),
+    Handler(Handler),
 }
 
 pub type VAttributes = HashMap<String, VAttribute>;
 
+/// Namespace an element is created in, content of inline `<svg>` and `<math>`
+/// is not rendered by the browser when created as html elements
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Namespace {
+    #[default]
+    Html,
+    Svg,
+    MathMl,
+}
+
+impl Namespace {
+    pub fn uri(self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+        }
+    }
+
+    /// Namespace with given uri, anything unknown is treated as html
+    pub fn from_uri(uri: &str) -> Self {
+        [Namespace::Svg, Namespace::MathMl]
+            .iter()
+            .copied()
+            .find(|namespace| namespace.uri() == uri)
+            .unwrap_or(Namespace::Html)
+    }
+}
+
 pub enum VNodeData {
     Element {
         tag: String,
+        namespace: Namespace,
         attributes: VAttributes,
     },
     Text {
         content: String,
     },
+    /// Siblings without a wrapping element, e.g. roots of a nested component,
+    /// rendered as its children followed by an empty text node that anchors the end of the group
+    Fragment,
 }
 
-// No need for parent node pointer
-// Parent can be accessed with parentNode js call
-// in web_sys this would be parent_node() call on Node
-pub enum Change {
+/// Single dom operation produced by diffing two virtual trees,
+/// new nodes are referenced as virtual nodes and get created while change is applied
+pub enum Change<'a> {
     Insert {
-        element: SharableDomNode,
+        parent: DomNode,
+        vnode: &'a VNode,
+        /// Node goes in front of this one, to the end of the parent when there is none
+        before: Option<DomNode>,
     },
     Delete {
-        element: SharableDomNode,
+        parent: DomNode,
+        vnode: &'a VNode,
     },
-    SetAttributes {
-        element: SharableDomNode,
-        attributes: VAttributes,
+    Replace {
+        parent: DomNode,
+        old: &'a VNode,
+        vnode: &'a VNode,
+    },
+    SetAttribute {
+        element: DomNode,
+        name: &'a str,
+        value: &'a str,
+    },
+    RemoveAttribute {
+        element: DomNode,
+        name: &'a str,
     },
     AddHandler {
-        element: SharableDomNode,
-        attributes: VAttributes,
+        element: DomNode,
+        event: &'a str,
+        handler: &'a Handler,
+    },
+    RemoveHandler {
+        element: DomNode,
+        event: &'a str,
+        handler: &'a Handler,
     },
     SetInnerText {
-        element: SharableDomNode,
-        content: String,
+        element: DomNode,
+        content: &'a str,
+    },
+    /// Put keyed node in front of its next sibling, or in front of `end` when it is the last,
+    /// node is created if it was not rendered yet
+    Place {
+        parent: DomNode,
+        vnode: &'a VNode,
+        next: Option<&'a VNode>,
+        end: Option<DomNode>,
+    },
+    /// Keyed node may end up somewhere else, it slides there from where it was,
+    /// see `apply_changes`
+    Slide {
+        vnode: &'a VNode,
     },
 }
 
 // Application should be trivial to implement
-impl Change {
-    pub fn apply() {}
+impl<'a> Change<'a> {
+    pub fn apply(self, backend: &Rc<dyn DomBackend>) {
+        match self {
+            Change::Insert {
+                parent,
+                vnode,
+                before,
+            } => vnode.mount(backend, &parent, before.as_ref()),
+            Change::Delete { parent, vnode } => vnode.unmount(backend, &parent),
+            Change::Replace { parent, old, vnode } => match (&old.data, &vnode.data) {
+                (VNodeData::Fragment, _) | (_, VNodeData::Fragment) => {
+                    vnode.mount(backend, &parent, Some(&old.nodes()[0]));
+                    old.unmount(backend, &parent);
+                }
+                // new node takes the place of the old one while it is leaving
+                _ if old.transition.is_some() => {
+                    vnode.mount(backend, &parent, Some(&old.node()));
+                    old.unmount(backend, &parent);
+                }
+                _ => {
+                    let node = vnode.to_dom(&**backend);
+                    backend.replace_child(&parent, &node.borrow(), &old.node());
+                    vnode.enter(backend);
+                }
+            },
+            Change::SetAttribute {
+                element,
+                name,
+                value,
+            } => backend.set_attribute(&element, name, value),
+            Change::RemoveAttribute { element, name } => backend.remove_attribute(&element, name),
+            Change::AddHandler {
+                element,
+                event,
+                handler,
+            } => backend.add_listener(&element, event, handler),
+            Change::RemoveHandler {
+                element,
+                event,
+                handler,
+            } => backend.remove_listener(&element, event, handler),
+            Change::SetInnerText { element, content } => backend.set_text(&element, content),
+            Change::Place {
+                parent,
+                vnode,
+                next,
+                end,
+            } => {
+                let before = next.map(|next| next.nodes()[0].clone()).or(end);
+                let rendered = vnode.dom.borrow().is_some();
+
+                if !rendered {
+                    return vnode.mount(backend, &parent, before.as_ref());
+                }
+
+                for node in vnode.nodes() {
+                    match &before {
+                        Some(reference) => backend.insert_before(&parent, &node, reference),
+                        None => backend.append_child(&parent, &node),
+                    }
+                }
+            }
+            // positions before and after the whole patch are needed, nothing to do alone
+            Change::Slide { .. } => {}
+        }
+    }
+}
+
+/// Apply changes of a patch in order, nodes that slide are measured before anything changes
+/// and once everything is in place, then animated from one position to the other
+pub fn apply_changes(changes: Vec<Change>, backend: &Rc<dyn DomBackend>) {
+    let slides: Vec<_> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Slide { vnode } => {
+                let node = vnode.dom.borrow().clone()?;
+                let first = backend.position(&node)?;
+                Some((*vnode, node, first))
+            }
+            _ => None,
+        })
+        .collect();
+
+    for change in changes {
+        change.apply(backend);
+    }
+
+    for (vnode, node, first) in slides {
+        if let (Some(transition), Some(last)) = (&vnode.transition, backend.position(&node)) {
+            transition.slide(backend, &node, first, last);
+        }
+    }
 }
 
 pub type VDom = Vec<VNode>;
 
+/// Prefix of attributes that mark event handlers in rendered html
+pub const HANDLER_MARKER: &str = "data-on-";
+
+/// Elements that can not have children and are rendered without closing tag
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is not markup, so it is written out without escaping
+pub const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+pub fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn escape_attribute(value: &str) -> String {
+    escape_text(value)
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Attributes whose value is a url the browser navigates to or loads
+pub const URL_ATTRIBUTES: &[&str] = &["href", "src", "action", "formaction", "data", "xlink:href"];
+
+/// Attributes whose value is markup the browser renders as a document, e.g. `<iframe srcdoc>`,
+/// they can not be bound at all
+pub const MARKUP_ATTRIBUTES: &[&str] = &["srcdoc"];
+
+/// Schemes urls of dynamic attributes may have, relative urls have no scheme and are fine
+pub const SAFE_URL_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+/// Reason dynamic attribute value was not rendered
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// `on*` attributes run their value as a script
+    EventHandlerAttribute { name: String },
+    /// Url with a scheme outside of `SAFE_URL_SCHEMES`, e.g. `javascript:`
+    UnsafeUrl { name: String, url: String },
+    /// Markup of `MARKUP_ATTRIBUTES` runs its own scripts
+    MarkupAttribute { name: String },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::EventHandlerAttribute { name } => {
+                write!(f, "{} can not be bound, use @{} instead", name, &name[2..])
+            }
+            Violation::UnsafeUrl { name, url } => write!(f, "{} has unsafe url {:?}", name, url),
+            Violation::MarkupAttribute { name } => write!(f, "{} can not be bound", name),
+        }
+    }
+}
+
+/// Whether `setAttribute` accepts the name, i.e. it is an xml name, e.g. `title`, `xlink:href`
+/// or `:title`, but not `@click`
+pub fn is_valid_attribute_name(name: &str) -> bool {
+    let start = |c: char| c.is_alphabetic() || c == '_' || c == ':' || !c.is_ascii();
+    let rest = |c: char| start(c) || c.is_ascii_digit() || c == '-' || c == '.';
+
+    let mut chars = name.chars();
+    chars.next().is_some_and(start) && chars.all(rest)
+}
+
+/// Check value bound to an attribute at runtime, static attributes written
+/// in templates are trusted and never go through this
+pub fn sanitize_attribute(name: &str, value: &str) -> Result<(), Violation> {
+    let name = name.to_lowercase();
+
+    if name.starts_with("on") {
+        return Err(Violation::EventHandlerAttribute { name });
+    }
+
+    if MARKUP_ATTRIBUTES.contains(&name.as_str()) {
+        return Err(Violation::MarkupAttribute { name });
+    }
+
+    if !URL_ATTRIBUTES.contains(&name.as_str()) {
+        return Ok(());
+    }
+
+    match url_scheme(value) {
+        Some(scheme) if !SAFE_URL_SCHEMES.contains(&scheme.as_str()) => Err(Violation::UnsafeUrl {
+            name,
+            url: value.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Lower case scheme of an url, `None` for relative urls.
+/// Browsers ignore surrounding whitespace and tabs or newlines anywhere in the url,
+/// so `java\tscript:` is a `javascript:` url too
+fn url_scheme(url: &str) -> Option<String> {
+    let url: String = url
+        .trim_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+
+    let end = url.find([':', '/', '?', '#'])?;
+    let scheme = &url[..end];
+
+    let valid = url[end..].starts_with(':')
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+
+    match valid {
+        true => Some(scheme.to_ascii_lowercase()),
+        false => None,
+    }
+}
+
+fn hydrate_attributes(
+    backend: &dyn DomBackend,
+    element: &DomNode,
+    tag: &str,
+    attributes: &VAttributes,
+    mismatches: &mut Vec<String>,
+) {
+    for (name, attribute) in attributes {
+        match attribute {
+            VAttribute::Attribute(value) => {
+                if backend.attribute(element, name).as_ref() != Some(value) {
+                    mismatches.push(format!(
+                        "<{}> attribute {} differs from {:?}",
+                        tag, name, value
+                    ));
+                    backend.set_attribute(element, name, value);
+                }
+            }
+            VAttribute::Handler(handler) => backend.add_listener(element, name, handler),
+        }
+    }
+
+    for name in backend.attribute_names(element) {
+        if let Some(event) = name.strip_prefix(HANDLER_MARKER) {
+            if !matches!(attributes.get(event), Some(VAttribute::Handler(_))) {
+                mismatches.push(format!("<{}> has no handler for {}", tag, event));
+            }
+        } else if !matches!(attributes.get(&name), Some(VAttribute::Attribute(_))) {
+            mismatches.push(format!("<{}> has unexpected attribute {}", tag, name));
+        } else {
+            continue;
+        }
+
+        backend.remove_attribute(element, &name);
+    }
+}
+
+/// Hydrate every child of `parent` against virtual nodes, removing unexpected leftovers
+pub fn hydrate_children(
+    backend: &dyn DomBackend,
+    vnodes: &[VNode],
+    parent: &DomNode,
+    mismatches: &mut Vec<String>,
+) -> Vec<SharableDomNode> {
+    let existing = backend.children(parent);
+    let mut next = 0;
+
+    let result = hydrate_siblings(backend, vnodes, parent, &existing, &mut next, mismatches);
+
+    for node in existing.iter().skip(next) {
+        mismatches.push(format!("unexpected {}", describe(backend, node)));
+        backend.remove_child(parent, node);
+    }
+
+    result
+}
+
+/// Hydrate virtual nodes against existing children starting from `next`, fragments adopt
+/// as many children as they have nodes and get their anchor inserted right after them
+fn hydrate_siblings(
+    backend: &dyn DomBackend,
+    vnodes: &[VNode],
+    parent: &DomNode,
+    existing: &[DomNode],
+    next: &mut usize,
+    mismatches: &mut Vec<String>,
+) -> Vec<SharableDomNode> {
+    let mut result = vec![];
+    let mut index = 0;
+
+    while let Some(vnode) = vnodes.get(index) {
+        match vnode.data {
+            VNodeData::Text { .. } => {
+                let run = vnodes[index..]
+                    .iter()
+                    .take_while(|vnode| matches!(vnode.data, VNodeData::Text { .. }))
+                    .count();
+                let texts = &vnodes[index..index + run];
+                index += run;
+
+                result.extend(hydrate_texts(
+                    backend, texts, parent, existing, next, mismatches,
+                ));
+                continue;
+            }
+            VNodeData::Fragment => {
+                let children =
+                    hydrate_siblings(backend, &vnode.children, parent, existing, next, mismatches);
+                result.extend(children);
+
+                // server markup has no anchors, they are not a mismatch
+                let anchor = backend.create_text("");
+                match existing.get(*next) {
+                    Some(following) => backend.insert_before(parent, &anchor, following),
+                    None => backend.append_child(parent, &anchor),
+                }
+
+                *vnode.dom.borrow_mut() = Some(anchor.clone());
+                result.push(Rc::new(RefCell::new(anchor)));
+            }
+            _ => {
+                result.push(vnode.hydrate(backend, existing.get(*next), parent, mismatches));
+                *next += 1;
+            }
+        }
+
+        index += 1;
+    }
+
+    result
+}
+
+/// Hydrate adjacent text nodes, parser merges them in to a single text node
+/// and server writes nothing for empty ones, so the existing node is split up again
+fn hydrate_texts(
+    backend: &dyn DomBackend,
+    texts: &[VNode],
+    parent: &DomNode,
+    existing: &[DomNode],
+    next: &mut usize,
+    mismatches: &mut Vec<String>,
+) -> Vec<SharableDomNode> {
+    let joined: String = texts
+        .iter()
+        .filter_map(|vnode| match &vnode.data {
+            VNodeData::Text { content } => Some(content.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let text_node = existing
+        .get(*next)
+        .filter(|node| backend.text(node).is_some());
+
+    let (first, rest) = match text_node {
+        Some(node) if texts.len() > 1 => {
+            if backend.text(node).as_deref() != Some(joined.as_str()) {
+                mismatches.push(format!(
+                    "{} differs from {:?}",
+                    describe(backend, node),
+                    joined
+                ));
+            }
+
+            *next += 1;
+            let first = texts[0].adopt_text(backend, node);
+            (vec![first], &texts[1..])
+        }
+        Some(node) => {
+            let adopted = texts[0].hydrate(backend, Some(node), parent, mismatches);
+            *next += 1;
+            return vec![adopted];
+        }
+        None if joined.is_empty() => (vec![], texts),
+        None => {
+            return texts
+                .iter()
+                .map(|vnode| {
+                    let adopted = vnode.hydrate(backend, existing.get(*next), parent, mismatches);
+                    *next += 1;
+                    adopted
+                })
+                .collect()
+        }
+    };
+
+    let mut result = first;
+
+    for vnode in rest {
+        let created = vnode.to_dom(backend);
+        match existing.get(*next) {
+            Some(following) => backend.insert_before(parent, &created.borrow(), following),
+            None => backend.append_child(parent, &created.borrow()),
+        }
+        result.push(created);
+    }
+
+    result
+}
+
+fn describe(backend: &dyn DomBackend, node: &DomNode) -> String {
+    match (backend.tag(node), backend.text(node)) {
+        (Some(tag), _) => format!("<{}>", tag),
+        (None, Some(content)) => format!("text {:?}", content),
+        (None, None) => "node".to_string(),
+    }
+}
+
+/// Compare children of the same parent and produce changes that turn old dom in to the new one,
+/// nodes are matched by position
+pub fn diff_children<'a>(old: &'a [VNode], new: &'a [VNode], parent: &DomNode) -> Vec<Change<'a>> {
+    diff_siblings(old, new, parent, None)
+}
+
+/// Same as `diff_children`, but nodes are added in front of `end` instead of to the end
+/// of the parent, so siblings that follow in the same parent stay where they are
+fn diff_siblings<'a>(
+    old: &'a [VNode],
+    new: &'a [VNode],
+    parent: &DomNode,
+    end: Option<&DomNode>,
+) -> Vec<Change<'a>> {
+    if is_keyed(old) && is_keyed(new) {
+        return diff_keyed(old, new, parent, end);
+    }
+
+    let mut changes = vec![];
+
+    for (old, new) in old.iter().zip(new.iter()) {
+        changes.extend(old.diff(new, parent));
+    }
+
+    for vnode in old.iter().skip(new.len()) {
+        changes.push(Change::Delete {
+            parent: parent.clone(),
+            vnode,
+        });
+    }
+
+    for vnode in new.iter().skip(old.len()) {
+        changes.push(Change::Insert {
+            parent: parent.clone(),
+            vnode,
+            before: end.cloned(),
+        });
+    }
+
+    changes
+}
+
+fn is_keyed(vnodes: &[VNode]) -> bool {
+    !vnodes.is_empty() && vnodes.iter().all(|vnode| vnode.key.is_some())
+}
+
+/// Match siblings by key, so they keep their dom nodes when they are reordered,
+/// nodes of the longest run that kept its order stay where they are, the rest is placed
+/// in front of the node that follows it, starting from the last one
+fn diff_keyed<'a>(
+    old: &'a [VNode],
+    new: &'a [VNode],
+    parent: &DomNode,
+    end: Option<&DomNode>,
+) -> Vec<Change<'a>> {
+    let mut by_key: HashMap<&str, usize> = HashMap::new();
+    for (index, vnode) in old.iter().enumerate() {
+        if let Some(key) = vnode.key.as_deref() {
+            by_key.entry(key).or_insert(index);
+        }
+    }
+
+    // duplicate keys are matched once, the rest is rendered anew
+    let mut matched = vec![false; old.len()];
+    let sources: Vec<Option<usize>> = new
+        .iter()
+        .map(|vnode| {
+            let source = vnode
+                .key
+                .as_deref()
+                .and_then(|key| by_key.get(key).copied())?;
+            (!std::mem::replace(&mut matched[source], true)).then_some(source)
+        })
+        .collect();
+
+    let mut changes = vec![];
+
+    for (vnode, source) in new.iter().zip(sources.iter()) {
+        if let Some(source) = *source {
+            changes.extend(old[source].diff(vnode, parent));
+            changes.extend(sliding(vnode).map(|vnode| Change::Slide { vnode }));
+        }
+    }
+
+    for (vnode, _) in old.iter().zip(matched).filter(|(_, matched)| !matched) {
+        changes.push(Change::Delete {
+            parent: parent.clone(),
+            vnode,
+        });
+    }
+
+    let stay = longest_increasing(&sources);
+
+    for (index, vnode) in new.iter().enumerate().rev() {
+        if !stay[index] {
+            changes.push(Change::Place {
+                parent: parent.clone(),
+                vnode,
+                next: new.get(index + 1),
+                end: end.cloned(),
+            });
+        }
+    }
+
+    changes
+}
+
+/// Elements of the node that are animated when they move, fragments are looked through
+fn sliding(vnode: &VNode) -> Box<dyn Iterator<Item = &VNode> + '_> {
+    match (&vnode.data, &vnode.transition) {
+        (VNodeData::Fragment, _) => Box::new(vnode.children.iter().flat_map(sliding)),
+        (VNodeData::Element { .. }, Some(_)) => Box::new(std::iter::once(vnode)),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+/// Positions of the longest run of matched nodes whose old positions keep growing,
+/// these nodes are already in the right order and do not have to move
+fn longest_increasing(sources: &[Option<usize>]) -> Vec<bool> {
+    // last position of the best run of every length, with the position that precedes it
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; sources.len()];
+
+    for (index, source) in sources.iter().enumerate() {
+        if source.is_none() {
+            continue;
+        }
+
+        let length = tails.partition_point(|&tail| sources[tail] < *source);
+        if length > 0 {
+            previous[index] = Some(tails[length - 1]);
+        }
+
+        match tails.get_mut(length) {
+            Some(tail) => *tail = index,
+            None => tails.push(index),
+        }
+    }
+
+    let mut stay = vec![false; sources.len()];
+    let mut current = tails.last().copied();
+
+    while let Some(index) = current {
+        stay[index] = true;
+        current = previous[index];
+    }
+
+    stay
+}
+
+fn diff_attributes<'a>(
+    element: &DomNode,
+    old: &'a VAttributes,
+    new: &'a VAttributes,
+) -> Vec<Change<'a>> {
+    let mut changes = vec![];
+
+    for (name, attribute) in new {
+        match (old.get(name), attribute) {
+            (Some(VAttribute::Attribute(old)), VAttribute::Attribute(new)) if old == new => {}
+            (Some(VAttribute::Handler(old)), VAttribute::Handler(handler)) if handler.same(old) => {
+                handler.adopt(old)
+            }
+            (Some(VAttribute::Handler(old)), VAttribute::Handler(handler)) => {
+                changes.push(Change::RemoveHandler {
+                    element: element.clone(),
+                    event: name,
+                    handler: old,
+                });
+                changes.push(Change::AddHandler {
+                    element: element.clone(),
+                    event: name,
+                    handler,
+                });
+            }
+            (_, VAttribute::Attribute(value)) => changes.push(Change::SetAttribute {
+                element: element.clone(),
+                name,
+                value,
+            }),
+            (_, VAttribute::Handler(handler)) => changes.push(Change::AddHandler {
+                element: element.clone(),
+                event: name,
+                handler,
+            }),
+        }
+    }
+
+    for (name, attribute) in old {
+        match (attribute, new.get(name)) {
+            (VAttribute::Attribute(_), Some(VAttribute::Attribute(_))) => {}
+            (VAttribute::Handler(_), Some(VAttribute::Handler(_))) => {}
+            (VAttribute::Attribute(_), _) => changes.push(Change::RemoveAttribute {
+                element: element.clone(),
+                name,
+            }),
+            (VAttribute::Handler(handler), _) => changes.push(Change::RemoveHandler {
+                element: element.clone(),
+                event: name,
+                handler,
+            }),
+        }
+    }
+
+    changes
+}
+
 pub struct VNode {
     pub data: VNodeData,
     pub children: Vec<VNode>,
+    /// Dom node this virtual node is currently rendered to
+    pub dom: RefCell<Option<DomNode>>,
+    /// Animates the node when it is inserted in to or removed from the dom by a patch
+    pub transition: Option<Transition>,
+    /// Identity of the node among its siblings, e.g. an item of `<for key="id">`,
+    /// keyed siblings are matched by key instead of by position
+    pub key: Option<String>,
 }
 
 impl VNode {
-    pub fn to_dom(&self) -> SharableDomNode {
-        let document = web_sys::window()
-            .expect("could not get js/window")
-            .document()
-            .expect("could not get js/document instance");
+    pub fn new(data: VNodeData, children: Vec<VNode>) -> Self {
+        VNode {
+            data,
+            children,
+            dom: RefCell::new(None),
+            transition: None,
+            key: None,
+        }
+    }
 
-        match &self.data {
-            VNodeData::Text { content } => {
-                let txt = document.create_text_node(&content.clone());
+    pub fn fragment(children: Vec<VNode>) -> Self {
+        VNode::new(VNodeData::Fragment, children)
+    }
 
-                Rc::new(RefCell::new(DomNode::Text(txt)))
+    /// Dom node of already rendered virtual node, the end anchor in case of a fragment
+    pub fn node(&self) -> DomNode {
+        self.dom
+            .borrow()
+            .clone()
+            .expect("virtual node is not rendered yet")
+    }
+
+    /// Top level dom nodes of already rendered virtual node, fragments end with their anchor
+    pub fn nodes(&self) -> Vec<DomNode> {
+        match self.data {
+            VNodeData::Fragment => self
+                .children
+                .iter()
+                .flat_map(VNode::nodes)
+                .chain(std::iter::once(self.node()))
+                .collect(),
+            _ => vec![self.node()],
+        }
+    }
+
+    /// Create top level dom nodes, a single one unless this is a fragment
+    pub fn create(&self, backend: &dyn DomBackend) -> Vec<DomNode> {
+        match self.data {
+            VNodeData::Fragment => {
+                let mut nodes: Vec<_> = self
+                    .children
+                    .iter()
+                    .flat_map(|child| child.create(backend))
+                    .collect();
+
+                let anchor = backend.create_text("");
+                *self.dom.borrow_mut() = Some(anchor.clone());
+                nodes.push(anchor);
+
+                nodes
             }
-            VNodeData::Element { tag, attributes } => {
-                let element = document
-                    .create_element(tag)
-                    .expect("could not create dom element");
+            _ => vec![self.to_dom(backend).borrow().clone()],
+        }
+    }
+
+    /// Create dom nodes and insert them in front of `before` or at the end of the parent
+    pub fn mount(&self, backend: &Rc<dyn DomBackend>, parent: &DomNode, before: Option<&DomNode>) {
+        for node in self.create(&**backend) {
+            match before {
+                Some(reference) => backend.insert_before(parent, &node, reference),
+                None => backend.append_child(parent, &node),
+            }
+        }
+
+        self.enter(backend);
+    }
+
+    /// Take dom nodes out of the parent, nodes with a transition leave first
+    pub fn unmount(&self, backend: &Rc<dyn DomBackend>, parent: &DomNode) {
+        match (&self.data, &self.transition) {
+            (VNodeData::Fragment, _) => {
+                for child in self.children.iter() {
+                    child.unmount(backend, parent);
+                }
+                backend.remove_child(parent, &self.node());
+            }
+            (_, Some(transition)) => transition.leave(backend, parent, &self.node()),
+            _ => backend.remove_child(parent, &self.node()),
+        }
+    }
+
+    fn enter(&self, backend: &Rc<dyn DomBackend>) {
+        match (&self.data, &self.transition) {
+            (VNodeData::Fragment, _) => {
+                for child in self.children.iter() {
+                    child.enter(backend);
+                }
+            }
+            (_, Some(transition)) => transition.enter(backend, &self.node()),
+            _ => {}
+        }
+    }
+
+    /// Create dom node of an element or a text, fragments have no single node, see `create`
+    pub fn to_dom(&self, backend: &dyn DomBackend) -> SharableDomNode {
+        let node = match &self.data {
+            VNodeData::Text { content } => backend.create_text(content),
+            VNodeData::Element {
+                attributes,
+                tag,
+                namespace,
+            } => {
+                let element = match namespace {
+                    Namespace::Html => backend.create_element(tag),
+                    namespace => backend.create_element_ns(namespace.uri(), tag),
+                };
 
                 for (name, attribute) in attributes {
                     match attribute {
-                        VAttribute::Attribute(value) => element
-                            .set_attribute(name, value)
-                            .expect("could not set attribute"),
-                        VAttribute::Handler(closure) => {
-                            element
-                                .add_event_listener_with_callback(
-                                    name,
-                                    closure.as_ref().unchecked_ref(),
-                                )
-                                .expect("colud not add event listener");
+                        VAttribute::Attribute(value) => {
+                            backend.set_attribute(&element, name, value)
+                        }
+                        VAttribute::Handler(handler) => {
+                            backend.add_listener(&element, name, handler)
+                        }
+                    }
+                }
+
+                for node in self.children.iter().flat_map(|child| child.create(backend)) {
+                    backend.append_child(&element, &node);
+                }
+
+                element
+            }
+            VNodeData::Fragment => panic!("fragment has no single dom node"),
+        };
+
+        *self.dom.borrow_mut() = Some(node.clone());
+
+        Rc::new(RefCell::new(node))
+    }
+
+    /// Adopt node rendered by the server in place of creating a new one,
+    /// only event listeners get attached when existing markup matches virtual node.
+    /// Mismatching nodes are reported in to `mismatches` and replaced with freshly created ones
+    pub fn hydrate(
+        &self,
+        backend: &dyn DomBackend,
+        existing: Option<&DomNode>,
+        parent: &DomNode,
+        mismatches: &mut Vec<String>,
+    ) -> SharableDomNode {
+        let adopted = match (&self.data, existing) {
+            (VNodeData::Text { content }, Some(node)) if backend.text(node).is_some() => {
+                if backend.text(node).as_ref() != Some(content) {
+                    mismatches.push(format!(
+                        "{} differs from {:?}",
+                        describe(backend, node),
+                        content
+                    ));
+                    backend.set_text(node, content);
+                }
+
+                true
+            }
+            (
+                VNodeData::Element {
+                    tag, attributes, ..
+                },
+                Some(node),
+            ) if backend.tag(node).as_ref() == Some(tag) => {
+                hydrate_attributes(backend, node, tag, attributes, mismatches);
+                hydrate_children(backend, &self.children, node, mismatches);
+
+                true
+            }
+            _ => false,
+        };
+
+        if let (true, Some(node)) = (adopted, existing) {
+            *self.dom.borrow_mut() = Some(node.clone());
+            return Rc::new(RefCell::new(node.clone()));
+        }
+
+        let created = self.to_dom(backend);
+
+        match existing {
+            Some(node) => {
+                mismatches.push(format!(
+                    "{} does not match {}",
+                    describe(backend, node),
+                    self.describe()
+                ));
+                backend.replace_child(parent, &created.borrow(), node);
+            }
+            None => {
+                mismatches.push(format!("{} is missing", self.describe()));
+                backend.append_child(parent, &created.borrow());
+            }
+        }
+
+        created
+    }
+
+    /// Take over existing text node, its content is replaced with the one of this node
+    fn adopt_text(&self, backend: &dyn DomBackend, node: &DomNode) -> SharableDomNode {
+        if let VNodeData::Text { content } = &self.data {
+            backend.set_text(node, content);
+        }
+
+        *self.dom.borrow_mut() = Some(node.clone());
+        Rc::new(RefCell::new(node.clone()))
+    }
+
+    fn describe(&self) -> String {
+        match &self.data {
+            VNodeData::Text { content } => format!("text {:?}", content),
+            VNodeData::Element { tag, .. } => format!("<{}>", tag),
+            VNodeData::Fragment => "fragment".to_string(),
+        }
+    }
+
+    /// Render node as html markup without touching the browser,
+    /// handlers are rendered as `data-on-<event>` markers with message name as a value
+    pub fn to_html_string(&self) -> String {
+        let mut html = String::new();
+        self.write_html(&mut html);
+        html
+    }
+
+    fn write_html(&self, html: &mut String) {
+        match &self.data {
+            VNodeData::Text { content } => html.push_str(&escape_text(content)),
+            VNodeData::Element {
+                tag, attributes, ..
+            } => {
+                html.push('<');
+                html.push_str(tag);
+
+                // keep output stable between renders
+                let mut names: Vec<_> = attributes.keys().collect();
+                names.sort();
+
+                for name in names {
+                    let (name, value) = match &attributes[name] {
+                        VAttribute::Attribute(value) => (name.clone(), value),
+                        VAttribute::Handler(handler) => {
+                            (format!("{}{}", HANDLER_MARKER, name), &handler.message)
+                        }
+                    };
+
+                    html.push_str(&format!(" {}=\"{}\"", name, escape_attribute(value)));
+                }
+
+                html.push('>');
+
+                if VOID_ELEMENTS.contains(&tag.as_str()) {
+                    return;
+                }
+
+                for child in self.children.iter() {
+                    match &child.data {
+                        VNodeData::Text { content }
+                            if RAW_TEXT_ELEMENTS.contains(&tag.as_str()) =>
+                        {
+                            html.push_str(content)
                         }
+                        _ => child.write_html(html),
                     }
                 }
 
-                let children: Vec<_> = self.children.iter().map(|c| c.to_dom()).collect();
+                html.push_str(&format!("</{}>", tag));
+            }
+            VNodeData::Fragment => {
+                for child in self.children.iter() {
+                    child.write_html(html);
+                }
+            }
+        }
+    }
+
+    /// Compare with newer version of the same node, new node takes over already rendered dom node
+    pub fn diff<'a>(&'a self, other: &'a VNode, parent: &DomNode) -> Vec<Change<'a>> {
+        let element = self.node();
+
+        match (&self.data, &other.data) {
+            (VNodeData::Text { content }, VNodeData::Text { content: new }) => {
+                *other.dom.borrow_mut() = Some(element.clone());
 
-                for child in children {
-                    element
-                        .append_child(&*(child.borrow()))
-                        .expect("could not insert a child");
+                if content == new {
+                    vec![]
+                } else {
+                    vec![Change::SetInnerText {
+                        element,
+                        content: new,
+                    }]
                 }
+            }
+            (
+                VNodeData::Element {
+                    tag,
+                    namespace,
+                    attributes,
+                },
+                VNodeData::Element {
+                    tag: new_tag,
+                    namespace: new_namespace,
+                    attributes: new_attributes,
+                },
+            ) if tag == new_tag && namespace == new_namespace => {
+                *other.dom.borrow_mut() = Some(element.clone());
+
+                let mut changes = diff_attributes(&element, attributes, new_attributes);
+                changes.extend(diff_children(&self.children, &other.children, &element));
+                changes
+            }
+            (VNodeData::Fragment, VNodeData::Fragment) => {
+                *other.dom.borrow_mut() = Some(element.clone());
 
-                Rc::new(RefCell::new(DomNode::Element(element)))
+                // children live in the same parent, the anchor keeps them in front of siblings
+                diff_siblings(&self.children, &other.children, parent, Some(&element))
             }
+            _ => vec![Change::Replace {
+                parent: parent.clone(),
+                old: self,
+                vnode: other,
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn element(tag: &str, attributes: Vec<(&str, VAttribute)>, children: Vec<VNode>) -> VNode {
+        VNode::new(
+            VNodeData::Element {
+                tag: tag.to_string(),
+                namespace: Namespace::Html,
+                attributes: attributes
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+            },
+            children,
+        )
+    }
+
+    fn text(content: &str) -> VNode {
+        VNode::new(
+            VNodeData::Text {
+                content: content.to_string(),
+            },
+            vec![],
+        )
+    }
+
+    fn attribute(value: &str) -> VAttribute {
+        VAttribute::Attribute(value.to_string())
+    }
+
+    #[test]
+    fn to_html_string_nested() {
+        let node = element("div", vec![], vec![element("p", vec![], vec![text("hi")])]);
+        assert_eq!(node.to_html_string(), "<div><p>hi</p></div>");
+    }
+
+    #[test]
+    fn to_html_string_sorted_attributes() {
+        let node = element(
+            "a",
+            vec![("id", attribute("link")), ("href", attribute("/"))],
+            vec![],
+        );
+        assert_eq!(node.to_html_string(), "<a href=\"/\" id=\"link\"></a>");
+    }
+
+    #[test]
+    fn to_html_string_escapes_text() {
+        let node = element("p", vec![], vec![text("<script>alert('x') & co</script>")]);
+        assert_eq!(
+            node.to_html_string(),
+            "<p>&lt;script&gt;alert('x') &amp; co&lt;/script&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn to_html_string_keeps_raw_text() {
+        let node = element("style", vec![], vec![text("a > b { content: \"&\" }")]);
+        assert_eq!(
+            node.to_html_string(),
+            "<style>a > b { content: \"&\" }</style>"
+        );
+    }
+
+    #[test]
+    fn to_html_string_escapes_attributes() {
+        let node = element("p", vec![("title", attribute("\"><b x='y'"))], vec![]);
+        assert_eq!(
+            node.to_html_string(),
+            "<p title=\"&quot;&gt;&lt;b x=&#39;y&#39;\"></p>"
+        );
+    }
+
+    #[test]
+    fn to_html_string_void_element() {
+        let node = element("img", vec![("src", attribute("a.png"))], vec![]);
+        assert_eq!(node.to_html_string(), "<img src=\"a.png\">");
+    }
+
+    #[test]
+    fn to_html_string_handler_marker() {
+        let handler = VAttribute::Handler(Handler::new("increment", |_| {}));
+        let node = element("button", vec![("click", handler)], vec![text("+")]);
+        assert_eq!(
+            node.to_html_string(),
+            "<button data-on-click=\"increment\">+</button>"
+        );
+    }
+
+    fn patch(old: &[VNode], new: &[VNode]) -> String {
+        let memory = Rc::new(MemoryBackend::new());
+        let backend: Rc<dyn DomBackend> = Rc::clone(&memory) as _;
+        let body = DomNode::Memory(memory.body());
+
+        for node in old.iter().flat_map(|vnode| vnode.create(&*backend)) {
+            backend.append_child(&body, &node);
         }
+
+        apply_changes(diff_children(old, new, &body), &backend);
+
+        memory.body().inner_html()
     }
 
-    pub fn diff(&self, other: &VNode) -> Vec<Change> {
-        vec![]
+    fn hydrate(html: &str, vnodes: &[VNode]) -> (MemoryBackend, Vec<String>) {
+        let backend = MemoryBackend::new();
+        let body = DomNode::Memory(backend.body());
+        backend.append_html(&body, html);
+
+        let mut mismatches = vec![];
+        hydrate_children(&backend, vnodes, &body, &mut mismatches);
+        (backend, mismatches)
+    }
+
+    #[test]
+    fn hydrate_adopts_matching_markup() {
+        let vnodes = [element("p", vec![("id", attribute("a"))], vec![text("hi")])];
+        let (backend, mismatches) = hydrate("<p id=\"a\">hi</p>", &vnodes);
+        let paragraph = backend.body().children()[0].clone();
+
+        assert!(mismatches.is_empty());
+        assert!(paragraph.is_same(&memory(vnodes[0].node())));
+        assert!(paragraph.children()[0].is_same(&memory(vnodes[0].children[0].node())));
+    }
+
+    #[test]
+    fn hydrate_splits_text_merged_by_parser() {
+        let vnodes = [element("p", vec![], vec![text("a"), text(""), text("b")])];
+        let (backend, mismatches) = hydrate("<p>ab</p>", &vnodes);
+        let texts = backend.body().children()[0].children();
+
+        assert!(mismatches.is_empty());
+        assert_eq!(texts.len(), 3);
+        for (text, vnode) in texts.iter().zip(vnodes[0].children.iter()) {
+            assert!(text.is_same(&memory(vnode.node())));
+        }
+        assert_eq!(backend.body().inner_html(), "<p>ab</p>");
+
+        let (backend, mismatches) = hydrate("<p>ac</p>", &vnodes);
+        assert_eq!(mismatches, vec!["text \"ac\" differs from \"ab\""]);
+        assert_eq!(backend.body().inner_html(), "<p>ab</p>");
+    }
+
+    #[test]
+    fn hydrate_creates_empty_text_without_mismatch() {
+        let vnodes = [text(""), element("i", vec![], vec![])];
+        let (backend, mismatches) = hydrate("<i></i>", &vnodes);
+
+        assert!(mismatches.is_empty());
+        assert_eq!(backend.body().children().len(), 2);
+        assert!(backend.body().children()[1].is_same(&memory(vnodes[1].node())));
+    }
+
+    #[test]
+    fn diff_same_tree_is_empty() {
+        let old = element("p", vec![("id", attribute("a"))], vec![text("hi")]);
+        let new = element("p", vec![("id", attribute("a"))], vec![text("hi")]);
+        let backend = MemoryBackend::new();
+        old.to_dom(&backend);
+
+        assert!(old.diff(&new, &DomNode::Memory(backend.body())).is_empty());
+    }
+
+    #[test]
+    fn diff_patches_text_and_attributes() {
+        let old = [element(
+            "p",
+            vec![("id", attribute("a")), ("title", attribute("x"))],
+            vec![text("hi")],
+        )];
+        let new = [element(
+            "p",
+            vec![("id", attribute("b")), ("class", attribute("c"))],
+            vec![text("bye")],
+        )];
+
+        assert_eq!(patch(&old, &new), "<p class=\"c\" id=\"b\">bye</p>");
+        assert!(old[0].node().is_same(&new[0].node()));
+    }
+
+    #[test]
+    fn diff_inserts_and_deletes_children() {
+        let old = [text("a"), text("b")];
+        let new = [text("a"), text("b"), element("i", vec![], vec![])];
+        assert_eq!(patch(&old, &new), "ab<i></i>");
+
+        let old = [text("a"), text("b"), text("c")];
+        let new = [text("a")];
+        assert_eq!(patch(&old, &new), "a");
+    }
+
+    #[test]
+    fn diff_replaces_different_tags() {
+        let old = [element("p", vec![], vec![text("a")]), text("b")];
+        let new = [element("b", vec![], vec![text("a")]), text("b")];
+        assert_eq!(patch(&old, &new), "<b>a</b>b");
+    }
+
+    #[test]
+    fn diff_moves_handlers() {
+        let old = [element(
+            "button",
+            vec![("click", VAttribute::Handler(Handler::new("a", |_| {})))],
+            vec![],
+        )];
+        let new = [element(
+            "button",
+            vec![("focus", VAttribute::Handler(Handler::new("b", |_| {})))],
+            vec![],
+        )];
+        patch(&old, &new);
+
+        match new[0].node() {
+            DomNode::Memory(node) => {
+                assert_eq!(node.listener_count("click"), 0);
+                assert_eq!(node.listener_count("focus"), 1);
+            }
+            DomNode::Web(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn sanitize_blocks_handlers_and_unsafe_urls() {
+        assert_eq!(sanitize_attribute("title", "javascript:x"), Ok(()));
+        assert_eq!(
+            sanitize_attribute("onClick", "alert(1)"),
+            Err(Violation::EventHandlerAttribute {
+                name: "onclick".to_string()
+            })
+        );
+
+        for url in [
+            "/a:b",
+            "page?x=y:z",
+            "#top",
+            "https://a.b",
+            "MAILTO:x@y",
+            "",
+        ] {
+            assert_eq!(sanitize_attribute("href", url), Ok(()), "{}", url);
+        }
+
+        for url in [
+            "javascript:alert(1)",
+            " JavaScript:x",
+            "java\tscript:x",
+            "\u{1}javascript:x",
+            "data:text/html,x",
+            "vbscript:x",
+        ] {
+            assert!(sanitize_attribute("src", url).is_err(), "{:?}", url);
+        }
+
+        for name in ["data", "xlink:href"] {
+            assert!(
+                sanitize_attribute(name, "javascript:x").is_err(),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            sanitize_attribute("srcdoc", "<p>hi</p>"),
+            Err(Violation::MarkupAttribute {
+                name: "srcdoc".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn attribute_names_are_xml_names() {
+        for name in ["title", "data-x", "xlink:href", ":title", "_a.b", "ünï"] {
+            assert!(is_valid_attribute_name(name), "{}", name);
+        }
+
+        for name in ["@click", "", "1a", "-a", "a b", "a\"", "a=b", "#x"] {
+            assert!(!is_valid_attribute_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn elements_are_created_in_their_namespace() {
+        let svg = |tag: &str, children| {
+            let mut node = element(tag, vec![], children);
+            if let VNodeData::Element { namespace, .. } = &mut node.data {
+                *namespace = Namespace::Svg;
+            }
+            node
+        };
+
+        let old = [svg("svg", vec![svg("foreignObject", vec![])])];
+        let backend = MemoryBackend::new();
+        let node = memory(old[0].to_dom(&backend).borrow().clone());
+        assert_eq!(node.namespace().unwrap(), Namespace::Svg.uri());
+        assert_eq!(node.children()[0].tag().unwrap(), "foreignObject");
+
+        // same tag in another namespace is another element
+        let new = [element("svg", vec![], vec![])];
+        assert!(matches!(
+            old[0].diff(&new[0], &DomNode::Memory(backend.body()))[..],
+            [Change::Replace { .. }]
+        ));
+    }
+
+    #[test]
+    fn diff_patches_fragments_in_place() {
+        let old = [
+            VNode::fragment(vec![element("a", vec![], vec![])]),
+            element("i", vec![], vec![]),
+        ];
+        let new = [
+            VNode::fragment(vec![
+                element("a", vec![], vec![]),
+                element("b", vec![], vec![]),
+            ]),
+            element("i", vec![], vec![]),
+        ];
+        assert_eq!(patch(&old, &new), "<a></a><b></b><i></i>");
+        // sibling after the fragment is not touched
+        assert!(old[1].node().is_same(&new[1].node()));
+
+        let new = [
+            VNode::fragment(vec![element("b", vec![], vec![])]),
+            element("i", vec![], vec![]),
+        ];
+        let old = [
+            VNode::fragment(vec![
+                element("a", vec![], vec![]),
+                element("b", vec![], vec![]),
+            ]),
+            element("i", vec![], vec![]),
+        ];
+        assert_eq!(patch(&old, &new), "<b></b><i></i>");
+    }
+
+    #[test]
+    fn diff_replaces_fragments_and_elements() {
+        let old = [element("p", vec![], vec![]), element("i", vec![], vec![])];
+        let new = [
+            VNode::fragment(vec![text("a"), element("b", vec![], vec![])]),
+            element("i", vec![], vec![]),
+        ];
+        assert_eq!(patch(&old, &new), "a<b></b><i></i>");
+        assert_eq!(patch(&new, &old), "<p></p><i></i>");
+    }
+
+    fn keyed(key: &str, children: Vec<VNode>) -> VNode {
+        let mut vnode = VNode::fragment(children);
+        vnode.key = Some(key.to_string());
+        vnode
+    }
+
+    #[test]
+    fn diff_moves_keyed_nodes() {
+        let item = |key: &str| keyed(key, vec![element("li", vec![], vec![text(key)])]);
+        let old = [VNode::fragment(vec![
+            item("a"),
+            item("b"),
+            item("c"),
+            item("d"),
+        ])];
+        let new = [VNode::fragment(vec![
+            item("d"),
+            item("b"),
+            item("e"),
+            item("a"),
+        ])];
+
+        assert_eq!(
+            patch(&old, &new),
+            "<li>d</li><li>b</li><li>e</li><li>a</li>"
+        );
+        // items keep their dom nodes wherever they end up
+        let li = |list: &VNode, index: usize| list.children[index].children[0].node();
+        for (from, to) in [(3, 0), (1, 1), (0, 3)] {
+            assert!(li(&old[0], from).is_same(&li(&new[0], to)));
+        }
+    }
+
+    #[test]
+    fn diff_matches_duplicate_keys_once() {
+        let item = |key: &str| keyed(key, vec![text(key)]);
+        let old = [VNode::fragment(vec![item("a")])];
+        let new = [VNode::fragment(vec![item("a"), item("a"), item("b")])];
+
+        assert_eq!(patch(&old, &new), "aab");
+        assert_eq!(patch(&new, &old), "a");
+    }
+
+    #[test]
+    fn longest_increasing_run_stays() {
+        let stay = longest_increasing(&[Some(3), Some(1), None, Some(2), Some(0)]);
+        assert_eq!(stay, vec![false, true, false, true, false]);
+    }
+
+    fn memory(node: DomNode) -> crate::backend::MemoryNode {
+        match node {
+            DomNode::Memory(node) => node,
+            DomNode::Web(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn diff_reuses_same_handlers() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let handler = Handler::new("a", move |_| counter.set(counter.get() + 1)).owner(1);
+        let old = element(
+            "button",
+            vec![("click", VAttribute::Handler(handler))],
+            vec![],
+        );
+        let new = element(
+            "button",
+            vec![(
+                "click",
+                VAttribute::Handler(Handler::new("a", |_| {}).owner(1)),
+            )],
+            vec![],
+        );
+
+        let backend = MemoryBackend::new();
+        old.to_dom(&backend);
+        assert!(old.diff(&new, &DomNode::Memory(backend.body())).is_empty());
+        drop(old);
+
+        let node = memory(new.node());
+        node.dispatch("click");
+        assert_eq!(calls.get(), 1);
+        assert_eq!(node.listener_count("click"), 1);
+    }
+
+    #[test]
+    fn diff_replaces_handlers_of_other_components() {
+        let old = element(
+            "button",
+            vec![(
+                "click",
+                VAttribute::Handler(Handler::new("a", |_| {}).owner(1)),
+            )],
+            vec![],
+        );
+        let new = element(
+            "button",
+            vec![(
+                "click",
+                VAttribute::Handler(Handler::new("a", |_| {}).owner(2)),
+            )],
+            vec![],
+        );
+
+        let backend = MemoryBackend::new();
+        old.to_dom(&backend);
+        assert_eq!(old.diff(&new, &DomNode::Memory(backend.body())).len(), 2);
+    }
+
+    #[test]
+    fn dropped_handlers_stop_listening() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let handler = Handler::new("a", move |_| counter.set(counter.get() + 1));
+        let vnode = element(
+            "button",
+            vec![("click", VAttribute::Handler(handler))],
+            vec![],
+        );
+
+        let backend = MemoryBackend::new();
+        vnode.to_dom(&backend);
+        let node = memory(vnode.node());
+        drop(vnode);
+
+        node.dispatch("click");
+        assert_eq!(calls.get(), 0);
+        assert_eq!(node.listener_count("click"), 0);
     }
 }
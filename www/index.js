@@ -1,9 +1,6 @@
 import * as wasm from "rust-wasm-components";
 
+// framework requests animation frames on its own whenever components need to render,
+// hosts that drive rendering themselves can call
+// framework.set_automatic_ticks(false) and then framework.tick() when they want to
 let framework = wasm.run();
-let cb = function() {
-    framework.tick();
-    window.requestAnimationFrame(cb);
-};
-
-// cb();